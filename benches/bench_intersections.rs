@@ -48,6 +48,7 @@ fn bench_intersections(c: &mut Criterion) {
         })),
     );
     let heart = RayMarchingShape::new(
+        None,
         Box::new(Heart::new()),
         0.01,
         InversableTransform::new(
@@ -60,6 +61,7 @@ fn bench_intersections(c: &mut Criterion) {
                 color: Vector3d::new(0.9, 0.1, 0.1),
             }),
         })),
+        4,
     );
 
     let mut group = c.benchmark_group("Intersects");
@@ -0,0 +1,97 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use ray_tracing::{
+    algebra::Vector3d,
+    camera::ray_caster::ImageParams,
+    renderer::{step_by_step::ThreadPoolRenderer, tiled::TileRenderer, Renderer, TraceSettings},
+    world::Scene,
+};
+use std::{
+    fs,
+    sync::{Arc, RwLock},
+    thread,
+    time::Duration,
+};
+
+const WIDTH: u32 = 96;
+const HEIGHT: u32 = 96;
+const SAMPLES: u32 = 4;
+
+fn render(renderer: &mut dyn Renderer, scene: &Arc<RwLock<Scene>>) -> Vec<Vector3d> {
+    let img_params = ImageParams {
+        width: WIDTH,
+        height: HEIGHT,
+    };
+    let camera = Arc::new(RwLock::new(scene.read().unwrap().camera().clone()));
+    let mut buffer = vec![Vector3d::zero(); (WIDTH * HEIGHT) as usize];
+    renderer.start_rendering(camera, &img_params, SAMPLES);
+    while !renderer.render_step(&mut buffer) {
+        thread::sleep(Duration::from_micros(200));
+    }
+    buffer
+}
+
+fn bench_renderers(c: &mut Criterion) {
+    let json = fs::read_to_string("scenes/cornell_box.json").unwrap();
+    let scene = Arc::new(RwLock::new(Scene::from_json(&json).unwrap()));
+    let settings = TraceSettings::default();
+
+    // The channel based thread pool renderer against the tile renderer. On a single core
+    // machine (cornell_box, 96x96, 4 spp) the tiles were faster up to 4 threads and even
+    // with the thread pool from 16 on, the mean times in ms being:
+    //   threads   thread pool   tiles
+    //   1         333.69        296.24
+    //   4         376.09        281.03
+    //   16        351.08        349.17
+    //   64        326.68        348.67
+    // Whether the tiles win at high thread counts needs a run on a multi-core machine.
+    let mut group = c.benchmark_group("Renderers");
+    group.sample_size(10);
+    for threads in [1, 4, 16, 64] {
+        group.bench_with_input(
+            BenchmarkId::new("Thread pool", threads),
+            &threads,
+            |b, &threads| {
+                let mut renderer = ThreadPoolRenderer::new(scene.clone(), threads, settings);
+                b.iter(|| render(&mut renderer, &scene))
+            },
+        );
+        group.bench_with_input(
+            BenchmarkId::new("Tiles", threads),
+            &threads,
+            |b, &threads| {
+                let mut renderer = TileRenderer::new(scene.clone(), threads, settings);
+                b.iter(|| render(&mut renderer, &scene))
+            },
+        );
+    }
+    group.finish();
+
+    let mut group = c.benchmark_group("Tile renderer");
+    group.sample_size(10);
+    for threads in [1, 4, 16, 64] {
+        group.bench_with_input(
+            BenchmarkId::new("Threads", threads),
+            &threads,
+            |b, &threads| {
+                let mut renderer = TileRenderer::new(scene.clone(), threads, settings);
+                b.iter(|| render(&mut renderer, &scene))
+            },
+        );
+    }
+    for tile_size in [4, 16, 64] {
+        group.bench_with_input(
+            BenchmarkId::new("Tile size", tile_size),
+            &tile_size,
+            |b, &tile_size| {
+                let threads = thread::available_parallelism().map_or(4, |n| n.get() as u32);
+                let mut renderer =
+                    TileRenderer::new(scene.clone(), threads, settings).with_tile_size(tile_size);
+                b.iter(|| render(&mut renderer, &scene))
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_renderers);
+criterion_main!(benches);
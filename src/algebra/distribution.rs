@@ -0,0 +1,137 @@
+/// Piecewise-constant density over [0, 1) built from non-negative function values.
+#[derive(Debug, Clone)]
+pub struct Distribution1D {
+    func: Vec<f64>,
+    cdf: Vec<f64>,
+    func_int: f64,
+}
+
+impl Distribution1D {
+    pub fn new(func: Vec<f64>) -> Self {
+        let n = func.len();
+        let mut cdf = vec![0.0; n + 1];
+        for i in 0..n {
+            cdf[i + 1] = cdf[i] + func[i].abs() / n as f64;
+        }
+
+        let func_int = cdf[n];
+        if func_int == 0.0 {
+            for (i, c) in cdf.iter_mut().enumerate() {
+                *c = i as f64 / n as f64;
+            }
+        } else {
+            for c in cdf.iter_mut() {
+                *c /= func_int;
+            }
+        }
+
+        Self {
+            func,
+            cdf,
+            func_int,
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        self.func.len()
+    }
+
+    /// Integral of the function over [0, 1).
+    pub fn func_int(&self) -> f64 {
+        self.func_int
+    }
+
+    /// Returns the sampled point, its density and the index of its segment.
+    pub fn sample_continuous(&self, u: f64) -> (f64, f64, usize) {
+        let offset = self
+            .cdf
+            .partition_point(|&c| c <= u)
+            .saturating_sub(1)
+            .min(self.count() - 1);
+
+        let mut du = u - self.cdf[offset];
+        let width = self.cdf[offset + 1] - self.cdf[offset];
+        if width > 0.0 {
+            du /= width;
+        }
+
+        let x = ((offset as f64 + du) / self.count() as f64).min(1.0 - f64::EPSILON);
+        (x, self.pdf_at(offset), offset)
+    }
+
+    pub fn pdf(&self, x: f64) -> f64 {
+        let offset = ((x * self.count() as f64) as usize).min(self.count() - 1);
+        self.pdf_at(offset)
+    }
+
+    fn pdf_at(&self, offset: usize) -> f64 {
+        if self.func_int > 0.0 {
+            self.func[offset] / self.func_int
+        } else {
+            1.0
+        }
+    }
+}
+
+/// Piecewise-constant density over [0, 1)^2 given as rows of function values.
+#[derive(Debug, Clone)]
+pub struct Distribution2D {
+    conditional: Vec<Distribution1D>,
+    marginal: Distribution1D,
+}
+
+impl Distribution2D {
+    /// `func` holds `height` rows of `width` values each.
+    pub fn new(func: &[f64], width: usize, height: usize) -> Self {
+        let conditional = func
+            .chunks(width)
+            .take(height)
+            .map(|row| Distribution1D::new(row.to_vec()))
+            .collect::<Vec<_>>();
+        let marginal = Distribution1D::new(conditional.iter().map(|d| d.func_int()).collect());
+
+        Self {
+            conditional,
+            marginal,
+        }
+    }
+
+    /// Returns the point (column, row coordinates) and its density.
+    pub fn sample_continuous(&self, u: (f64, f64)) -> ((f64, f64), f64) {
+        let (y, pdf_y, row) = self.marginal.sample_continuous(u.1);
+        let (x, pdf_x, _) = self.conditional[row].sample_continuous(u.0);
+        ((x, y), pdf_x * pdf_y)
+    }
+
+    pub fn pdf(&self, p: (f64, f64)) -> f64 {
+        let row = ((p.1 * self.marginal.count() as f64) as usize).min(self.marginal.count() - 1);
+        let row_dist = &self.conditional[row];
+        if self.marginal.func_int() == 0.0 {
+            return 1.0;
+        }
+        row_dist.pdf(p.0) * row_dist.func_int() / self.marginal.func_int()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_distribution_2d() {
+        let func = [0.0, 1.0, 3.0, 0.0, 2.0, 2.0];
+        let dist = Distribution2D::new(&func, 3, 2);
+
+        let ((x, y), pdf) = dist.sample_continuous((0.5, 0.25));
+        assert_eq!(pdf, dist.pdf((x, y)));
+        assert!(x >= 1.0 / 3.0 && y < 0.5);
+
+        // densities are proportional to the function and integrate to one
+        let integral: f64 = (0..6)
+            .map(|i| dist.pdf(((i % 3) as f64 / 3.0 + 0.1, (i / 3) as f64 / 2.0 + 0.1)) / 6.0)
+            .sum();
+        assert!((integral - 1.0).abs() < 1e-12);
+        assert_eq!(dist.pdf((0.1, 0.1)), 0.0);
+        assert!((dist.pdf((0.7, 0.1)) / dist.pdf((0.5, 0.1)) - 3.0).abs() < 1e-12);
+    }
+}
@@ -0,0 +1,113 @@
+use std::ops::{Add, Mul, Sub};
+
+use serde::{Deserialize, Serialize};
+
+/// How values change between keyframes.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Interpolation {
+    #[default]
+    Linear,
+    /// Cubic curve through the keyframes which eases in and out at the first and the last one.
+    Smooth,
+}
+
+impl Interpolation {
+    /// Value at `time` of the curve through `keys` sorted by time, where `key` returns the
+    /// time and the value of a keyframe. The first and the last values are held outside of
+    /// the keyframes.
+    pub fn sample<K, T>(&self, keys: &[K], time: f64, key: impl Fn(&K) -> (f64, T)) -> T
+    where
+        T: Copy + Add<Output = T> + Sub<Output = T> + Mul<f64, Output = T>,
+    {
+        let next = keys.partition_point(|k| key(k).0 <= time);
+        if next == 0 {
+            return key(&keys[0]).1;
+        }
+        if next == keys.len() {
+            return key(&keys[next - 1]).1;
+        }
+
+        let (t0, p0) = key(&keys[next - 1]);
+        let (t1, p1) = key(&keys[next]);
+        let dt = t1 - t0;
+        let s = (time - t0) / dt;
+        match self {
+            Interpolation::Linear => p0 + (p1 - p0) * s,
+            Interpolation::Smooth => {
+                let (m0, m1) = (tangent(keys, next - 1, &key), tangent(keys, next, &key));
+
+                let (s2, s3) = (s * s, s * s * s);
+                p0 * (2.0 * s3 - 3.0 * s2 + 1.0)
+                    + m0 * ((s3 - 2.0 * s2 + s) * dt)
+                    + p1 * (3.0 * s2 - 2.0 * s3)
+                    + m1 * ((s3 - s2) * dt)
+            }
+        }
+    }
+
+    /// Bezier control points of the curve between the keyframes `i` and `i + 1`, the curve
+    /// stays in their convex hull.
+    pub fn control_points<K, T>(&self, keys: &[K], i: usize, key: impl Fn(&K) -> (f64, T)) -> [T; 4]
+    where
+        T: Copy + Add<Output = T> + Sub<Output = T> + Mul<f64, Output = T>,
+    {
+        let (t0, p0) = key(&keys[i]);
+        let (t1, p1) = key(&keys[i + 1]);
+        let (m0, m1) = match self {
+            Interpolation::Linear => ((p1 - p0) * (1.0 / (t1 - t0)), (p1 - p0) * (1.0 / (t1 - t0))),
+            Interpolation::Smooth => (tangent(keys, i, &key), tangent(keys, i + 1, &key)),
+        };
+        let third = (t1 - t0) / 3.0;
+        [p0, p0 + m0 * third, p1 - m1 * third, p1]
+    }
+}
+
+/// Catmull-Rom tangent per unit of time at the keyframe `i`, flat at the ends.
+fn tangent<K, T>(keys: &[K], i: usize, key: &impl Fn(&K) -> (f64, T)) -> T
+where
+    T: Copy + Sub<Output = T> + Mul<f64, Output = T>,
+{
+    if i == 0 || i + 1 == keys.len() {
+        return key(&keys[i]).1 * 0.0;
+    }
+    let (before_t, before) = key(&keys[i - 1]);
+    let (after_t, after) = key(&keys[i + 1]);
+    (after - before) * (1.0 / (after_t - before_t))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_interpolation() {
+        let keys = [(0.0, 0.0), (1.0, 2.0), (3.0, 6.0)];
+        let at = |mode: Interpolation, time| mode.sample(&keys, time, |k| *k);
+
+        assert_eq!(at(Interpolation::Linear, -1.0), 0.0);
+        assert_eq!(at(Interpolation::Linear, 0.5), 1.0);
+        assert_eq!(at(Interpolation::Linear, 2.0), 4.0);
+        assert_eq!(at(Interpolation::Linear, 5.0), 6.0);
+
+        // passes through the keyframes, eases out of the first one
+        for (time, value) in keys {
+            assert!((at(Interpolation::Smooth, time) - value).abs() < 1e-12);
+        }
+        assert!(at(Interpolation::Smooth, 0.1) < at(Interpolation::Linear, 0.1));
+        // keys on a line stay close to it
+        assert!((at(Interpolation::Smooth, 1.5) - 3.0).abs() < 0.2);
+
+        // the curve stays between the lowest and the highest control point
+        for mode in [Interpolation::Linear, Interpolation::Smooth] {
+            for i in 0..2 {
+                let points = mode.control_points(&keys, i, |k| *k);
+                let low = points.iter().copied().fold(f64::INFINITY, f64::min);
+                let high = points.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+                for step in 0..=100 {
+                    let time = keys[i].0 + (keys[i + 1].0 - keys[i].0) * step as f64 / 100.0;
+                    assert!((low - 1e-12..=high + 1e-12).contains(&at(mode, time)));
+                }
+            }
+        }
+    }
+}
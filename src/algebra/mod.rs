@@ -7,8 +7,12 @@ use std::{
 use rand::Rng;
 use serde::{Deserialize, Serialize};
 
+pub mod distribution;
 pub mod equation;
+pub mod interpolation;
 pub mod noise;
+pub mod sampler;
+pub mod sampling;
 pub mod transform;
 
 #[inline]
@@ -57,7 +61,10 @@ impl Vector3d {
     }
 
     pub fn random(min: f64, max: f64) -> Vector3d {
-        let mut rng = rand::thread_rng();
+        Self::random_with(&mut rand::thread_rng(), min, max)
+    }
+
+    pub fn random_with(rng: &mut impl Rng, min: f64, max: f64) -> Vector3d {
         Vector3d {
             x: rng.gen_range(min..=max),
             y: rng.gen_range(min..=max),
@@ -196,6 +203,16 @@ impl Vector3d {
     pub fn max_component(&self) -> f64 {
         self.x.max(self.y).max(self.z)
     }
+
+    /// Applies `f` to every component.
+    pub fn map(&self, f: impl Fn(f64) -> f64) -> Vector3d {
+        Vector3d::new(f(self.x), f(self.y), f(self.z))
+    }
+
+    /// Relative luminance of a linear Rec. 709 color.
+    pub fn luminance(&self) -> f64 {
+        0.2126 * self.x + 0.7152 * self.y + 0.0722 * self.z
+    }
 }
 
 impl Display for Vector3d {
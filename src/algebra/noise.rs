@@ -1,6 +1,6 @@
 use super::Vector3d;
 use itertools::Itertools;
-use rand::{prelude::SliceRandom, thread_rng, Rng};
+use rand::{prelude::SliceRandom, rngs::StdRng, Rng, SeedableRng};
 
 #[allow(dead_code)]
 #[derive(Debug, Clone)]
@@ -16,13 +16,13 @@ pub struct Perlin {
 
 impl Default for Perlin {
     fn default() -> Self {
-        Self::new()
+        Self::new(0)
     }
 }
 
 impl Perlin {
-    pub fn new() -> Self {
-        let mut rng = thread_rng();
+    pub fn new(seed: u64) -> Self {
+        let mut rng = StdRng::seed_from_u64(seed);
         let mut perm_x = (0..256).collect_vec();
         let mut perm_y = (0..256).collect_vec();
         let mut perm_z = (0..256).collect_vec();
@@ -35,7 +35,9 @@ impl Perlin {
             perm_y,
             perm_z,
             ranfloat: (0..256).map(|_| rng.gen()).collect_vec(),
-            ranvec: (0..256).map(|_| Vector3d::random(-1.0, 1.0)).collect_vec(),
+            ranvec: (0..256)
+                .map(|_| Vector3d::random_with(&mut rng, -1.0, 1.0))
+                .collect_vec(),
             cartesian: (0..3).map(|_| 0..2).multi_cartesian_product().collect_vec(),
         }
     }
@@ -0,0 +1,359 @@
+use std::{fmt::Debug, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+/// Dimensions taken by the camera for each sample: film position, lens position and time.
+/// Paths continue from here, so the tracing threads don't depend on the ray caster's state.
+pub const CAMERA_DIMENSIONS: u32 = 5;
+
+/// Source of the random numbers of a pixel sample. Values only depend on the pixel, the
+/// sample index and the dimension, so any thread can pick up a sample where another left it.
+pub trait Sampler: Debug + Send {
+    /// Moves to sample `index` of `pixel`, the next value is taken from `dimension`.
+    fn start_pixel_sample(&mut self, pixel: u32, index: u32, dimension: u32);
+
+    /// Value in [0, 1) of the next dimension.
+    fn get_1d(&mut self) -> f64;
+
+    /// Point of the unit square taking the next two dimensions.
+    fn get_2d(&mut self) -> (f64, f64);
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SamplerType {
+    /// Uncorrelated random numbers.
+    #[default]
+    Independent,
+    /// Jittered strata, a grid of them for 2D values.
+    Stratified,
+    /// Halton sequence randomly shifted per pixel.
+    Halton,
+    /// Owen-scrambled Sobol (0, 2)-sequence in pairs of dimensions.
+    Sobol,
+}
+
+impl SamplerType {
+    pub fn sampler(&self, samples_per_pixel: u32, seed: u32) -> Box<dyn Sampler> {
+        let state = SampleState {
+            seed,
+            ..Default::default()
+        };
+        match self {
+            SamplerType::Independent => Box::new(IndependentSampler { state }),
+            SamplerType::Stratified => Box::new(StratifiedSampler {
+                state,
+                samples_per_pixel: samples_per_pixel.max(1),
+            }),
+            SamplerType::Halton => Box::new(HaltonSampler { state }),
+            SamplerType::Sobol => Box::new(SobolSampler { state }),
+        }
+    }
+}
+
+impl FromStr for SamplerType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "independent" => Ok(SamplerType::Independent),
+            "stratified" => Ok(SamplerType::Stratified),
+            "halton" => Ok(SamplerType::Halton),
+            "sobol" => Ok(SamplerType::Sobol),
+            _ => Err(format!("Unknown sampler: {}", s)),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+struct SampleState {
+    seed: u32,
+    pixel: u32,
+    index: u32,
+    dimension: u32,
+}
+
+impl SampleState {
+    fn start(&mut self, pixel: u32, index: u32, dimension: u32) {
+        self.pixel = pixel;
+        self.index = index;
+        self.dimension = dimension;
+    }
+
+    /// Returns the current dimension and skips `count` of them.
+    fn take(&mut self, count: u32) -> u32 {
+        let dimension = self.dimension;
+        self.dimension += count;
+        dimension
+    }
+
+    /// Random bits of the current pixel which don't change between its samples.
+    fn pixel_hash(&self, dimension: u32, salt: u32) -> u32 {
+        hash(&[self.seed, self.pixel, dimension, salt])
+    }
+
+    fn sample_hash(&self, dimension: u32) -> u32 {
+        hash(&[self.seed, self.pixel, dimension, self.index, 0x5bd1e995])
+    }
+}
+
+#[derive(Debug)]
+pub struct IndependentSampler {
+    state: SampleState,
+}
+
+impl Sampler for IndependentSampler {
+    fn start_pixel_sample(&mut self, pixel: u32, index: u32, dimension: u32) {
+        self.state.start(pixel, index, dimension);
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        let dimension = self.state.take(1);
+        to_unit(self.state.sample_hash(dimension))
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        (self.get_1d(), self.get_1d())
+    }
+}
+
+#[derive(Debug)]
+pub struct StratifiedSampler {
+    state: SampleState,
+    samples_per_pixel: u32,
+}
+
+impl StratifiedSampler {
+    /// Stratum of the current sample among `count` ones, shuffled per pixel and dimension.
+    fn stratum(&self, count: u32, dimension: u32) -> u32 {
+        permute(
+            self.state.index % count,
+            count,
+            self.state.pixel_hash(dimension, 1),
+        )
+    }
+}
+
+impl Sampler for StratifiedSampler {
+    fn start_pixel_sample(&mut self, pixel: u32, index: u32, dimension: u32) {
+        self.state.start(pixel, index, dimension);
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        let dimension = self.state.take(1);
+        let count = self.samples_per_pixel;
+        let jitter = to_unit(self.state.sample_hash(dimension));
+        ((self.stratum(count, dimension) as f64 + jitter) / count as f64).min(ONE_MINUS_EPSILON)
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        let dimension = self.state.take(2);
+        let columns = (self.samples_per_pixel as f64).sqrt().ceil() as u32;
+        let rows = self.samples_per_pixel.div_ceil(columns);
+        let stratum = self.stratum(columns * rows, dimension);
+
+        let jitter = (
+            to_unit(self.state.sample_hash(dimension)),
+            to_unit(self.state.sample_hash(dimension + 1)),
+        );
+        (
+            (((stratum % columns) as f64 + jitter.0) / columns as f64).min(ONE_MINUS_EPSILON),
+            (((stratum / columns) as f64 + jitter.1) / rows as f64).min(ONE_MINUS_EPSILON),
+        )
+    }
+}
+
+const PRIMES: [u32; 32] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71, 73, 79, 83, 89,
+    97, 101, 103, 107, 109, 113, 127, 131,
+];
+
+#[derive(Debug)]
+pub struct HaltonSampler {
+    state: SampleState,
+}
+
+impl Sampler for HaltonSampler {
+    fn start_pixel_sample(&mut self, pixel: u32, index: u32, dimension: u32) {
+        self.state.start(pixel, index, dimension);
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        let dimension = self.state.take(1);
+        let base = match PRIMES.get(dimension as usize) {
+            Some(&base) => base,
+            // past the table the sequence is too poorly distributed to help
+            None => return to_unit(self.state.sample_hash(dimension)),
+        };
+        // Cranley-Patterson rotation decorrelates the pixels
+        let shift = to_unit(self.state.pixel_hash(dimension, 2));
+        let value = radical_inverse(self.state.index, base) + shift;
+        (value - value.floor()).min(ONE_MINUS_EPSILON)
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        (self.get_1d(), self.get_1d())
+    }
+}
+
+#[derive(Debug)]
+pub struct SobolSampler {
+    state: SampleState,
+}
+
+impl SobolSampler {
+    /// Index of the current sample, shuffled per pixel and pair of dimensions. Owen
+    /// scrambling keeps every power of two block of indices together, so the first
+    /// samples of a pixel still are a well stratified set.
+    fn shuffled_index(&self, dimension: u32) -> u32 {
+        nested_uniform_scramble(self.state.index, self.state.pixel_hash(dimension, 3))
+    }
+
+    fn scrambled(&self, value: u32, dimension: u32) -> f64 {
+        to_unit(nested_uniform_scramble(value, self.state.pixel_hash(dimension, 4)))
+    }
+}
+
+impl Sampler for SobolSampler {
+    fn start_pixel_sample(&mut self, pixel: u32, index: u32, dimension: u32) {
+        self.state.start(pixel, index, dimension);
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        let dimension = self.state.take(1);
+        let index = self.shuffled_index(dimension);
+        self.scrambled(index.reverse_bits(), dimension)
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        let dimension = self.state.take(2);
+        let index = self.shuffled_index(dimension);
+        (
+            self.scrambled(index.reverse_bits(), dimension),
+            self.scrambled(sobol_second_dimension(index), dimension + 1),
+        )
+    }
+}
+
+const ONE_MINUS_EPSILON: f64 = 1.0 - f64::EPSILON / 2.0;
+
+fn to_unit(bits: u32) -> f64 {
+    bits as f64 / (1u64 << 32) as f64
+}
+
+fn hash(values: &[u32]) -> u32 {
+    let mut h: u64 = 0x9e3779b97f4a7c15;
+    for &value in values {
+        h = (h ^ value as u64).wrapping_mul(0xff51afd7ed558ccd);
+        h ^= h >> 33;
+    }
+    h = h.wrapping_mul(0xc4ceb9fe1a85ec53);
+    h ^= h >> 33;
+    (h >> 32) as u32
+}
+
+/// Element at `index` of a random permutation of `0..count` chosen by `key` (Kensler).
+fn permute(mut index: u32, count: u32, key: u32) -> u32 {
+    if count <= 1 {
+        return 0;
+    }
+    let mut mask = count - 1;
+    mask |= mask >> 1;
+    mask |= mask >> 2;
+    mask |= mask >> 4;
+    mask |= mask >> 8;
+    mask |= mask >> 16;
+
+    loop {
+        index ^= key;
+        index = index.wrapping_mul(0xe170893d);
+        index ^= key >> 16;
+        index ^= (index & mask) >> 4;
+        index ^= key >> 8;
+        index = index.wrapping_mul(0x0929eb3f);
+        index ^= key >> 23;
+        index ^= (index & mask) >> 1;
+        index = index.wrapping_mul(1 | key >> 27);
+        index = index.wrapping_mul(0x6935fa69);
+        index ^= (index & mask) >> 11;
+        index = index.wrapping_mul(0x74dcb303);
+        index ^= (index & mask) >> 2;
+        index = index.wrapping_mul(0x9e501cc3);
+        index ^= (index & mask) >> 2;
+        index = index.wrapping_mul(0xc860a3df);
+        index &= mask;
+        index ^= index >> 5;
+        if index < count {
+            return index.wrapping_add(key) % count;
+        }
+    }
+}
+
+fn radical_inverse(mut index: u32, base: u32) -> f64 {
+    let inverse_base = 1.0 / base as f64;
+    let mut factor = inverse_base;
+    let mut result = 0.0;
+    while index > 0 {
+        result += (index % base) as f64 * factor;
+        index /= base;
+        factor *= inverse_base;
+    }
+    result
+}
+
+fn sobol_second_dimension(mut index: u32) -> u32 {
+    let mut result = 0;
+    let mut direction = 1u32 << 31;
+    while index != 0 {
+        if index & 1 != 0 {
+            result ^= direction;
+        }
+        index >>= 1;
+        direction ^= direction >> 1;
+    }
+    result
+}
+
+/// Owen scrambling of the bits of `value` from the highest one (Burley, after Laine-Karras).
+fn nested_uniform_scramble(value: u32, seed: u32) -> u32 {
+    let mut x = value.reverse_bits();
+    x = x.wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50b47c);
+    x ^= x.wrapping_mul(0xb82f1e52);
+    x ^= x.wrapping_mul(0xc7afe638);
+    x ^= x.wrapping_mul(0x8d22f6e6);
+    x.reverse_bits()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_samplers_stratify() {
+        let samples = 16;
+        for sampler_type in [SamplerType::Stratified, SamplerType::Sobol] {
+            let mut sampler = sampler_type.sampler(samples, 7);
+            let mut strata_1d = vec![0; samples as usize];
+            let mut strata_2d = vec![0; samples as usize];
+            for index in 0..samples {
+                sampler.start_pixel_sample(42, index, 0);
+                let (u, v) = sampler.get_2d();
+                let w = sampler.get_1d();
+                strata_2d[(u * 4.0) as usize + 4 * (v * 4.0) as usize] += 1;
+                strata_1d[(w * samples as f64) as usize] += 1;
+            }
+            assert!(strata_2d.iter().all(|&n| n == 1), "{:?}", sampler_type);
+            assert!(strata_1d.iter().all(|&n| n == 1), "{:?}", sampler_type);
+        }
+
+        for sampler_type in [SamplerType::Independent, SamplerType::Halton] {
+            let mut sampler = sampler_type.sampler(samples, 7);
+            for index in 0..samples {
+                sampler.start_pixel_sample(42, index, 0);
+                for _ in 0..40 {
+                    assert!((0.0..1.0).contains(&sampler.get_1d()));
+                }
+            }
+        }
+    }
+}
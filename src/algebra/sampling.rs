@@ -0,0 +1,95 @@
+use super::Vector3d;
+use std::f64::consts::PI;
+
+/// Maps a point of the unit square to a uniformly distributed direction.
+pub fn uniform_sphere(u: (f64, f64)) -> Vector3d {
+    let z = 1.0 - 2.0 * u.0;
+    let r = (1.0 - z * z).max(0.0).sqrt();
+    let phi = 2.0 * PI * u.1;
+    Vector3d::new(r * phi.cos(), r * phi.sin(), z)
+}
+
+/// Maps the unit square to the unit disk keeping strata compact (Shirley-Chiu).
+pub fn concentric_disk(u: (f64, f64)) -> (f64, f64) {
+    let (a, b) = (2.0 * u.0 - 1.0, 2.0 * u.1 - 1.0);
+    if a == 0.0 && b == 0.0 {
+        return (0.0, 0.0);
+    }
+    let (r, theta) = if a.abs() > b.abs() {
+        (a, PI / 4.0 * (b / a))
+    } else {
+        (b, PI / 2.0 - PI / 4.0 * (a / b))
+    };
+    (r * theta.cos(), r * theta.sin())
+}
+
+/// Uniform point inside a regular polygon inscribed in the unit circle.
+pub fn regular_polygon(u: (f64, f64), sides: u32, rotation: f64) -> (f64, f64) {
+    let scaled = u.0 * sides as f64;
+    let side = (scaled as u32).min(sides - 1);
+    let u0 = scaled - side as f64;
+
+    let angle = 2.0 * PI / sides as f64;
+    let a0 = rotation + angle * side as f64;
+    let a1 = a0 + angle;
+    // uniform point in the triangle between the center and the side
+    let s = u0.sqrt();
+    let (b0, b1) = (s * (1.0 - u.1), s * u.1);
+    (
+        b0 * a0.cos() + b1 * a1.cos(),
+        b0 * a0.sin() + b1 * a1.sin(),
+    )
+}
+
+/// Direction around +z with density cos(theta) / PI.
+pub fn cosine_hemisphere(u: (f64, f64)) -> Vector3d {
+    let r = u.0.sqrt();
+    let phi = 2.0 * PI * u.1;
+    Vector3d::new(r * phi.cos(), r * phi.sin(), (1.0 - u.0).max(0.0).sqrt())
+}
+
+/// Direction around +z with density (n + 1) / (2 PI) * cos(theta)^n.
+pub fn power_cosine_hemisphere(u: (f64, f64), exponent: f64) -> Vector3d {
+    let cos_theta = u.0.powf(1.0 / (exponent + 1.0));
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    let phi = 2.0 * PI * u.1;
+    Vector3d::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta)
+}
+
+/// MIS weight of a sample drawn with density `f_pdf` when `g_pdf` could also produce it.
+pub fn power_heuristic(f_pdf: f64, g_pdf: f64) -> f64 {
+    let f = f_pdf * f_pdf;
+    let g = g_pdf * g_pdf;
+    if f + g == 0.0 {
+        0.0
+    } else {
+        f / (f + g)
+    }
+}
+
+/// Orthonormal basis with `w` along a given direction.
+#[derive(Debug, Clone)]
+pub struct Onb {
+    pub u: Vector3d,
+    pub v: Vector3d,
+    pub w: Vector3d,
+}
+
+impl Onb {
+    pub fn from_w(w: &Vector3d) -> Self {
+        let w = w.normalize();
+        let a = if w.x.abs() > 0.9 {
+            Vector3d::new(0.0, 1.0, 0.0)
+        } else {
+            Vector3d::new(1.0, 0.0, 0.0)
+        };
+        let v = w.cross(&a).normalize();
+        let u = w.cross(&v);
+        Self { u, v, w }
+    }
+
+    /// Converts local coordinates to world space.
+    pub fn local(&self, a: &Vector3d) -> Vector3d {
+        a.x * self.u + a.y * self.v + a.z * self.w
+    }
+}
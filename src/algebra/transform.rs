@@ -1,7 +1,16 @@
-use super::Vector3d;
+use super::{interpolation::Interpolation, Vector3d};
 use crate::world::ray::Ray;
 use serde::{de, de::Visitor, Deserialize, Serialize};
-use std::{fmt::Display, ops::Mul};
+use std::{borrow::Cow, fmt::Display, ops::Mul};
+
+/// Pose of an animated transform at a moment in time.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Keyframe {
+    pub time: f64,
+    pub translate: Vector3d,
+    pub rotate: Vector3d,
+    pub scale: Vector3d,
+}
 
 #[derive(Serialize, Debug, Clone)]
 pub struct InversableTransform {
@@ -10,6 +19,10 @@ pub struct InversableTransform {
     scale: Vector3d,
     pub direct: Transform,
     pub inverse: Transform,
+    /// Empty for a static transform.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    keyframes: Vec<Keyframe>,
+    interpolation: Interpolation,
 }
 
 impl InversableTransform {
@@ -19,13 +32,66 @@ impl InversableTransform {
         let inverse = Transform::scale(Vector3d::new(1.0 / scale.x, 1.0 / scale.y, 1.0 / scale.z))
             * Transform::rotate_inverse(Vector3d::new(-rotate.x, -rotate.y, -rotate.z))
             * Transform::translate(Vector3d::new(-translate.x, -translate.y, -translate.z));
-        Self { translate, rotate, scale, direct, inverse }
+        Self {
+            translate,
+            rotate,
+            scale,
+            direct,
+            inverse,
+            keyframes: Vec::new(),
+            interpolation: Interpolation::Linear,
+        }
+    }
+
+    /// Moves between the keyframes. `direct` and `inverse` hold the pose of the earliest one.
+    /// Two keyframes can't share a time.
+    pub fn animated(mut keyframes: Vec<Keyframe>, interpolation: Interpolation) -> Self {
+        keyframes.sort_by(|a, b| a.time.total_cmp(&b.time));
+        if let Some(time) = repeated_time(&keyframes) {
+            panic!("Two keyframes at time {}", time);
+        }
+        let first = &keyframes[0];
+        let mut transform = Self::new(first.translate, first.rotate, first.scale);
+        if keyframes.len() > 1 {
+            transform.keyframes = keyframes;
+            transform.interpolation = interpolation;
+        }
+        transform
+    }
+
+    pub fn is_animated(&self) -> bool {
+        !self.keyframes.is_empty()
+    }
+
+    /// Get a reference to the inversable transform's keyframes.
+    pub fn keyframes(&self) -> &[Keyframe] {
+        &self.keyframes
+    }
+
+    pub fn interpolation(&self) -> Interpolation {
+        self.interpolation
+    }
+
+    /// Static transform at the given time, holding the first and the last pose
+    /// outside of the keyframes.
+    pub fn at(&self, time: f64) -> Cow<'_, InversableTransform> {
+        if self.keyframes.is_empty() {
+            return Cow::Borrowed(self);
+        }
+
+        let keys = &self.keyframes;
+        Cow::Owned(Self::new(
+            self.interpolation.sample(keys, time, |key| (key.time, key.translate)),
+            self.interpolation.sample(keys, time, |key| (key.time, key.rotate)),
+            self.interpolation.sample(keys, time, |key| (key.time, key.scale)),
+        ))
     }
 
     pub fn direct_transform_ray(&self, ray: &Ray) -> Ray {
         Ray {
             origin: self.direct.transform_point(&ray.origin),
             direction: self.direct.transform_vector(&ray.direction),
+            time: ray.time,
         }
     }
 
@@ -33,6 +99,7 @@ impl InversableTransform {
         Ray {
             origin: self.inverse.transform_point(&ray.origin),
             direction: self.inverse.transform_vector(&ray.direction),
+            time: ray.time,
         }
     }
 
@@ -50,6 +117,24 @@ impl InversableTransform {
     pub fn scale(&self) -> Vector3d {
         self.scale
     }
+
+    /// Ratio between world and local surface area at a point with the given local normal.
+    pub fn area_scale(&self, local_normal: &Vector3d) -> f64 {
+        self.direct.determinant().abs() * self.inverse.transform_normal(local_normal).length()
+    }
+
+    /// Same as `area_scale`, but for a point given by its world-space normal.
+    pub fn world_area_scale(&self, normal: &Vector3d) -> f64 {
+        self.direct.determinant().abs() / self.direct.transform_normal(normal).length()
+    }
+}
+
+/// Time of two of the keyframes sorted by time, if any.
+fn repeated_time(keyframes: &[Keyframe]) -> Option<f64> {
+    keyframes
+        .windows(2)
+        .find(|pair| pair[0].time == pair[1].time)
+        .map(|pair| pair[0].time)
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -129,6 +214,8 @@ impl<'de> Deserialize<'de> for InversableTransform {
             Translate,
             Rotate,
             Scale,
+            Keyframes,
+            Interpolation,
         }
 
         struct InversableTransformVisitor;
@@ -147,6 +234,8 @@ impl<'de> Deserialize<'de> for InversableTransform {
                 let mut translate = None;
                 let mut rotate = None;
                 let mut scale = None;
+                let mut keyframes = None;
+                let mut interpolation = None;
                 while let Some(key) = map.next_key()? {
                     match key {
                         Field::Translate => {
@@ -167,8 +256,37 @@ impl<'de> Deserialize<'de> for InversableTransform {
                             }
                             scale = Some(map.next_value()?);
                         }
+                        Field::Keyframes => {
+                            if keyframes.is_some() {
+                                return Err(de::Error::duplicate_field("keyframes"));
+                            }
+                            keyframes = Some(map.next_value()?);
+                        }
+                        Field::Interpolation => {
+                            if interpolation.is_some() {
+                                return Err(de::Error::duplicate_field("interpolation"));
+                            }
+                            interpolation = Some(map.next_value()?);
+                        }
                     }
                 }
+                if let Some(keyframes) = keyframes {
+                    let mut keyframes: Vec<Keyframe> = keyframes;
+                    if keyframes.is_empty() {
+                        return Err(de::Error::invalid_length(0, &"at least one keyframe"));
+                    }
+                    keyframes.sort_by(|a, b| a.time.total_cmp(&b.time));
+                    if let Some(time) = repeated_time(&keyframes) {
+                        return Err(de::Error::custom(format!(
+                            "two keyframes at time {}",
+                            time
+                        )));
+                    }
+                    return Ok(InversableTransform::animated(
+                        keyframes,
+                        interpolation.unwrap_or_default(),
+                    ));
+                }
                 let translate: Vector3d =
                     translate.ok_or_else(|| de::Error::missing_field("translate"))?;
                 let rotate: Vector3d = rotate.ok_or_else(|| de::Error::missing_field("rotate"))?;
@@ -180,7 +298,7 @@ impl<'de> Deserialize<'de> for InversableTransform {
 
         deserializer.deserialize_struct(
             "InversableTransform",
-            &["translate", "rotate", "scale"],
+            &["translate", "rotate", "scale", "keyframes", "interpolation"],
             InversableTransformVisitor,
         )
     }
@@ -424,6 +542,13 @@ impl Transform {
         }
     }
 
+    pub fn determinant(&self) -> f64 {
+        let m = &self.0;
+        m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+            - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+            + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+    }
+
     #[allow(dead_code)]
     fn decompose(&self) -> InversableTransformJson {
         let mat = &self.0;
@@ -0,0 +1,112 @@
+use std::{env, path::Path, process};
+
+use ray_tracing::output::{
+    denoise::{Denoiser, Guides},
+    exr::{read_exr, ExrPrecision},
+    save_image,
+    tonemap::ToneMapping,
+};
+
+const USAGE: &str = "Usage: denoise <render.exr> [--iterations N] [--sigma-color X] \
+[--sigma-normal X] [--sigma-depth X] [--output FILE]
+
+The input needs the albedo, normal and depth layers written by
+render --aovs albedo,normal,depth. The output is <render>_denoised.exr by default.";
+
+struct DenoiseArgs {
+    input: String,
+    denoiser: Denoiser,
+    output: Option<String>,
+}
+
+impl DenoiseArgs {
+    fn parse(args: &[String]) -> Result<Self, String> {
+        let mut input = None;
+        let mut denoiser = Denoiser::default();
+        let mut output = None;
+
+        let mut iter = args.iter();
+        while let Some(arg) = iter.next() {
+            if !arg.starts_with("--") {
+                if input.replace(arg.clone()).is_some() {
+                    return Err(format!("Unexpected argument: {}", arg));
+                }
+                continue;
+            }
+
+            let value = iter
+                .next()
+                .ok_or_else(|| format!("Missing value for {}", arg))?;
+            let error = || format!("Incorrect value for {}: {}", arg, value);
+            let float = || match value.parse::<f64>() {
+                Ok(x) if x > 0.0 && x.is_finite() => Ok(x),
+                _ => Err(error()),
+            };
+            match arg.as_str() {
+                "--iterations" => denoiser.iterations = value.parse().map_err(|_| error())?,
+                "--sigma-color" => denoiser.sigma_color = float()?,
+                "--sigma-normal" => denoiser.sigma_normal = float()?,
+                "--sigma-depth" => denoiser.sigma_depth = float()?,
+                "--output" => output = Some(value.clone()),
+                _ => return Err(format!("Unknown option: {}", arg)),
+            }
+        }
+
+        Ok(DenoiseArgs {
+            input: input.ok_or("Need input image")?,
+            denoiser,
+            output,
+        })
+    }
+}
+
+fn main() {
+    let args = env::args().skip(1).collect::<Vec<String>>();
+    let args = DenoiseArgs::parse(&args).unwrap_or_else(|err| {
+        eprintln!("{}\n{}", err, USAGE);
+        process::exit(2);
+    });
+
+    if let Err(err) = denoise(&args) {
+        eprintln!("{}", err);
+        process::exit(1);
+    }
+}
+
+fn denoise(args: &DenoiseArgs) -> Result<(), String> {
+    let image = read_exr(&args.input)?;
+    let missing = |layer: &str| format!("{} has no {} layer", args.input, layer);
+    let color = image.rgb("").ok_or_else(|| missing("color"))?;
+    let guides = Guides {
+        albedo: image.rgb("albedo").ok_or_else(|| missing("albedo"))?,
+        normal: image.rgb("normal").ok_or_else(|| missing("normal"))?,
+        depth: image
+            .channels
+            .get("depth")
+            .cloned()
+            .ok_or_else(|| missing("depth"))?,
+    };
+
+    let denoised = args
+        .denoiser
+        .denoise(image.width, image.height, &color, &guides);
+
+    let output = args.output.clone().unwrap_or_else(|| {
+        let path = Path::new(&args.input);
+        let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+        path.with_file_name(format!("{}_denoised.exr", stem))
+            .to_string_lossy()
+            .into_owned()
+    });
+    save_image(
+        &output,
+        image.width,
+        image.height,
+        &denoised,
+        &[],
+        &ToneMapping::default(),
+        ExrPrecision::Float,
+    )?;
+    println!("Saved {}", output);
+    Ok(())
+}
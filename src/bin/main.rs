@@ -12,7 +12,11 @@ use ray_tracing::{
         ray_caster::{ImageParams, MultisamplerRayCaster},
         Camera, CameraOrbitControl,
     },
-    renderer::{step_by_step, thread_pool_new, Renderer},
+    output::{
+        denoise::{Denoiser, Guides},
+        tonemap::ToneMapping,
+    },
+    renderer::{accumulation::Progressive, tiled::TileRenderer, Renderer, TraceSettings},
     world::Scene,
 };
 use winit::{
@@ -24,6 +28,9 @@ use winit::{
 use winit_input_helper::WinitInputHelper;
 
 const SIZE: (u32, u32) = (1600, 900);
+/// The image is refined until the estimated relative error gets below the target.
+const MAX_SAMPLES: u32 = 1024;
+const TARGET_ERROR: f64 = 0.01;
 // const SIZE: (u32, u32) = (800, 450);
 // const BOX_SIZE: i16 = 64;
 
@@ -58,7 +65,7 @@ fn main() -> Result<(), Error> {
             return Ok(());
         }
     };
-    let mut state = RendererState::new(&world_file, RenderMode::StepByStep);
+    let mut state = RendererState::new(world_file);
 
     event_loop.run(move |event, _, control_flow| {
         // Handle input events
@@ -184,11 +191,6 @@ fn main() -> Result<(), Error> {
     });
 }
 
-enum RenderMode {
-    Static,
-    StepByStep,
-}
-
 struct RendererState {
     is_redraw: bool,
     is_finished: bool,
@@ -198,14 +200,17 @@ struct RendererState {
     shared_camera: Arc<RwLock<Camera>>,
     shared_world: Arc<RwLock<Scene>>,
     samples_num: u32,
-    render_mode: RenderMode,
 
     camera_control: CameraOrbitControl,
-    is_high_sampling: bool,
+    tone_mapping: ToneMapping,
+    is_denoising: bool,
+    /// Denoised image of the accumulation after `denoised_passes` passes.
+    denoised: Option<Vec<Vector3d>>,
+    denoised_passes: u32,
 }
 
 impl RendererState {
-    fn new(world_file: &str, render_mode: RenderMode) -> Self {
+    fn new(world_file: &str) -> Self {
         let json_file =
             fs::read_to_string(world_file).expect("Something went wrong reading the file");
         let scene = Scene::from_json(&json_file)
@@ -226,18 +231,17 @@ impl RendererState {
         let color_buffer = vec![Vector3d::new(0.0, 0.0, 0.0); (SIZE.0 * SIZE.1) as usize];
         let shared_camera = Arc::new(RwLock::new(scene.camera().clone()));
         let shared_scene = Arc::new(RwLock::new(scene));
-        let renderer: Box<dyn Renderer> = match render_mode {
-            RenderMode::Static => Box::new(thread_pool_new::ThreadPoolRenderer::new(
-                shared_scene.clone(),
-                12,
-                50,
-            )),
-            RenderMode::StepByStep => Box::new(step_by_step::ThreadPoolRenderer::new(
-                shared_scene.clone(),
-                12,
-                50,
-            )),
+        // first hits are always traced so the denoiser can be toggled without restarting
+        let settings = TraceSettings {
+            aovs: true,
+            progressive: Some(Progressive {
+                pass_samples: 1,
+                target_error: Some(TARGET_ERROR),
+            }),
+            ..Default::default()
         };
+        let renderer: Box<dyn Renderer> =
+            Box::new(TileRenderer::new(shared_scene.clone(), 12, settings));
 
         let camera_control =
             CameraOrbitControl::from_camera(shared_camera.clone(), Vector3d::new(0.0, 0.0, 0.0));
@@ -253,40 +257,63 @@ impl RendererState {
             shared_camera,
             shared_world: shared_scene,
             samples_num: 0,
-            render_mode,
             camera_control,
-            is_high_sampling: false,
+            tone_mapping: ToneMapping::default(),
+            is_denoising: false,
+            denoised: None,
+            denoised_passes: 0,
         }
     }
 
+    fn denoise(&self) -> Vec<Vector3d> {
+        let aovs = self.renderer.aovs();
+        if aovs.is_empty() {
+            return self.color_buffer.clone();
+        }
+        let guides = Guides::from_aovs(aovs, &self.shared_camera.read().unwrap());
+        Denoiser::default().denoise(
+            self.img_params.width,
+            self.img_params.height,
+            &self.color_buffer,
+            &guides,
+        )
+    }
+
     fn render(&mut self, frame: &mut [u8]) {
         // println!("Render");
-        let samples = if self.is_high_sampling { 100 } else { 1 };
-
-        if self.is_redraw && self.is_finished {
+        if self.is_redraw {
             self.is_redraw = false;
             self.is_finished = false;
-            self.renderer.stop_rendering();
+            self.denoised = None;
             self.renderer
-                .start_rendering(self.shared_camera.clone(), &self.img_params, samples);
+                .start_rendering(self.shared_camera.clone(), &self.img_params, MAX_SAMPLES);
         }
         if !self.is_finished {
             // let start = time::Instant::now();
             self.is_finished = self.renderer.render_step(&mut self.color_buffer);
-            // println!(
-            //     "Rendered in {} ms",
-            //     (time::Instant::now() - start).whole_milliseconds()
-            // );
+            if self.is_finished {
+                let convergence = self.renderer.convergence();
+                println!(
+                    "Rendered {:.0} samples per pixel in {} ms, RMSE {:.2}%",
+                    convergence.samples_per_pixel,
+                    convergence.elapsed.as_millis(),
+                    convergence.relative_rmse * 100.0
+                );
+            }
         }
-        for (dest, src) in frame.chunks_mut(4).zip(&self.color_buffer) {
-            let r = src.x.sqrt();
-            let g = src.y.sqrt();
-            let b = src.z.sqrt();
-            dest[0] = (r.clamp(0.0, 0.999) * 256.0) as u8;
-            dest[1] = (g.clamp(0.0, 0.999) * 256.0) as u8;
-            dest[2] = (b.clamp(0.0, 0.999) * 256.0) as u8;
-            dest[3] = 255;
+        // refreshed with every pass, the first one also brings the guides
+        if self.is_denoising {
+            let passes = self.renderer.progress().passes;
+            if passes > 0 && (self.denoised.is_none() || passes != self.denoised_passes) {
+                self.denoised = Some(self.denoise());
+                self.denoised_passes = passes;
+            }
         }
+        let buffer = match &self.denoised {
+            Some(denoised) if self.is_denoising => denoised,
+            _ => &self.color_buffer,
+        };
+        self.tone_mapping.write_rgba8(buffer, frame);
     }
 
     fn process_input(&mut self, input: &WinitInputHelper) -> bool {
@@ -335,12 +362,29 @@ impl RendererState {
         //     fs::write("saved_world.json", json).expect("Could not save world file");
         // }
 
+        if input.key_pressed(VirtualKeyCode::T) {
+            self.tone_mapping.operator = self.tone_mapping.operator.next();
+            println!("Tone mapping: {}", self.tone_mapping.operator);
+        }
+        if input.key_pressed(VirtualKeyCode::RBracket) {
+            self.tone_mapping.exposure += 0.5;
+            println!("Exposure: {:+} EV", self.tone_mapping.exposure);
+        }
+        if input.key_pressed(VirtualKeyCode::LBracket) {
+            self.tone_mapping.exposure -= 0.5;
+            println!("Exposure: {:+} EV", self.tone_mapping.exposure);
+        }
+        if input.key_pressed(VirtualKeyCode::N) {
+            self.is_denoising = !self.is_denoising;
+            println!("Denoiser: {}", if self.is_denoising { "on" } else { "off" });
+        }
+
         if input.key_pressed(VirtualKeyCode::Space) {
-            if self.is_high_sampling {
-                self.is_high_sampling = false;
-            } else {
-                self.is_high_sampling = true;
+            if self.is_finished {
                 self.is_redraw = true;
+            } else {
+                self.renderer.stop_rendering();
+                self.is_finished = true;
             }
         }
 
@@ -355,9 +399,12 @@ impl RendererState {
                 let r = ray_tracing::renderer::trace_pixel_samples(
                     &(index, rays),
                     &*self.shared_world.read().unwrap(),
-                    10,
+                    &TraceSettings {
+                        max_depth: Some(10),
+                        ..Default::default()
+                    },
                 );
-                println!("{}", r.1);
+                println!("{}", r.1.mean);
             }
         }
 
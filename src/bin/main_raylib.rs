@@ -3,7 +3,6 @@ use std::{
     env, fs,
     sync::{Arc, RwLock},
 };
-use time::{Duration, Instant};
 
 use log::error;
 use ray_tracing::{
@@ -12,11 +11,22 @@ use ray_tracing::{
         ray_caster::{ImageParams, MultisamplerRayCaster},
         Camera, CameraOrbitControl,
     },
-    renderer::{step_by_step, thread_pool_new, Renderer},
+    output::{
+        denoise::{Denoiser, Guides},
+        tonemap::ToneMapping,
+    },
+    renderer::{
+        accumulation::{Convergence, Progressive},
+        tiled::TileRenderer,
+        Renderer, TraceSettings,
+    },
     world::Scene,
 };
 
 const SIZE: (i32, i32) = (1600, 900);
+/// The image is refined up to the samples number given on the command line, or until
+/// the estimated relative error gets below the target.
+const TARGET_ERROR: f64 = 0.01;
 
 fn main() {
     let args = env::args().collect::<Vec<String>>();
@@ -45,13 +55,7 @@ fn main() {
         .title("Hello, World")
         .build();
 
-    let mut state = RendererState::new(
-        &world_file,
-        RenderMode::StepByStep,
-        samples,
-        width as u32,
-        height as u32,
-    );
+    let mut state = RendererState::new(&world_file, samples, width as u32, height as u32);
 
     let mut frame = vec![0; (width * height * 4) as usize];
 
@@ -106,7 +110,12 @@ fn main() {
             d.draw_texture(&txt, 0, 0, Color::WHITE);
             d.draw_fps(12, 12);
             d.draw_text(
-                &format!("{} ms", state.render_duration.whole_milliseconds()),
+                &format!(
+                    "{:.0} spp {} ms RMSE {:.2}%",
+                    state.convergence.samples_per_pixel,
+                    state.convergence.elapsed.as_millis(),
+                    state.convergence.relative_rmse * 100.0
+                ),
                 12,
                 32,
                 20,
@@ -116,12 +125,6 @@ fn main() {
     }
 }
 
-#[allow(dead_code)]
-enum RenderMode {
-    Static,
-    StepByStep,
-}
-
 #[allow(dead_code)]
 struct RendererState {
     is_redraw: bool,
@@ -132,24 +135,20 @@ struct RendererState {
     shared_camera: Arc<RwLock<Camera>>,
     shared_world: Arc<RwLock<Scene>>,
     samples_num: u32,
-    render_mode: RenderMode,
 
     camera_control: CameraOrbitControl,
-    is_high_sampling: bool,
     samples_high: u32,
 
-    render_start: Instant,
-    render_duration: Duration,
+    convergence: Convergence,
+    tone_mapping: ToneMapping,
+    is_denoising: bool,
+    /// Denoised image of the accumulation after `denoised_passes` passes.
+    denoised: Option<Vec<Vector3d>>,
+    denoised_passes: u32,
 }
 
 impl RendererState {
-    fn new(
-        world_file: &str,
-        render_mode: RenderMode,
-        samples: u32,
-        width: u32,
-        height: u32,
-    ) -> Self {
+    fn new(world_file: &str, samples: u32, width: u32, height: u32) -> Self {
         let json_file =
             fs::read_to_string(world_file).expect("Something went wrong reading the file");
 
@@ -165,18 +164,17 @@ impl RendererState {
         let color_buffer = vec![Vector3d::new(0.0, 0.0, 0.0); (width * height) as usize];
         let shared_camera = Arc::new(RwLock::new(scene.camera().clone()));
         let shared_scene = Arc::new(RwLock::new(scene));
-        let renderer: Box<dyn Renderer> = match render_mode {
-            RenderMode::Static => Box::new(thread_pool_new::ThreadPoolRenderer::new(
-                shared_scene.clone(),
-                12,
-                50,
-            )),
-            RenderMode::StepByStep => Box::new(step_by_step::ThreadPoolRenderer::new(
-                shared_scene.clone(),
-                12,
-                50,
-            )),
+        // first hits are always traced so the denoiser can be toggled without restarting
+        let settings = TraceSettings {
+            aovs: true,
+            progressive: Some(Progressive {
+                pass_samples: 1,
+                target_error: Some(TARGET_ERROR),
+            }),
+            ..Default::default()
         };
+        let renderer: Box<dyn Renderer> =
+            Box::new(TileRenderer::new(shared_scene.clone(), 12, settings));
 
         let camera_control = CameraOrbitControl::from_camera(
             shared_camera.clone(),
@@ -195,34 +193,48 @@ impl RendererState {
             shared_camera,
             shared_world: shared_scene,
             samples_num: 0,
-            render_mode,
             camera_control,
 
-            is_high_sampling: false,
             samples_high: samples,
 
-            render_start: Instant::now(),
-            render_duration: Duration::seconds(0),
+            convergence: Convergence {
+                passes: 0,
+                samples_per_pixel: 0.0,
+                rmse: f64::INFINITY,
+                relative_rmse: f64::INFINITY,
+                elapsed: Default::default(),
+            },
+            tone_mapping: ToneMapping::default(),
+            is_denoising: false,
+            denoised: None,
+            denoised_passes: 0,
         }
     }
 
-    fn render(&mut self, frame: &mut [u8]) {
-        let samples_number = if self.is_high_sampling {
-            self.samples_high
-        } else {
-            1
-        };
+    fn denoise(&self) -> Vec<Vector3d> {
+        let aovs = self.renderer.aovs();
+        if aovs.is_empty() {
+            return self.color_buffer.clone();
+        }
+        let guides = Guides::from_aovs(aovs, &self.shared_camera.read().unwrap());
+        Denoiser::default().denoise(
+            self.img_params.width,
+            self.img_params.height,
+            &self.color_buffer,
+            &guides,
+        )
+    }
 
-        if self.is_redraw && self.is_finished {
+    fn render(&mut self, frame: &mut [u8]) {
+        if self.is_redraw {
             self.is_redraw = false;
             self.is_finished = false;
-            self.renderer.stop_rendering();
+            self.denoised = None;
             self.renderer.start_rendering(
                 self.shared_camera.clone(),
                 &self.img_params,
-                samples_number,
+                self.samples_high,
             );
-            self.render_start = Instant::now();
 
             // for v in self.color_buffer.iter_mut() {
             //     *v = Vector3d::zero();
@@ -231,21 +243,22 @@ impl RendererState {
 
         if !self.is_finished {
             self.is_finished = self.renderer.render_step(&mut self.color_buffer);
-
-            if self.is_finished {
-                self.render_duration = Instant::now() - self.render_start;
-            }
-
-            for (dest, src) in frame.chunks_mut(4).zip(&self.color_buffer) {
-                let r = src.x.sqrt();
-                let g = src.y.sqrt();
-                let b = src.z.sqrt();
-                dest[0] = (r.clamp(0.0, 0.999) * 256.0) as u8;
-                dest[1] = (g.clamp(0.0, 0.999) * 256.0) as u8;
-                dest[2] = (b.clamp(0.0, 0.999) * 256.0) as u8;
-                dest[3] = 255;
+            self.convergence = self.renderer.convergence();
+        }
+        // refreshed with every pass, the first one also brings the guides
+        if self.is_denoising {
+            let passes = self.renderer.progress().passes;
+            if passes > 0 && (self.denoised.is_none() || passes != self.denoised_passes) {
+                self.denoised = Some(self.denoise());
+                self.denoised_passes = passes;
             }
         }
+
+        let buffer = match &self.denoised {
+            Some(denoised) if self.is_denoising => denoised,
+            _ => &self.color_buffer,
+        };
+        self.tone_mapping.write_rgba8(buffer, frame);
     }
 
     fn process_input(&mut self, input: &RaylibHandle) -> bool {
@@ -308,12 +321,29 @@ impl RendererState {
         //     fs::write("saved_world.json", json).expect("Could not save world file");
         // }
 
+        if input.is_key_pressed(KeyboardKey::KEY_T) {
+            self.tone_mapping.operator = self.tone_mapping.operator.next();
+            println!("Tone mapping: {}", self.tone_mapping.operator);
+        }
+        if input.is_key_pressed(KeyboardKey::KEY_RIGHT_BRACKET) {
+            self.tone_mapping.exposure += 0.5;
+            println!("Exposure: {:+} EV", self.tone_mapping.exposure);
+        }
+        if input.is_key_pressed(KeyboardKey::KEY_LEFT_BRACKET) {
+            self.tone_mapping.exposure -= 0.5;
+            println!("Exposure: {:+} EV", self.tone_mapping.exposure);
+        }
+        if input.is_key_pressed(KeyboardKey::KEY_N) {
+            self.is_denoising = !self.is_denoising;
+            println!("Denoiser: {}", if self.is_denoising { "on" } else { "off" });
+        }
+
         if input.is_key_pressed(KeyboardKey::KEY_SPACE) {
-            if self.is_high_sampling {
-                self.is_high_sampling = false;
-            } else {
-                self.is_high_sampling = true;
+            if self.is_finished {
                 self.is_redraw = true;
+            } else {
+                self.renderer.stop_rendering();
+                self.is_finished = true;
             }
         }
 
@@ -330,9 +360,12 @@ impl RendererState {
                 let r = ray_tracing::renderer::trace_pixel_samples(
                     &(index, rays),
                     &*self.shared_world.read().unwrap(),
-                    10,
+                    &TraceSettings {
+                        max_depth: Some(10),
+                        ..Default::default()
+                    },
                 );
-                println!("{}", r.1);
+                println!("{}", r.1.mean);
             }
         }
 
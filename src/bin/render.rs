@@ -0,0 +1,428 @@
+use std::{
+    env, fs,
+    io::{self, Write},
+    ops::RangeInclusive,
+    path::Path,
+    process,
+    sync::{Arc, RwLock},
+    thread,
+    time::{Duration, Instant},
+};
+
+use ray_tracing::{
+    algebra::Vector3d,
+    camera::{ray_caster::ImageParams, Camera},
+    output::{
+        denoise::{Denoiser, Guides},
+        exr::ExrPrecision,
+        heatmap, save_image,
+        tonemap::ToneMapping,
+    },
+    renderer::{
+        aov::Aov,
+        checkpoint::{scene_hash, Checkpoint},
+        progress::{Progress, RenderEvent, RenderState},
+        tiled::TileRenderer,
+        Renderer, TraceSettings,
+    },
+    world::Scene,
+};
+
+const USAGE: &str = "Usage: render <scene.json> [--width N] [--height N] [--samples N] \
+[--depth N] [--rr-depth N] [--sampler NAME] [--seed N] [--threads N] [--frames FIRST-LAST] [--tonemap NAME] [--exposure EV] \
+[--white L] [--exr-precision half|float] [--aovs NAME,...] [--denoise] [--adaptive ERROR] \
+[--min-samples N] [--progressive N] [--target-error ERROR] [--time-limit SECONDS] \
+[--checkpoint FILE] [--checkpoint-interval SECONDS] [--resume] [--heatmap FILE] [--output FILE]
+
+Samplers: independent, stratified, halton, sobol.
+Tone mapping: linear, reinhard, extended-reinhard (white at radiance L), aces, agx.
+.exr and .hdr outputs keep the linear radiance, other formats are tone mapped.
+Output variables: albedo, normal, depth, position, uv, object_id or all. They are
+written as layers of .exr outputs, as separate <output>_<name> images otherwise.
+--denoise filters the image guided by the albedo, normal and depth of the first hits.
+--adaptive keeps adding --min-samples samples to the pixels whose relative standard
+error is above ERROR, up to --samples. --heatmap saves the samples taken per pixel.
+--progressive refines the whole image in passes of N samples, --target-error stops
+once the estimated RMSE relative to the average luminance is below ERROR.
+--time-limit stops every frame after SECONDS and saves the samples taken until then.
+--checkpoint saves the samples to FILE at the end of a pass every --checkpoint-interval
+(60 seconds by default) and when the frame is done, those of the last complete pass
+if --time-limit stopped it. --resume goes on from the samples of FILE, unless the scene,
+camera, size, sampler, depths or adaptive settings changed. Raise --samples to add
+samples to a finished render.
+
+Animated scenes render numbered frames, the run of # in the output name is replaced
+with the frame number, otherwise the number is appended to the file name.";
+
+const PROGRESS_WIDTH: usize = 40;
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(100);
+
+struct RenderArgs {
+    scene_file: String,
+    width: u32,
+    height: u32,
+    samples: u32,
+    trace: TraceSettings,
+    threads: u32,
+    frames: Option<RangeInclusive<u32>>,
+    tone_mapping: ToneMapping,
+    exr_precision: ExrPrecision,
+    aovs: Vec<Aov>,
+    denoise: bool,
+    time_limit: Option<Duration>,
+    checkpoint: Option<String>,
+    checkpoint_interval: Duration,
+    resume: bool,
+    heatmap: Option<String>,
+    output: String,
+}
+
+impl RenderArgs {
+    fn parse(args: &[String]) -> Result<Self, String> {
+        let mut scene_file = None;
+        let mut result = RenderArgs {
+            scene_file: String::new(),
+            width: 1600,
+            height: 900,
+            samples: 100,
+            trace: TraceSettings::default(),
+            threads: thread::available_parallelism().map_or(4, |n| n.get() as u32),
+            frames: None,
+            tone_mapping: ToneMapping::default(),
+            exr_precision: ExrPrecision::default(),
+            aovs: Vec::new(),
+            denoise: false,
+            time_limit: None,
+            checkpoint: None,
+            checkpoint_interval: Duration::from_secs(60),
+            resume: false,
+            heatmap: None,
+            output: "rendered.png".into(),
+        };
+
+        let mut iter = args.iter();
+        while let Some(arg) = iter.next() {
+            if !arg.starts_with("--") {
+                if scene_file.replace(arg.clone()).is_some() {
+                    return Err(format!("Unexpected argument: {}", arg));
+                }
+                continue;
+            }
+            if arg == "--denoise" {
+                result.denoise = true;
+                continue;
+            }
+            if arg == "--resume" {
+                result.resume = true;
+                continue;
+            }
+
+            let value = iter
+                .next()
+                .ok_or_else(|| format!("Missing value for {}", arg))?;
+            match arg.as_str() {
+                "--width" => result.width = parse_number(arg, value)?,
+                "--height" => result.height = parse_number(arg, value)?,
+                "--samples" => result.samples = parse_number(arg, value)?,
+                "--depth" => result.trace.max_depth = Some(parse_number(arg, value)?),
+                "--rr-depth" => result.trace.rr_min_depth = parse_number(arg, value)?,
+                "--sampler" => result.trace.sampler = value.parse()?,
+                "--seed" => {
+                    result.trace.seed = value
+                        .parse()
+                        .map_err(|_| format!("Incorrect value for {}: {}", arg, value))?
+                }
+                "--threads" => result.threads = parse_number(arg, value)?,
+                "--frames" => result.frames = Some(parse_frames(value)?),
+                "--tonemap" => result.tone_mapping.operator = value.parse()?,
+                "--exposure" => result.tone_mapping.exposure = parse_float(arg, value)?,
+                "--white" => result.tone_mapping.white = parse_float(arg, value)?,
+                "--exr-precision" => result.exr_precision = value.parse()?,
+                "--aovs" => result.aovs = parse_aovs(value)?,
+                "--adaptive" => {
+                    let threshold = parse_float(arg, value)?;
+                    let adaptive = result.trace.adaptive.get_or_insert_with(Default::default);
+                    adaptive.threshold = threshold;
+                }
+                "--min-samples" => {
+                    let min_samples = parse_number(arg, value)?;
+                    let adaptive = result.trace.adaptive.get_or_insert_with(Default::default);
+                    adaptive.min_samples = min_samples;
+                }
+                "--progressive" => {
+                    let pass_samples = parse_number(arg, value)?;
+                    let progressive = result.trace.progressive.get_or_insert_with(Default::default);
+                    progressive.pass_samples = pass_samples;
+                }
+                "--target-error" => {
+                    let target_error = parse_float(arg, value)?;
+                    let progressive = result.trace.progressive.get_or_insert_with(Default::default);
+                    progressive.target_error = Some(target_error);
+                }
+                "--time-limit" => match parse_float(arg, value)? {
+                    seconds if seconds > 0.0 => {
+                        result.time_limit = Some(Duration::from_secs_f64(seconds))
+                    }
+                    _ => return Err(format!("Incorrect value for {}: {}", arg, value)),
+                },
+                "--checkpoint" => result.checkpoint = Some(value.clone()),
+                "--checkpoint-interval" => match parse_float(arg, value)? {
+                    seconds if seconds >= 0.0 => {
+                        result.checkpoint_interval = Duration::from_secs_f64(seconds)
+                    }
+                    _ => return Err(format!("Incorrect value for {}: {}", arg, value)),
+                },
+                "--heatmap" => result.heatmap = Some(value.clone()),
+                "--output" => result.output = value.clone(),
+                _ => return Err(format!("Unknown option: {}", arg)),
+            }
+        }
+
+        result.trace.aovs = !result.aovs.is_empty() || result.denoise;
+        if result.resume && result.checkpoint.is_none() {
+            return Err("--resume needs --checkpoint".into());
+        }
+        result.scene_file = scene_file.ok_or("Need world file")?;
+        Ok(result)
+    }
+}
+
+fn parse_number(name: &str, value: &str) -> Result<u32, String> {
+    match value.parse() {
+        Ok(n) if n > 0 => Ok(n),
+        _ => Err(format!("Incorrect value for {}: {}", name, value)),
+    }
+}
+
+fn parse_float(name: &str, value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(x) if x.is_finite() => Ok(x),
+        _ => Err(format!("Incorrect value for {}: {}", name, value)),
+    }
+}
+
+fn parse_aovs(value: &str) -> Result<Vec<Aov>, String> {
+    if value == "all" {
+        return Ok(Aov::ALL.to_vec());
+    }
+    value.split(',').map(|name| name.trim().parse()).collect()
+}
+
+/// `FIRST-LAST` or a single frame.
+fn parse_frames(value: &str) -> Result<RangeInclusive<u32>, String> {
+    let error = || format!("Incorrect value for --frames: {}", value);
+    let (first, last) = value.split_once('-').unwrap_or((value, value));
+    let first: u32 = first.trim().parse().map_err(|_| error())?;
+    let last: u32 = last.trim().parse().map_err(|_| error())?;
+    if last < first {
+        return Err(error());
+    }
+    Ok(first..=last)
+}
+
+fn frame_file_name(output: &str, frame: u32) -> String {
+    if let Some(start) = output.find('#') {
+        let width = output[start..].chars().take_while(|&c| c == '#').count();
+        return format!(
+            "{}{:0width$}{}",
+            &output[..start],
+            frame,
+            &output[start + width..],
+            width = width
+        );
+    }
+    match output.rfind('.').filter(|&dot| !output[dot..].contains('/')) {
+        Some(dot) => format!("{}_{:04}{}", &output[..dot], frame, &output[dot..]),
+        None => format!("{}_{:04}", output, frame),
+    }
+}
+
+fn main() {
+    let args = env::args().skip(1).collect::<Vec<String>>();
+    let args = RenderArgs::parse(&args).unwrap_or_else(|err| {
+        eprintln!("{}\n{}", err, USAGE);
+        process::exit(2);
+    });
+
+    if let Err(err) = render(&args) {
+        eprintln!("{}", err);
+        process::exit(1);
+    }
+}
+
+fn render(args: &RenderArgs) -> Result<(), String> {
+    let json = fs::read_to_string(&args.scene_file)
+        .map_err(|err| format!("Could not read scene file {}: {}", args.scene_file, err))?;
+    let scene = Scene::from_json_seeded(&json, args.trace.seed)
+        .map_err(|err| format!("Loading scene {} failed: {}", args.scene_file, err))?;
+
+    // output, heatmap, checkpoint and camera of every frame
+    let frames = match (scene.animation(), &args.frames) {
+        (None, None) => vec![(
+            args.output.clone(),
+            args.heatmap.clone(),
+            args.checkpoint.clone(),
+            scene.camera().clone(),
+        )],
+        (None, Some(_)) => return Err(format!("Scene {} has no animation", args.scene_file)),
+        (Some(animation), frames) => frames
+            .clone()
+            .unwrap_or_else(|| animation.frames())
+            .map(|frame| {
+                (
+                    frame_file_name(&args.output, frame),
+                    args.heatmap.as_ref().map(|name| frame_file_name(name, frame)),
+                    args.checkpoint.as_ref().map(|name| frame_file_name(name, frame)),
+                    animation.camera_at(scene.camera(), frame),
+                )
+            })
+            .collect(),
+    };
+
+    let img_params = ImageParams {
+        width: args.width,
+        height: args.height,
+    };
+    let shared_scene = Arc::new(RwLock::new(scene));
+    let mut renderer = TileRenderer::new(shared_scene, args.threads, args.trace);
+    if args.checkpoint.is_some() {
+        let hash = scene_hash(&json).map_err(|err| err.to_string())?;
+        renderer = renderer.with_checkpoints(hash, args.checkpoint_interval);
+    }
+    renderer.set_time_budget(args.time_limit);
+    let mut printed: Option<Instant> = None;
+    renderer.on_progress(Box::new(move |event, progress| {
+        if event == RenderEvent::Finished
+            || printed.is_none_or(|printed| printed.elapsed() >= PROGRESS_INTERVAL)
+        {
+            printed = Some(Instant::now());
+            print_progress(progress);
+        }
+    }));
+
+    for (output, heatmap_output, checkpoint, camera) in frames {
+        let resume = match &checkpoint {
+            Some(checkpoint) if args.resume && Path::new(checkpoint).exists() => {
+                Some(Checkpoint::load(checkpoint)?)
+            }
+            _ => None,
+        };
+        let mut buffer = render_image(
+            &mut renderer,
+            camera.clone(),
+            &img_params,
+            args.samples,
+            resume,
+            checkpoint.as_deref(),
+        )?;
+        match renderer.progress().state {
+            RenderState::OutOfTime => println!("Time limit reached"),
+            RenderState::Converged => println!("Target error reached"),
+            _ => (),
+        }
+        if args.denoise {
+            let guides = Guides::from_aovs(renderer.aovs(), &camera);
+            buffer = Denoiser::default().denoise(
+                img_params.width,
+                img_params.height,
+                &buffer,
+                &guides,
+            );
+        }
+        let aovs = args
+            .aovs
+            .iter()
+            .map(|aov| (*aov, aov.buffer(renderer.aovs(), &camera)))
+            .collect::<Vec<_>>();
+        save_image(
+            &output,
+            img_params.width,
+            img_params.height,
+            &buffer,
+            &aovs,
+            &args.tone_mapping,
+            args.exr_precision,
+        )?;
+        println!("Saved {}", output);
+
+        let convergence = renderer.convergence();
+        println!(
+            "{:.1} samples per pixel in {} passes, {:.1}s, estimated RMSE {:.4} ({:.2}%)",
+            convergence.samples_per_pixel,
+            convergence.passes,
+            convergence.elapsed.as_secs_f64(),
+            convergence.rmse,
+            convergence.relative_rmse * 100.0
+        );
+        if let Some(heatmap_output) = heatmap_output {
+            let samples = renderer.accumulation().sample_counts();
+            save_image(
+                &heatmap_output,
+                img_params.width,
+                img_params.height,
+                &heatmap(&samples, args.samples),
+                &[],
+                &ToneMapping::default(),
+                args.exr_precision,
+            )?;
+            println!("Saved {}", heatmap_output);
+        }
+    }
+    Ok(())
+}
+
+/// Renders the frame, going on from the samples of `resume` if any and saving the
+/// checkpoints of the renderer to `checkpoint`.
+fn render_image(
+    renderer: &mut TileRenderer,
+    camera: Camera,
+    img_params: &ImageParams,
+    samples: u32,
+    resume: Option<Checkpoint>,
+    checkpoint: Option<&str>,
+) -> Result<Vec<Vector3d>, String> {
+    let mut buffer = vec![Vector3d::zero(); (img_params.width * img_params.height) as usize];
+
+    let camera = Arc::new(RwLock::new(camera));
+    match resume {
+        Some(resume) => {
+            let first = resume.next_sample;
+            renderer.resume_rendering(camera, img_params, samples, resume)?;
+            println!("Resuming from {} samples per pixel", first);
+        }
+        None => renderer.start_rendering(camera, img_params, samples),
+    }
+    // polled often, the next pass only starts from here
+    loop {
+        let is_finished = renderer.render_step(&mut buffer);
+        if let (Some(path), Some(saved)) = (checkpoint, renderer.take_checkpoint()) {
+            saved.save(path)?;
+        }
+        if is_finished {
+            break;
+        }
+        thread::sleep(Duration::from_millis(5));
+    }
+    eprintln!();
+
+    Ok(buffer)
+}
+
+fn print_progress(progress: &Progress) {
+    let fraction = progress.fraction().clamp(0.0, 1.0);
+    let filled = (fraction * PROGRESS_WIDTH as f64) as usize;
+    let eta = match progress.eta {
+        Some(eta) => format!("{:.1}s", eta.as_secs_f64()),
+        None => "?".into(),
+    };
+    eprint!(
+        "\r[{}{}] {:>3}% {:.1}s, {} spp, ETA {}   ",
+        "#".repeat(filled),
+        " ".repeat(PROGRESS_WIDTH - filled),
+        (fraction * 100.0) as u32,
+        progress.elapsed.as_secs_f64(),
+        progress.samples,
+        eta
+    );
+    io::stderr().flush().ok();
+}
@@ -6,27 +6,53 @@ use std::{
 
 use serde::{Deserialize, Serialize};
 
-use crate::algebra::Vector3d;
+use crate::algebra::{
+    sampling::{concentric_disk, regular_polygon},
+    Vector3d,
+};
+
+use self::projection::{Projection, ProjectionType};
 
+pub mod projection;
 pub mod ray_caster;
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 struct CameraJson {
+    #[serde(rename = "type", default)]
+    projection: ProjectionType,
     position: Vector3d,
     direction: Vector3d,
     up: Vector3d,
     fov: f64,
     focal_length: f64,
+    #[serde(default)]
+    aperture: f64,
+    focus_distance: Option<f64>,
+    #[serde(default)]
+    aperture_blades: u32,
+    #[serde(default)]
+    blade_rotation: f64,
+    #[serde(default)]
+    shutter_open: f64,
+    #[serde(default)]
+    shutter_close: f64,
 }
 
 impl From<Camera> for CameraJson {
     fn from(cam: Camera) -> Self {
         CameraJson {
+            projection: cam.projection,
             position: cam.position,
             direction: cam.direction,
             up: cam.up,
             focal_length: cam.focal_length,
             fov: cam.fov.to_degrees(),
+            aperture: cam.aperture,
+            focus_distance: Some(cam.focus_distance),
+            aperture_blades: cam.aperture_blades,
+            blade_rotation: cam.blade_rotation.to_degrees(),
+            shutter_open: cam.shutter_open,
+            shutter_close: cam.shutter_close,
         }
     }
 }
@@ -40,6 +66,17 @@ pub struct Camera {
     up: Vector3d,
     fov: f64,
     focal_length: f64,
+    /// Diameter of the lens, zero for a pinhole.
+    aperture: f64,
+    /// Distance along the view direction to the plane in focus.
+    focus_distance: f64,
+    /// Number of sides of a polygonal aperture, zero for a round one.
+    aperture_blades: u32,
+    blade_rotation: f64,
+    projection: ProjectionType,
+    /// Rays are spread over the time between opening and closing the shutter.
+    shutter_open: f64,
+    shutter_close: f64,
 
     //  autogenerated
     rigth: Vector3d,
@@ -47,13 +84,19 @@ pub struct Camera {
 
 impl From<CameraJson> for Camera {
     fn from(cam: CameraJson) -> Self {
-        Camera::new(
+        let mut camera = Camera::new(
             &cam.position,
             &cam.direction,
             &cam.up,
             cam.focal_length,
             cam.fov.to_radians(),
-        )
+        );
+        camera.set_aperture(cam.aperture);
+        camera.set_focus_distance(cam.focus_distance.unwrap_or(cam.focal_length));
+        camera.set_aperture_blades(cam.aperture_blades, cam.blade_rotation.to_radians());
+        camera.set_projection(cam.projection);
+        camera.set_shutter(cam.shutter_open, cam.shutter_close);
+        camera
     }
 }
 
@@ -84,6 +127,13 @@ impl Camera {
             rigth: right_vec,
             fov: fov,
             focal_length: focal_length,
+            aperture: 0.0,
+            focus_distance: focal_length,
+            aperture_blades: 0,
+            blade_rotation: 0.0,
+            projection: ProjectionType::Perspective,
+            shutter_open: 0.0,
+            shutter_close: 0.0,
         }
     }
 
@@ -119,6 +169,86 @@ impl Camera {
         self.focal_length = focal_length;
     }
 
+    /// Get a reference to the camera's aperture.
+    pub fn aperture(&self) -> f64 {
+        self.aperture
+    }
+
+    /// Set the camera's aperture.
+    pub fn set_aperture(&mut self, aperture: f64) {
+        self.aperture = aperture;
+    }
+
+    /// Get a reference to the camera's focus distance.
+    pub fn focus_distance(&self) -> f64 {
+        self.focus_distance
+    }
+
+    /// Set the camera's focus distance.
+    pub fn set_focus_distance(&mut self, focus_distance: f64) {
+        self.focus_distance = focus_distance;
+    }
+
+    /// Get a reference to the camera's aperture blades.
+    pub fn aperture_blades(&self) -> u32 {
+        self.aperture_blades
+    }
+
+    /// Set the number of aperture blades and their rotation in radians.
+    pub fn set_aperture_blades(&mut self, blades: u32, rotation: f64) {
+        self.aperture_blades = blades;
+        self.blade_rotation = rotation;
+    }
+
+    /// Get a reference to the camera's projection type.
+    pub fn projection_type(&self) -> ProjectionType {
+        self.projection
+    }
+
+    /// Set the camera's projection.
+    pub fn set_projection(&mut self, projection: ProjectionType) {
+        self.projection = projection;
+    }
+
+    pub fn projection(&self) -> &'static dyn Projection {
+        self.projection.projection()
+    }
+
+    /// Get a reference to the camera's shutter open time.
+    pub fn shutter_open(&self) -> f64 {
+        self.shutter_open
+    }
+
+    /// Get a reference to the camera's shutter close time.
+    pub fn shutter_close(&self) -> f64 {
+        self.shutter_close
+    }
+
+    /// Set the times of opening and closing the shutter.
+    pub fn set_shutter(&mut self, open: f64, close: f64) {
+        self.shutter_open = open;
+        self.shutter_close = close.max(open);
+    }
+
+    /// Maps `u` from [0, 1) onto the shutter interval.
+    pub fn sample_time(&self, u: f64) -> f64 {
+        self.shutter_open + u * (self.shutter_close - self.shutter_open)
+    }
+
+    /// Picks a point on the lens relative to the camera position.
+    pub fn sample_lens(&self, u: (f64, f64)) -> Vector3d {
+        if self.aperture <= 0.0 {
+            return Vector3d::zero();
+        }
+        let (x, y) = if self.aperture_blades >= 3 {
+            regular_polygon(u, self.aperture_blades, self.blade_rotation)
+        } else {
+            concentric_disk(u)
+        };
+        let radius = self.aperture / 2.0;
+        self.rigth * (x * radius) + self.up * (y * radius)
+    }
+
     /// Get a reference to the camera's position.
     pub fn position(&self) -> &Vector3d {
         &self.position
@@ -306,7 +436,7 @@ impl CameraOrbitControl {
 #[cfg(test)]
 mod tests {
     use crate::{
-        algebra::{self, Vector3d},
+        algebra::Vector3d,
         camera::ray_caster::{ImageParams, MultisamplerRayCaster},
     };
 
@@ -331,14 +461,68 @@ mod tests {
 
         let ray_caster = MultisamplerRayCaster::new(&cam, &img_params, 100);
 
-        assert!(algebra::approx_equal(
-            ray_caster.pixel_resolution(),
-            2.0 / img_params.width as f64
-        ));
-
         assert_eq!(
             ray_caster.len(),
             (img_params.width * img_params.height) as usize
         );
     }
+
+    #[test]
+    fn test_thin_lens() {
+        let img_params = ImageParams {
+            width: 100,
+            height: 50,
+        };
+        let mut cam = Camera::new(
+            &Vector3d::new(0.0, 0.0, 0.0),
+            &Vector3d::new(0.0, 0.0, -1.0),
+            &Vector3d::new(0.0, 1.0, 0.0),
+            1.0,
+            90.0_f64.to_radians(),
+        );
+        cam.set_aperture(0.5);
+        cam.set_focus_distance(4.0);
+        cam.set_aperture_blades(6, 0.3);
+
+        let ray_caster = MultisamplerRayCaster::new(&cam, &img_params, 1);
+        for lens in [(0.1, 0.2), (0.5, 0.5), (0.99, 0.7)] {
+            let ray = ray_caster.get_ray(30.0, 10.0, lens);
+            assert!((ray.origin - cam.position()).length() <= 0.25);
+
+            // every ray through a pixel meets the others on the focus plane
+            let t = (-4.0 - ray.origin.z) / ray.direction.z;
+            let focus_point = ray.origin + t * ray.direction;
+            let expected = Vector3d::new(-4.0 + 30.0 * 0.08, 2.0 - 10.0 * 0.08, -4.0);
+            assert!((focus_point - expected).length() < 1e-9, "{}", focus_point);
+        }
+    }
+
+    #[test]
+    fn test_projections() {
+        let mut cam: Camera = serde_json::from_str(
+            r#"{"type": "Orthographic", "position": [0.0, 0.0, 0.0], "direction": [0.0, 0.0, -1.0],
+                "up": [0.0, 1.0, 0.0], "fov": 90.0, "focal_length": 1.0, "focus_distance": 2.0}"#,
+        )
+        .unwrap();
+        assert_eq!(cam.projection_type(), ProjectionType::Orthographic);
+
+        let ray = cam.projection().get_ray(&cam, (1.0, -1.0), 2.0, None);
+        assert_eq!(ray.direction, Vector3d::new(0.0, 0.0, -1.0));
+        assert!((ray.origin - Vector3d::new(2.0, -1.0, 0.0)).length() < 1e-9);
+
+        cam.set_projection(ProjectionType::Fisheye);
+        cam.set_fov(PI);
+        let ray = cam.projection().get_ray(&cam, (1.0, 0.0), 2.0, None);
+        assert!((ray.direction - Vector3d::new(1.0, 0.0, 0.0)).length() < 1e-9);
+        let ray = cam.projection().get_ray(&cam, (0.0, 1.0), 2.0, None);
+        assert!((ray.direction - Vector3d::new(0.0, 1.0, -1.0).normalize()).length() < 1e-9);
+
+        cam.set_projection(ProjectionType::Equirectangular);
+        let ray = cam.projection().get_ray(&cam, (0.0, 0.0), 2.0, None);
+        assert!((ray.direction - Vector3d::new(0.0, 0.0, -1.0)).length() < 1e-9);
+        let ray = cam.projection().get_ray(&cam, (-1.0, 0.0), 2.0, None);
+        assert!((ray.direction - Vector3d::new(0.0, 0.0, 1.0)).length() < 1e-9);
+        let ray = cam.projection().get_ray(&cam, (0.5, 1.0), 2.0, None);
+        assert!((ray.direction - Vector3d::new(0.0, 1.0, 0.0)).length() < 1e-9);
+    }
 }
@@ -0,0 +1,142 @@
+use std::f64::consts::{FRAC_PI_2, PI};
+use std::fmt::Debug;
+
+use serde::{Deserialize, Serialize};
+
+use crate::world::ray::Ray;
+
+use super::Camera;
+
+/// Maps a point of the film to a primary ray.
+pub trait Projection: Debug + Send + Sync {
+    /// `film` spans [-1, 1] on both axes with y up, `aspect` is width over height.
+    /// `lens` is a point of the unit square mapped onto the aperture, `None` for a pinhole.
+    fn get_ray(
+        &self,
+        camera: &Camera,
+        film: (f64, f64),
+        aspect: f64,
+        lens: Option<(f64, f64)>,
+    ) -> Ray;
+}
+
+/// Selects the projection in the camera's JSON `type` tag.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ProjectionType {
+    #[default]
+    Perspective,
+    Orthographic,
+    Fisheye,
+    Equirectangular,
+}
+
+impl ProjectionType {
+    pub fn projection(&self) -> &'static dyn Projection {
+        match self {
+            ProjectionType::Perspective => &Perspective,
+            ProjectionType::Orthographic => &Orthographic,
+            ProjectionType::Fisheye => &Fisheye,
+            ProjectionType::Equirectangular => &Equirectangular,
+        }
+    }
+}
+
+/// Pinhole or thin lens camera, `fov` spans the image width.
+#[derive(Debug)]
+pub struct Perspective;
+
+impl Projection for Perspective {
+    fn get_ray(
+        &self,
+        camera: &Camera,
+        film: (f64, f64),
+        aspect: f64,
+        lens: Option<(f64, f64)>,
+    ) -> Ray {
+        let half_width = (camera.fov / 2.0).tan() * camera.focal_length;
+        let viewport_point = camera.position
+            + camera.direction * camera.focal_length
+            + camera.rigth * (film.0 * half_width)
+            + camera.up * (film.1 * half_width / aspect);
+
+        match lens {
+            Some(lens) if camera.aperture > 0.0 => {
+                let focus_scale = camera.focus_distance / camera.focal_length;
+                let target = camera.position + (viewport_point - camera.position) * focus_scale;
+                let origin = camera.position + camera.sample_lens(lens);
+                Ray::new(origin, target - origin)
+            }
+            _ => Ray::new(camera.position, viewport_point - camera.position),
+        }
+    }
+}
+
+/// Parallel rays, framed like the perspective view at the focus distance.
+#[derive(Debug)]
+pub struct Orthographic;
+
+impl Projection for Orthographic {
+    fn get_ray(
+        &self,
+        camera: &Camera,
+        film: (f64, f64),
+        aspect: f64,
+        _lens: Option<(f64, f64)>,
+    ) -> Ray {
+        let half_width = (camera.fov / 2.0).tan() * camera.focus_distance;
+        let origin = camera.position
+            + camera.rigth * (film.0 * half_width)
+            + camera.up * (film.1 * half_width / aspect);
+        Ray::new(origin, camera.direction)
+    }
+}
+
+/// Equidistant fisheye, the angle from the view direction grows linearly with
+/// the distance from the image center and `fov` spans the image width.
+#[derive(Debug)]
+pub struct Fisheye;
+
+impl Projection for Fisheye {
+    fn get_ray(
+        &self,
+        camera: &Camera,
+        film: (f64, f64),
+        aspect: f64,
+        _lens: Option<(f64, f64)>,
+    ) -> Ray {
+        let (x, y) = (film.0, film.1 / aspect);
+        let r = (x * x + y * y).sqrt();
+        if r == 0.0 {
+            return Ray::new(camera.position, camera.direction);
+        }
+
+        let theta = (r * camera.fov / 2.0).min(PI);
+        let side = (camera.rigth * x + camera.up * y) / r;
+        Ray::new(
+            camera.position,
+            camera.direction * theta.cos() + side * theta.sin(),
+        )
+    }
+}
+
+/// Latitude-longitude panorama covering the whole sphere, the image center
+/// looks along the view direction.
+#[derive(Debug)]
+pub struct Equirectangular;
+
+impl Projection for Equirectangular {
+    fn get_ray(
+        &self,
+        camera: &Camera,
+        film: (f64, f64),
+        _aspect: f64,
+        _lens: Option<(f64, f64)>,
+    ) -> Ray {
+        let longitude = film.0 * PI;
+        let latitude = film.1 * FRAC_PI_2;
+        let direction = camera.direction * (latitude.cos() * longitude.cos())
+            + camera.rigth * (latitude.cos() * longitude.sin())
+            + camera.up * latitude.sin();
+        Ray::new(camera.position, direction)
+    }
+}
@@ -1,9 +1,10 @@
 use std::ops::Range;
 
 use itertools::Itertools;
-use rand::{prelude::ThreadRng, Rng};
-
-use crate::{algebra::Vector3d, world::ray::Ray};
+use crate::{
+    algebra::sampler::{Sampler, SamplerType},
+    world::ray::Ray,
+};
 
 use super::Camera;
 
@@ -15,35 +16,26 @@ pub struct ImageParams {
 
 #[derive(Debug)]
 pub struct MultisamplerRayCaster {
-    camera_position: Vector3d,
-    camera_right: Vector3d,
-    camera_up: Vector3d,
+    camera: Camera,
+    width: f64,
+    height: f64,
 
-    left_top: Vector3d,
     coords_iter: itertools::Product<Range<u32>, Range<u32>>,
-    pixel_resolution: f64,
-    rng: ThreadRng,
+    sampler: Box<dyn Sampler>,
     samples_number: u32,
 }
 
 impl MultisamplerRayCaster {
     pub fn new(camera: &Camera, img_params: &ImageParams, samples_number: u32) -> Self {
-        let center = &camera.position + camera.focal_length * &camera.direction;
-        let aspect_ratio = img_params.width as f64 / img_params.height as f64;
-        let viewport_width = (camera.fov / 2.0).tan() * camera.focal_length * 2.0;
-        let viewport_height = viewport_width / aspect_ratio;
         let coords_iter = (0..img_params.height).cartesian_product(0..img_params.width);
         
         Self {
-            left_top: center - &camera.rigth * (viewport_width / 2.0)
-                + &camera.up * (viewport_height / 2.0),
+            camera: camera.clone(),
+            width: img_params.width as f64,
+            height: img_params.height as f64,
             coords_iter: coords_iter,
-            pixel_resolution: viewport_width / img_params.width as f64,
-            rng: rand::thread_rng(),
+            sampler: SamplerType::default().sampler(samples_number, 0),
             samples_number: samples_number,
-            camera_position: camera.position.clone(),
-            camera_right: camera.rigth.clone(),
-            camera_up: camera.up.clone(),
         }
     }
 
@@ -54,47 +46,53 @@ impl MultisamplerRayCaster {
         partial_image: ImageParams,
         samples_number: u32,
     ) -> Self {
-        let center = &camera.position + camera.focal_length * &camera.direction;
-        let aspect_ratio = whole_image.width as f64 / whole_image.height as f64;
-        let viewport_width = (camera.fov / 2.0).tan() * camera.focal_length * 2.0;
-        let viewport_height = viewport_width / aspect_ratio;
         let coords_iter =
             (from.0..partial_image.height).cartesian_product(from.1..partial_image.width);
 
         Self {
-            left_top: center - &camera.rigth * (viewport_width / 2.0)
-                + &camera.up * (viewport_height / 2.0),
+            camera: camera.clone(),
+            width: whole_image.width as f64,
+            height: whole_image.height as f64,
             coords_iter,
-            pixel_resolution: viewport_width / whole_image.width as f64,
-            rng: rand::thread_rng(),
+            sampler: SamplerType::default().sampler(samples_number, 0),
             samples_number: samples_number,
-            camera_position: camera.position.clone(),
-            camera_right: camera.rigth.clone(),
-            camera_up: camera.up.clone(),
         }
     }
 
-    pub fn get_ray(&self, x: f64, y: f64) -> Ray {
-        let dir = &self.left_top + (self.pixel_resolution * x) * &self.camera_right
-            - (self.pixel_resolution * y) * &self.camera_up;
-        Ray::new(self.camera_position.clone(), dir - &self.camera_position)
+    /// Takes the pixel samples from `sampler_type` seeded with `seed`.
+    pub fn with_sampler(mut self, sampler_type: SamplerType, seed: u32) -> Self {
+        self.sampler = sampler_type.sampler(self.samples_number, seed);
+        self
+    }
+
+    /// `lens` is a point of the unit square mapped onto the aperture.
+    pub fn get_ray(&self, x: f64, y: f64, lens: (f64, f64)) -> Ray {
+        let film = (2.0 * x / self.width - 1.0, 1.0 - 2.0 * y / self.height);
+        self.camera
+            .projection()
+            .get_ray(&self.camera, film, self.width / self.height, Some(lens))
     }
 
     pub fn get_pixel_sample(&mut self, x: u32, y: u32) -> Vec<Ray> {
-        (0..self.samples_number)
-            .map(|_| {
-                let u: f64 = self.rng.gen();
-                let v: f64 = self.rng.gen();
+        self.get_pixel_samples(x, y, 0..self.samples_number)
+    }
 
-                self.get_ray(x as f64 + u, y as f64 + v)
+    /// Rays of the samples of the pixel with the given indices.
+    pub fn get_pixel_samples(&mut self, x: u32, y: u32, indices: Range<u32>) -> Vec<Ray> {
+        let pixel = x + y * self.width as u32;
+        indices
+            .map(|index| {
+                self.sampler.start_pixel_sample(pixel, index, 0);
+                let (u, v) = self.sampler.get_2d();
+                let lens = self.sampler.get_2d();
+                let time = self.camera.sample_time(self.sampler.get_1d());
+
+                let mut ray = self.get_ray(x as f64 + u, y as f64 + v, lens);
+                ray.time = time;
+                ray
             })
             .collect()
     }
-
-    /// Get a reference to the multisampler ray caster's pixel resolution.
-    pub fn pixel_resolution(&self) -> f64 {
-        self.pixel_resolution
-    }
 }
 
 impl Iterator for MultisamplerRayCaster {
@@ -102,17 +100,7 @@ impl Iterator for MultisamplerRayCaster {
 
     fn next(&mut self) -> Option<Self::Item> {
         let (y, x) = self.coords_iter.next()?;
-        let samples = (0..self.samples_number)
-            .map(|_| {
-                let u: f64 = self.rng.gen();
-                let v: f64 = self.rng.gen();
-
-                let dir = &self.left_top
-                    + (self.pixel_resolution * (x as f64 + u)) * &self.camera_right
-                    - (self.pixel_resolution * (y as f64 + v)) * &self.camera_up;
-                Ray::new(self.camera_position.clone(), dir - &self.camera_position)
-            })
-            .collect();
+        let samples = self.get_pixel_sample(x, y);
 
         Some((x, y, samples))
     }
@@ -126,24 +114,18 @@ impl ExactSizeIterator for MultisamplerRayCaster {}
 
 pub struct SinglesamplerRayCaster<'a> {
     camera: &'a Camera,
-    left_bottom: Vector3d,
+    width: f64,
+    height: f64,
     coords_iter: itertools::Product<Range<u32>, Range<u32>>,
-    pixel_resolution: f64,
 }
 
 impl<'a> SinglesamplerRayCaster<'a> {
     pub fn new(camera: &'a Camera, img_params: ImageParams) -> Self {
-        let center = &camera.position + camera.focal_length * &camera.direction;
-        let aspect_ratio = img_params.width as f64 / img_params.height as f64;
-        let viewport_width = (camera.fov / 2.0).tan() * camera.focal_length * 2.0;
-        let viewport_height = viewport_width / aspect_ratio;
         Self {
             camera: camera,
-            left_bottom: center
-                - &camera.rigth * (viewport_width / 2.0)
-                - &camera.up * (viewport_height / 2.0),
+            width: img_params.width as f64,
+            height: img_params.height as f64,
             coords_iter: (0..img_params.height).cartesian_product(0..img_params.width),
-            pixel_resolution: viewport_width / img_params.width as f64,
         }
     }
 }
@@ -154,11 +136,18 @@ impl<'a> Iterator for SinglesamplerRayCaster<'a> {
     fn next(&mut self) -> Option<Self::Item> {
         let (v, u) = self.coords_iter.next()?;
 
-        let dir = &self.left_bottom
-            + (self.pixel_resolution * (u as f64 + 0.5)) * &self.camera.rigth
-            + (self.pixel_resolution * (v as f64 + 0.5)) * &self.camera.up;
-
-        let ray = Ray::new(self.camera.position.clone(), dir);
+        // rows are counted from the bottom
+        let film = (
+            2.0 * (u as f64 + 0.5) / self.width - 1.0,
+            2.0 * (v as f64 + 0.5) / self.height - 1.0,
+        );
+        let mut ray = self.camera.projection().get_ray(
+            self.camera,
+            film,
+            self.width / self.height,
+            None,
+        );
+        ray.time = self.camera.shutter_open();
 
         Some((u, v, ray))
     }
@@ -1,4 +1,5 @@
 pub mod algebra;
 pub mod camera;
+pub mod output;
 pub mod renderer;
 pub mod world;
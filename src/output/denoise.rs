@@ -0,0 +1,243 @@
+use std::thread;
+
+use crate::{
+    algebra::Vector3d,
+    camera::Camera,
+    renderer::aov::{Aov, AovBuffer, PixelAovs},
+};
+
+/// Feature buffers steering the denoiser, rows from the top.
+#[derive(Clone, Debug)]
+pub struct Guides {
+    pub albedo: Vec<Vector3d>,
+    /// Zero where nothing is hit.
+    pub normal: Vec<Vector3d>,
+    /// Camera space depth, infinite where nothing is hit.
+    pub depth: Vec<f64>,
+}
+
+impl Guides {
+    pub fn from_aovs(pixels: &[PixelAovs], camera: &Camera) -> Self {
+        let depth = match Aov::Depth.buffer(pixels, camera) {
+            AovBuffer::Scalar(depth) => depth,
+            _ => unreachable!(),
+        };
+        Self {
+            albedo: pixels.iter().map(|pixel| pixel.albedo).collect(),
+            normal: pixels.iter().map(|pixel| pixel.normal).collect(),
+            depth,
+        }
+    }
+}
+
+/// Edge-avoiding à-trous wavelet filter (Dammertz et al.). The color is divided by the
+/// albedo before filtering so textures stay sharp, and neighbours with a different
+/// albedo, normal or depth get little weight.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Denoiser {
+    /// Number of passes, the filter covers `4 * 2^iterations` pixels.
+    pub iterations: u32,
+    /// Tolerated difference of the compressed irradiance, halved at every pass.
+    pub sigma_color: f64,
+    pub sigma_albedo: f64,
+    pub sigma_normal: f64,
+    /// Tolerated relative depth difference per pixel of distance.
+    pub sigma_depth: f64,
+}
+
+impl Default for Denoiser {
+    fn default() -> Self {
+        Self {
+            iterations: 5,
+            sigma_color: 0.6,
+            sigma_albedo: 0.1,
+            sigma_normal: 0.3,
+            sigma_depth: 0.02,
+        }
+    }
+}
+
+const KERNEL: [f64; 5] = [1.0 / 16.0, 1.0 / 4.0, 3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+
+/// Albedo below which the color isn't divided any further, black surfaces would blow up.
+const MIN_ALBEDO: f64 = 0.01;
+
+impl Denoiser {
+    pub fn denoise(
+        &self,
+        width: u32,
+        height: u32,
+        color: &[Vector3d],
+        guides: &Guides,
+    ) -> Vec<Vector3d> {
+        let albedo = guides
+            .albedo
+            .iter()
+            .map(|albedo| albedo.map(|c| c.max(MIN_ALBEDO)))
+            .collect::<Vec<_>>();
+        let mut irradiance = color
+            .iter()
+            .zip(&albedo)
+            .map(|(color, albedo)| color.divide(albedo))
+            .collect::<Vec<_>>();
+
+        for iteration in 0..self.iterations {
+            irradiance = self.filter_pass(width, height, &irradiance, guides, iteration);
+        }
+
+        irradiance
+            .iter()
+            .zip(&albedo)
+            .map(|(irradiance, albedo)| irradiance.product(albedo))
+            .collect()
+    }
+
+    fn filter_pass(
+        &self,
+        width: u32,
+        height: u32,
+        input: &[Vector3d],
+        guides: &Guides,
+        iteration: u32,
+    ) -> Vec<Vector3d> {
+        let (width, height) = (width as usize, height as usize);
+        let step = 1 << iteration;
+        let sigma_color = self.sigma_color / (1 << iteration) as f64;
+        let threads = thread::available_parallelism().map_or(4, |n| n.get());
+        let rows_per_thread = height.div_ceil(threads).max(1);
+
+        let mut output = vec![Vector3d::zero(); input.len()];
+        thread::scope(|scope| {
+            for (chunk, rows) in output.chunks_mut(rows_per_thread * width).enumerate() {
+                scope.spawn(move || {
+                    for (offset, result) in rows.iter_mut().enumerate() {
+                        let index = chunk * rows_per_thread * width + offset;
+                        *result = self.filter_pixel(
+                            width,
+                            height,
+                            input,
+                            guides,
+                            index,
+                            step,
+                            sigma_color,
+                        );
+                    }
+                });
+            }
+        });
+        output
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn filter_pixel(
+        &self,
+        width: usize,
+        height: usize,
+        input: &[Vector3d],
+        guides: &Guides,
+        index: usize,
+        step: usize,
+        sigma_color: f64,
+    ) -> Vector3d {
+        let (x, y) = (index % width, index / width);
+        let color = compress(&input[index]);
+        let mut sum = Vector3d::zero();
+        let mut weight_sum = 0.0;
+
+        for (j, ky) in KERNEL.iter().enumerate() {
+            let ny = y as isize + (j as isize - 2) * step as isize;
+            if ny < 0 || ny >= height as isize {
+                continue;
+            }
+            for (i, kx) in KERNEL.iter().enumerate() {
+                let nx = x as isize + (i as isize - 2) * step as isize;
+                if nx < 0 || nx >= width as isize {
+                    continue;
+                }
+                let other = ny as usize * width + nx as usize;
+
+                let depth_weight = match (guides.depth[index], guides.depth[other]) {
+                    (a, b) if a.is_finite() && b.is_finite() => {
+                        let distance = ((nx - x as isize).abs() + (ny - y as isize).abs()) as f64;
+                        let tolerance = self.sigma_depth * distance.max(1.0) * a.abs().max(1e-6);
+                        (-(a - b).abs() / tolerance).exp()
+                    }
+                    (a, b) if a.is_finite() || b.is_finite() => continue,
+                    _ => 1.0,
+                };
+                let weight = kx
+                    * ky
+                    * depth_weight
+                    * gaussian(&(compress(&input[other]) - color), sigma_color)
+                    * gaussian(
+                        &(guides.albedo[other] - guides.albedo[index]),
+                        self.sigma_albedo,
+                    )
+                    * gaussian(
+                        &(guides.normal[other] - guides.normal[index]),
+                        self.sigma_normal,
+                    );
+                sum += input[other] * weight;
+                weight_sum += weight;
+            }
+        }
+
+        if weight_sum > 0.0 {
+            sum / weight_sum
+        } else {
+            input[index]
+        }
+    }
+}
+
+/// Maps radiance to [0, 1) so bright lights don't dominate the color distances.
+fn compress(color: &Vector3d) -> Vector3d {
+    color.map(|c| c.max(0.0) / (1.0 + c.max(0.0)))
+}
+
+fn gaussian(difference: &Vector3d, sigma: f64) -> f64 {
+    (-difference.squared_length() / (sigma * sigma)).exp()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    #[test]
+    fn test_denoise_keeps_edges() {
+        let (width, height) = (32, 32);
+        let mut rng = StdRng::seed_from_u64(0);
+        let albedo = (0..width * height)
+            .map(|i| {
+                let c = if i % width < width / 2 { 0.2 } else { 0.8 };
+                Vector3d::new(c, c, c)
+            })
+            .collect::<Vec<_>>();
+        let noisy = albedo
+            .iter()
+            .map(|albedo| *albedo * rng.gen_range(0.5..1.5))
+            .collect::<Vec<_>>();
+        let guides = Guides {
+            albedo: albedo.clone(),
+            normal: vec![Vector3d::new(0.0, 0.0, 1.0); albedo.len()],
+            depth: vec![1.0; albedo.len()],
+        };
+
+        let denoised = Denoiser::default().denoise(width, height, &noisy, &guides);
+        let error = |image: &[Vector3d]| {
+            image
+                .iter()
+                .zip(&albedo)
+                .map(|(pixel, albedo)| (pixel.x - albedo.x).abs())
+                .sum::<f64>()
+                / image.len() as f64
+        };
+        assert!(error(&denoised) < error(&noisy) / 4.0);
+
+        let row = (height / 2 * width) as usize;
+        let half = (width / 2) as usize;
+        assert!((denoised[row + half - 1].x - 0.2).abs() < 0.05);
+        assert!((denoised[row + half].x - 0.8).abs() < 0.15);
+    }
+}
@@ -0,0 +1,163 @@
+use std::{collections::HashMap, path::Path};
+
+use exr::prelude::*;
+
+use crate::algebra::Vector3d;
+
+/// Sample type of the written channels.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ExrPrecision {
+    #[default]
+    Half,
+    Float,
+}
+
+impl std::str::FromStr for ExrPrecision {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "half" => Ok(ExrPrecision::Half),
+            "float" => Ok(ExrPrecision::Float),
+            _ => Err(format!("Unknown EXR precision: {}", s)),
+        }
+    }
+}
+
+/// Values of a layer, rows from the top.
+#[derive(Debug, Clone, Copy)]
+pub enum ExrPixels<'a> {
+    Rgb(&'a [Vector3d]),
+    /// Single channel at the layer precision.
+    Scalar(&'a [f64]),
+    /// Single 32 bit integer channel.
+    Id(&'a [u32]),
+}
+
+impl ExrPixels<'_> {
+    fn len(&self) -> usize {
+        match self {
+            ExrPixels::Rgb(pixels) => pixels.len(),
+            ExrPixels::Scalar(pixels) => pixels.len(),
+            ExrPixels::Id(pixels) => pixels.len(),
+        }
+    }
+}
+
+/// Color image stored in the `name.R`, `name.G` and `name.B` channels, or in `R`, `G`
+/// and `B` when the name is empty. Single channel images are stored in the `name` channel.
+#[derive(Debug, Clone, Copy)]
+pub struct ExrLayer<'a> {
+    pub name: &'a str,
+    pub pixels: ExrPixels<'a>,
+}
+
+fn float_samples(values: impl Iterator<Item = f64>, precision: ExrPrecision) -> FlatSamples {
+    let values = values.map(|value| value as f32);
+    match precision {
+        ExrPrecision::Half => FlatSamples::F16(values.map(f16::from_f32).collect()),
+        ExrPrecision::Float => FlatSamples::F32(values.collect()),
+    }
+}
+
+/// Writes the layers as the channels of a single part EXR file, rows from the top.
+pub fn write_exr(
+    path: impl AsRef<Path>,
+    width: u32,
+    height: u32,
+    layers: &[ExrLayer],
+    precision: ExrPrecision,
+) -> std::result::Result<(), String> {
+    let path = path.as_ref();
+    let pixel_count = (width * height) as usize;
+    let mut channels = SmallVec::new();
+    for layer in layers {
+        if layer.pixels.len() != pixel_count {
+            return Err(format!(
+                "Layer {:?} has {} pixels instead of {}",
+                layer.name,
+                layer.pixels.len(),
+                pixel_count
+            ));
+        }
+        match layer.pixels {
+            ExrPixels::Rgb(pixels) => {
+                for (component, suffix) in ["R", "G", "B"].into_iter().enumerate() {
+                    let name = match layer.name {
+                        "" => suffix.to_string(),
+                        prefix => format!("{}.{}", prefix, suffix),
+                    };
+                    let values = pixels.iter().map(|pixel| pixel[component]);
+                    channels.push(AnyChannel::new(
+                        name.as_str(),
+                        float_samples(values, precision),
+                    ));
+                }
+            }
+            ExrPixels::Scalar(pixels) => channels.push(AnyChannel::new(
+                layer.name,
+                float_samples(pixels.iter().copied(), precision),
+            )),
+            ExrPixels::Id(pixels) => channels.push(AnyChannel::new(
+                layer.name,
+                FlatSamples::U32(pixels.to_vec()),
+            )),
+        }
+    }
+
+    let layer = Layer::new(
+        (width as usize, height as usize),
+        LayerAttributes::default(),
+        Encoding::FAST_LOSSLESS,
+        AnyChannels::sort(channels),
+    );
+    Image::from_layer(layer)
+        .write()
+        .to_file(path)
+        .map_err(|err| format!("Could not save image {}: {}", path.display(), err))
+}
+
+/// Channels of the first part of an EXR file, converted to floats.
+#[derive(Debug, Clone)]
+pub struct ExrImage {
+    pub width: u32,
+    pub height: u32,
+    pub channels: HashMap<String, Vec<f64>>,
+}
+
+impl ExrImage {
+    /// Layer written as `ExrPixels::Rgb`, `None` if a channel is missing.
+    pub fn rgb(&self, name: &str) -> Option<Vec<Vector3d>> {
+        let channel = |suffix: &str| match name {
+            "" => self.channels.get(suffix),
+            prefix => self.channels.get(&format!("{}.{}", prefix, suffix)),
+        };
+        let (r, g, b) = (channel("R")?, channel("G")?, channel("B")?);
+        Some(
+            (0..r.len())
+                .map(|i| Vector3d::new(r[i], g[i], b[i]))
+                .collect(),
+        )
+    }
+}
+
+pub fn read_exr(path: impl AsRef<Path>) -> std::result::Result<ExrImage, String> {
+    let path = path.as_ref();
+    let image = read_first_flat_layer_from_file(path)
+        .map_err(|err| format!("Could not read image {}: {}", path.display(), err))?;
+    let channels = image
+        .layer_data
+        .channel_data
+        .list
+        .iter()
+        .map(|channel| {
+            let values = channel.sample_data.values_as_f32().map(f64::from).collect();
+            (channel.name.to_string(), values)
+        })
+        .collect();
+    Ok(ExrImage {
+        width: image.layer_data.size.width() as u32,
+        height: image.layer_data.size.height() as u32,
+        channels,
+    })
+}
@@ -0,0 +1,26 @@
+use std::{fs::File, io::BufWriter, path::Path};
+
+use image::{codecs::hdr::HdrEncoder, Rgb};
+
+use crate::algebra::Vector3d;
+
+/// Writes a Radiance RGBE image, rows from the top.
+pub fn write_hdr(
+    path: impl AsRef<Path>,
+    width: u32,
+    height: u32,
+    pixels: &[Vector3d],
+) -> Result<(), String> {
+    let path = path.as_ref();
+    let error =
+        |err: &dyn std::fmt::Display| format!("Could not save image {}: {}", path.display(), err);
+
+    let file = File::create(path).map_err(|err| error(&err))?;
+    let data = pixels
+        .iter()
+        .map(|pixel| Rgb([pixel.x as f32, pixel.y as f32, pixel.z as f32]))
+        .collect::<Vec<_>>();
+    HdrEncoder::new(BufWriter::new(file))
+        .encode(&data, width as usize, height as usize)
+        .map_err(|err| error(&err))
+}
@@ -0,0 +1,279 @@
+use std::path::{Path, PathBuf};
+
+use crate::{
+    algebra::Vector3d,
+    renderer::aov::{Aov, AovBuffer},
+};
+
+use self::{
+    exr::{write_exr, ExrLayer, ExrPixels, ExrPrecision},
+    hdr::write_hdr,
+    tonemap::{srgb_decode, srgb_encode, ToneMapping},
+};
+
+pub mod denoise;
+pub mod exr;
+pub mod hdr;
+pub mod tonemap;
+
+impl AovBuffer {
+    fn exr_pixels(&self) -> ExrPixels<'_> {
+        match self {
+            AovBuffer::Rgb(pixels) => ExrPixels::Rgb(pixels),
+            AovBuffer::Scalar(pixels) => ExrPixels::Scalar(pixels),
+            AovBuffer::Id(pixels) => ExrPixels::Id(pixels),
+        }
+    }
+
+    /// Linear color version, scalars are gray and ids get a random color.
+    fn to_rgb(&self) -> Vec<Vector3d> {
+        match self {
+            AovBuffer::Rgb(pixels) => pixels.clone(),
+            AovBuffer::Scalar(pixels) => pixels
+                .iter()
+                .map(|&value| {
+                    let value = if value.is_finite() { value } else { 0.0 };
+                    Vector3d::new(value, value, value)
+                })
+                .collect(),
+            AovBuffer::Id(pixels) => pixels.iter().map(|&id| id_color(id)).collect(),
+        }
+    }
+}
+
+fn id_color(id: u32) -> Vector3d {
+    if id == 0 {
+        return Vector3d::zero();
+    }
+    // spread the bits so close ids get different colors
+    let hash = id.wrapping_mul(0x9e3779b1);
+    Vector3d::new(
+        (hash >> 24) as f64 / 255.0,
+        ((hash >> 16) & 0xff) as f64 / 255.0,
+        ((hash >> 8) & 0xff) as f64 / 255.0,
+    )
+}
+
+/// Scales the finite values of the channels to [0, 1], separately for each channel.
+fn normalize(pixels: &[Vector3d]) -> Vec<Vector3d> {
+    let finite = pixels
+        .iter()
+        .filter(|pixel| (0..3).all(|i| pixel[i].is_finite()));
+    let low = finite
+        .clone()
+        .fold(Vector3d::infinity(), |acc, p| acc.min(p));
+    let high = finite.fold(Vector3d::neg_infinity(), |acc, p| acc.max(p));
+    let range = (high - low).map(|c| if c > 0.0 { c } else { 1.0 });
+    pixels
+        .iter()
+        .map(|pixel| {
+            (*pixel - low)
+                .divide(&range)
+                .map(|c| if c.is_finite() { c } else { 0.0 })
+        })
+        .collect()
+}
+
+/// 8 bit preview of an output variable.
+fn aov_to_rgb8(aov: Aov, buffer: &AovBuffer) -> Vec<u8> {
+    let display = match (aov, buffer) {
+        (Aov::Normal, AovBuffer::Rgb(pixels)) => pixels
+            .iter()
+            .map(|n| (*n + Vector3d::new(1.0, 1.0, 1.0)) / 2.0)
+            .collect(),
+        (Aov::Position, AovBuffer::Rgb(pixels)) => normalize(pixels),
+        // near is bright, nothing hit is black
+        (_, AovBuffer::Scalar(pixels)) => normalize(&buffer.to_rgb())
+            .into_iter()
+            .zip(pixels)
+            .map(|(value, depth)| {
+                if depth.is_finite() {
+                    value.map(|c| 1.0 - 0.9 * c)
+                } else {
+                    Vector3d::zero()
+                }
+            })
+            .collect(),
+        (_, buffer) => buffer.to_rgb(),
+    };
+    // colors are stored as sRGB, the other values are shown as they are
+    let encode = |c: f64| match aov {
+        Aov::Albedo | Aov::ObjectId => srgb_encode(c.clamp(0.0, 1.0)),
+        _ => c.clamp(0.0, 1.0),
+    };
+    display
+        .iter()
+        .flat_map(|pixel| [pixel.x, pixel.y, pixel.z].map(|c| (encode(c) * 255.0).round() as u8))
+        .collect()
+}
+
+/// Stops of the heatmap ramp in sRGB, from black through purple and orange to yellow.
+const HEATMAP: [[f64; 3]; 5] = [
+    [0.0, 0.0, 0.02],
+    [0.34, 0.06, 0.43],
+    [0.73, 0.22, 0.33],
+    [0.98, 0.55, 0.04],
+    [0.99, 1.0, 0.64],
+];
+
+/// Linear colors showing `values` from zero to `max`, e.g. the samples taken per pixel.
+pub fn heatmap(values: &[u32], max: u32) -> Vec<Vector3d> {
+    values
+        .iter()
+        .map(|&value| {
+            let t = (value as f64 / max.max(1) as f64).clamp(0.0, 1.0);
+            let position = t * (HEATMAP.len() - 1) as f64;
+            let index = (position as usize).min(HEATMAP.len() - 2);
+            let fraction = position - index as f64;
+            let (low, high) = (HEATMAP[index], HEATMAP[index + 1]);
+            Vector3d::new(
+                low[0] + (high[0] - low[0]) * fraction,
+                low[1] + (high[1] - low[1]) * fraction,
+                low[2] + (high[2] - low[2]) * fraction,
+            )
+            .map(srgb_decode)
+        })
+        .collect()
+}
+
+/// `image_albedo.png` for the albedo of `image.png`.
+pub fn aov_file_name(path: &Path, aov: Aov) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match path.extension() {
+        Some(ext) => format!("{}_{}.{}", stem, aov, ext.to_string_lossy()),
+        None => format!("{}_{}", stem, aov),
+    };
+    path.with_file_name(name)
+}
+
+/// Saves a rendered image in the format given by the extension of `path`. EXR and
+/// Radiance files keep the linear radiance, other formats are tone mapped to 8 bits.
+/// The output variables become layers of an EXR file and separate images otherwise,
+/// named by `aov_file_name`.
+pub fn save_image(
+    path: impl AsRef<Path>,
+    width: u32,
+    height: u32,
+    pixels: &[Vector3d],
+    aovs: &[(Aov, AovBuffer)],
+    tone_mapping: &ToneMapping,
+    precision: ExrPrecision,
+) -> Result<(), String> {
+    let path = path.as_ref();
+    let extension = path
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase());
+    let save_rgb8 = |path: &Path, data: &[u8]| {
+        image::save_buffer(path, data, width, height, image::ColorType::Rgb8)
+            .map_err(|err| format!("Could not save image {}: {}", path.display(), err))
+    };
+    match extension.as_deref() {
+        Some("exr") => {
+            let layers = [ExrLayer {
+                name: "",
+                pixels: ExrPixels::Rgb(pixels),
+            }]
+            .into_iter()
+            .chain(aovs.iter().map(|(aov, buffer)| ExrLayer {
+                name: aov.name(),
+                pixels: buffer.exr_pixels(),
+            }))
+            .collect::<Vec<_>>();
+            return write_exr(path, width, height, &layers, precision);
+        }
+        Some("hdr") => write_hdr(path, width, height, pixels)?,
+        _ => save_rgb8(path, &tone_mapping.to_rgb8(pixels))?,
+    }
+
+    for (aov, buffer) in aovs {
+        let aov_path = aov_file_name(path, *aov);
+        match extension.as_deref() {
+            Some("hdr") => write_hdr(&aov_path, width, height, &buffer.to_rgb())?,
+            _ => save_rgb8(&aov_path, &aov_to_rgb8(*aov, buffer))?,
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hdr_output() {
+        let (width, height) = (3, 2);
+        let pixels = (0..width * height)
+            .map(|i| Vector3d::new(i as f64, 0.25, 100.0))
+            .collect::<Vec<_>>();
+        let dir = std::env::temp_dir();
+
+        for precision in [ExrPrecision::Half, ExrPrecision::Float] {
+            let path = dir.join(format!("ray_tracing_test_{:?}.exr", precision));
+            let depth = (0..width * height).map(f64::from).collect::<Vec<_>>();
+            let layers = [
+                ExrLayer {
+                    name: "",
+                    pixels: ExrPixels::Rgb(&pixels),
+                },
+                ExrLayer {
+                    name: "albedo",
+                    pixels: ExrPixels::Rgb(&pixels),
+                },
+                ExrLayer {
+                    name: "depth",
+                    pixels: ExrPixels::Scalar(&depth),
+                },
+                ExrLayer {
+                    name: "object_id",
+                    pixels: ExrPixels::Id(&[7; 6]),
+                },
+            ];
+            write_exr(&path, width, height, &layers, precision).unwrap();
+
+            let image = ::exr::prelude::read_first_flat_layer_from_file(&path).unwrap();
+            let _ = std::fs::remove_file(&path);
+            let names = image
+                .layer_data
+                .channel_data
+                .list
+                .iter()
+                .map(|channel| channel.name.to_string())
+                .collect::<Vec<_>>();
+            assert_eq!(
+                names,
+                [
+                    "B",
+                    "G",
+                    "R",
+                    "albedo.B",
+                    "albedo.G",
+                    "albedo.R",
+                    "depth",
+                    "object_id"
+                ]
+            );
+            let red = &image.layer_data.channel_data.list[2].sample_data;
+            assert_eq!(red.value_by_flat_index(5).to_f32(), 5.0);
+        }
+
+        let path = dir.join("ray_tracing_test.hdr");
+        save_image(
+            &path,
+            width,
+            height,
+            &pixels,
+            &[],
+            &ToneMapping::default(),
+            ExrPrecision::Half,
+        )
+        .unwrap();
+        let file = std::io::BufReader::new(std::fs::File::open(&path).unwrap());
+        let image = image::codecs::hdr::HdrDecoder::new(file)
+            .unwrap()
+            .read_image_hdr()
+            .unwrap();
+        let _ = std::fs::remove_file(&path);
+        let pixel = image[5];
+        assert!((pixel[0] - 5.0).abs() < 0.05 && (pixel[2] - 100.0).abs() < 1.0);
+    }
+}
@@ -0,0 +1,244 @@
+use std::{fmt::Display, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+use crate::algebra::Vector3d;
+
+/// Curve compressing scene radiance into the displayable range.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ToneMapOperator {
+    /// Clips everything above one.
+    #[default]
+    Linear,
+    Reinhard,
+    /// Reinhard reaching one at the `white` radiance.
+    ExtendedReinhard,
+    /// Fit of the ACES filmic reference and output transforms (Hill).
+    Aces,
+    /// AgX base look, which desaturates bright colors towards white (Wrensch's fit).
+    Agx,
+}
+
+impl ToneMapOperator {
+    pub const ALL: [ToneMapOperator; 5] = [
+        ToneMapOperator::Linear,
+        ToneMapOperator::Reinhard,
+        ToneMapOperator::ExtendedReinhard,
+        ToneMapOperator::Aces,
+        ToneMapOperator::Agx,
+    ];
+
+    /// The following operator, wrapping around.
+    pub fn next(&self) -> Self {
+        let index = Self::ALL.iter().position(|op| op == self).unwrap();
+        Self::ALL[(index + 1) % Self::ALL.len()]
+    }
+}
+
+impl Display for ToneMapOperator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            ToneMapOperator::Linear => "linear",
+            ToneMapOperator::Reinhard => "reinhard",
+            ToneMapOperator::ExtendedReinhard => "extended-reinhard",
+            ToneMapOperator::Aces => "aces",
+            ToneMapOperator::Agx => "agx",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for ToneMapOperator {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "linear" => Ok(ToneMapOperator::Linear),
+            "reinhard" => Ok(ToneMapOperator::Reinhard),
+            "extended-reinhard" => Ok(ToneMapOperator::ExtendedReinhard),
+            "aces" => Ok(ToneMapOperator::Aces),
+            "agx" => Ok(ToneMapOperator::Agx),
+            _ => Err(format!("Unknown tone mapping: {}", s)),
+        }
+    }
+}
+
+/// Display transform from linear radiance to 8 bit sRGB, shared by the viewer and the
+/// image writers.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ToneMapping {
+    pub operator: ToneMapOperator,
+    /// Exposure compensation in stops.
+    pub exposure: f64,
+    /// Radiance mapped to one by the extended Reinhard curve.
+    pub white: f64,
+}
+
+impl Default for ToneMapping {
+    fn default() -> Self {
+        Self {
+            operator: ToneMapOperator::default(),
+            exposure: 0.0,
+            white: 4.0,
+        }
+    }
+}
+
+impl ToneMapping {
+    /// Display-referred linear color in [0, 1].
+    pub fn map(&self, color: &Vector3d) -> Vector3d {
+        let color = (*color * self.exposure.exp2()).map(|c| c.max(0.0));
+        let mapped = match self.operator {
+            ToneMapOperator::Linear => color,
+            ToneMapOperator::Reinhard => color.map(|c| c / (1.0 + c)),
+            ToneMapOperator::ExtendedReinhard => {
+                let white_squared = self.white * self.white;
+                color.map(|c| c * (1.0 + c / white_squared) / (1.0 + c))
+            }
+            ToneMapOperator::Aces => aces(&color),
+            ToneMapOperator::Agx => agx(&color),
+        };
+        mapped.map(|c| c.clamp(0.0, 1.0))
+    }
+
+    pub fn to_srgb8(&self, color: &Vector3d) -> [u8; 3] {
+        let mapped = self.map(color);
+        [mapped.x, mapped.y, mapped.z].map(|c| (srgb_encode(c) * 255.0).round() as u8)
+    }
+
+    /// Packed RGB bytes of the image.
+    pub fn to_rgb8(&self, buffer: &[Vector3d]) -> Vec<u8> {
+        buffer
+            .iter()
+            .flat_map(|color| self.to_srgb8(color))
+            .collect()
+    }
+
+    /// Fills the color channels of an RGBA frame and makes it opaque.
+    pub fn write_rgba8(&self, buffer: &[Vector3d], frame: &mut [u8]) {
+        for (dest, src) in frame.chunks_mut(4).zip(buffer) {
+            dest[..3].copy_from_slice(&self.to_srgb8(src));
+            dest[3] = 255;
+        }
+    }
+}
+
+/// sRGB transfer function for a linear value in [0, 1].
+pub fn srgb_encode(c: f64) -> f64 {
+    if c <= 0.0031308 {
+        12.92 * c
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// Inverse of `srgb_encode`.
+pub fn srgb_decode(c: f64) -> f64 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+type Matrix3 = [[f64; 3]; 3];
+
+fn transform(m: &Matrix3, v: &Vector3d) -> Vector3d {
+    Vector3d::new(
+        m[0][0] * v.x + m[0][1] * v.y + m[0][2] * v.z,
+        m[1][0] * v.x + m[1][1] * v.y + m[1][2] * v.z,
+        m[2][0] * v.x + m[2][1] * v.y + m[2][2] * v.z,
+    )
+}
+
+const ACES_INPUT: Matrix3 = [
+    [0.59719, 0.35458, 0.04823],
+    [0.07600, 0.90834, 0.01566],
+    [0.02840, 0.13383, 0.83777],
+];
+
+const ACES_OUTPUT: Matrix3 = [
+    [1.60475, -0.53108, -0.07367],
+    [-0.10208, 1.10813, -0.00605],
+    [-0.00327, -0.07276, 1.07602],
+];
+
+fn aces(color: &Vector3d) -> Vector3d {
+    let v = transform(&ACES_INPUT, color).map(|c| {
+        let a = c * (c + 0.0245786) - 0.000090537;
+        let b = c * (0.983729 * c + 0.4329510) + 0.238081;
+        a / b
+    });
+    transform(&ACES_OUTPUT, &v)
+}
+
+const AGX_INSET: Matrix3 = [
+    [0.842479062253094, 0.0784335999999992, 0.0792237451477643],
+    [0.0423282422610123, 0.878468636469772, 0.0791661274605434],
+    [0.0423756549057051, 0.0784336, 0.879142973793104],
+];
+
+const AGX_OUTSET: Matrix3 = [
+    [1.19687900512017, -0.0980208811401368, -0.0990297440797205],
+    [-0.0528968517574562, 1.15190312990417, -0.0989611768448433],
+    [-0.0529716355144438, -0.0980434501171241, 1.15107367264116],
+];
+
+const AGX_MIN_EV: f64 = -12.47393;
+const AGX_MAX_EV: f64 = 4.026069;
+
+fn agx(color: &Vector3d) -> Vector3d {
+    let encoded = transform(&AGX_INSET, color).map(|c| {
+        let ev = c.max(1e-10).log2().clamp(AGX_MIN_EV, AGX_MAX_EV);
+        let x = (ev - AGX_MIN_EV) / (AGX_MAX_EV - AGX_MIN_EV);
+        // polynomial fit of the AgX sigmoid
+        let (x2, x4) = (x * x, x * x * x * x);
+        15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2 + 0.1191 * x
+            - 0.00232
+    });
+    // the curve targets a 2.2 gamma display
+    transform(&AGX_OUTSET, &encoded).map(|c| c.max(0.0).powf(2.2))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tone_mapping() {
+        assert_eq!(srgb_encode(0.0), 0.0);
+        assert!((srgb_encode(1.0) - 1.0).abs() < 1e-12);
+        assert!((srgb_decode(srgb_encode(0.2)) - 0.2).abs() < 1e-12);
+
+        let gray = Vector3d::new(0.18, 0.18, 0.18);
+        let linear = ToneMapping::default();
+        assert_eq!(linear.map(&gray).x, 0.18);
+        let brighter = ToneMapping {
+            exposure: 1.0,
+            ..linear
+        };
+        assert!((brighter.map(&gray).x - 0.36).abs() < 1e-12);
+
+        for operator in ToneMapOperator::ALL {
+            let mapping = ToneMapping {
+                operator,
+                ..Default::default()
+            };
+            // monotonic and bounded, bright lights don't clip before the white point
+            let mut previous = -1.0;
+            for radiance in [0.0, 0.01, 0.18, 0.5, 1.0, 2.0, 3.9, 16.0, 1000.0] {
+                let value = mapping.map(&Vector3d::new(radiance, radiance, radiance)).y;
+                assert!((0.0..=1.0).contains(&value), "{:?}", operator);
+                assert!(value >= previous, "{:?}", operator);
+                previous = value;
+            }
+            if operator != ToneMapOperator::Linear {
+                assert!(mapping.map(&Vector3d::new(2.0, 2.0, 2.0)).y < 1.0);
+            }
+            assert_eq!(
+                operator.to_string().parse::<ToneMapOperator>(),
+                Ok(operator)
+            );
+        }
+    }
+}
@@ -0,0 +1,209 @@
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+
+use crate::algebra::Vector3d;
+
+use super::adaptive::PixelStats;
+
+/// Refines the image in passes adding `pass_samples` samples to every pixel, until
+/// the samples number of the render or the target error is reached.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Progressive {
+    pub pass_samples: u32,
+    /// Relative RMSE at which the render stops, see `AccumulationBuffer::relative_rmse`.
+    pub target_error: Option<f64>,
+}
+
+impl Default for Progressive {
+    fn default() -> Self {
+        Self {
+            pass_samples: 1,
+            target_error: None,
+        }
+    }
+}
+
+/// Sum of the passes of a render, rows from the top.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AccumulationBuffer {
+    pub width: u32,
+    pub height: u32,
+    stats: Vec<PixelStats>,
+    passes: u32,
+    #[serde(skip, default = "Instant::now")]
+    started: Instant,
+    /// Set when the render is done, the clock doesn't run after that.
+    finished: Option<Duration>,
+    /// Time spent by the renders this one was resumed from.
+    earlier: Duration,
+}
+
+/// Summary of how far the render is.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Convergence {
+    pub passes: u32,
+    pub samples_per_pixel: f64,
+    pub rmse: f64,
+    pub relative_rmse: f64,
+    pub elapsed: Duration,
+}
+
+impl AccumulationBuffer {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            stats: vec![PixelStats::default(); (width * height) as usize],
+            passes: 0,
+            started: Instant::now(),
+            finished: None,
+            earlier: Duration::ZERO,
+        }
+    }
+
+    /// Restarts the clock of a finished buffer to add more samples.
+    pub fn resume(&mut self) {
+        self.earlier = self.elapsed();
+        self.started = Instant::now();
+        self.finished = None;
+    }
+
+    /// Adds samples of the pixel, `stats` covering only the new ones.
+    pub fn add(&mut self, index: usize, stats: &PixelStats) {
+        self.stats[index].merge(stats);
+    }
+
+    pub fn end_pass(&mut self) {
+        self.passes += 1;
+    }
+
+    pub fn finish(&mut self) {
+        self.finished.get_or_insert(self.started.elapsed());
+    }
+
+    pub fn pixel(&self, index: usize) -> &PixelStats {
+        &self.stats[index]
+    }
+
+    pub fn mean(&self) -> Vec<Vector3d> {
+        self.stats.iter().map(|stats| stats.mean).collect()
+    }
+
+    /// Variance of the luminance of the samples of every pixel.
+    pub fn variance(&self) -> Vec<f64> {
+        self.stats.iter().map(|stats| stats.variance()).collect()
+    }
+
+    pub fn sample_counts(&self) -> Vec<u32> {
+        self.stats.iter().map(|stats| stats.samples).collect()
+    }
+
+    pub fn passes(&self) -> u32 {
+        self.passes
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.earlier + self.finished.unwrap_or_else(|| self.started.elapsed())
+    }
+
+    /// Expected error of the luminance of the image, estimated from the variance of the
+    /// mean of every pixel. Infinite until every pixel has two samples.
+    pub fn rmse(&self) -> f64 {
+        if self.stats.iter().any(|stats| stats.samples < 2) {
+            return f64::INFINITY;
+        }
+        let squared_error = self
+            .stats
+            .iter()
+            .map(|stats| stats.variance() / stats.samples as f64)
+            .sum::<f64>();
+        (squared_error / self.stats.len().max(1) as f64).sqrt()
+    }
+
+    /// `rmse` divided by the average luminance, so it doesn't depend on the exposure.
+    pub fn relative_rmse(&self) -> f64 {
+        let luminance = self
+            .stats
+            .iter()
+            .map(|stats| stats.mean.luminance())
+            .sum::<f64>()
+            / self.stats.len().max(1) as f64;
+        self.rmse() / luminance.max(f64::MIN_POSITIVE)
+    }
+
+    pub fn convergence(&self) -> Convergence {
+        let samples = self
+            .stats
+            .iter()
+            .map(|stats| stats.samples as f64)
+            .sum::<f64>();
+        Convergence {
+            passes: self.passes,
+            samples_per_pixel: samples / self.stats.len().max(1) as f64,
+            rmse: self.rmse(),
+            relative_rmse: self.relative_rmse(),
+            elapsed: self.elapsed(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        camera::ray_caster::ImageParams,
+        renderer::{tests::ball_on_ground, tiled::TileRenderer, Renderer, TraceSettings},
+    };
+    use std::sync::{Arc, RwLock};
+
+    #[test]
+    fn test_progressive_accumulation() {
+        let mut buffer = AccumulationBuffer::new(2, 1);
+        for value in [0.2, 0.4, 0.6, 0.8] {
+            let mut stats = PixelStats::default();
+            stats.add_sample(&Vector3d::new(value, value, value));
+            buffer.add(0, &stats);
+            stats.add_sample(&Vector3d::new(0.5, 0.5, 0.5));
+            buffer.add(1, &stats);
+            buffer.end_pass();
+        }
+        assert_eq!(buffer.sample_counts(), [4, 8]);
+        assert_eq!(buffer.passes(), 4);
+        // first pixel: variance 0.2 / 3 over 4 samples, the second one has 8
+        let second = buffer.variance()[1] / 8.0;
+        let rmse = ((0.2 / 3.0 / 4.0 + second) / 2.0_f64).sqrt();
+        assert!((buffer.rmse() - rmse).abs() < 1e-12);
+        let luminance = (0.5 + buffer.mean()[1].luminance()) / 2.0;
+        assert!((buffer.relative_rmse() - rmse / luminance).abs() < 1e-12);
+
+        // a diffuse ball on the ground, refined until the error is low enough
+        let (scene, camera) = ball_on_ground();
+        let target_error = 0.01;
+        let settings = TraceSettings {
+            progressive: Some(Progressive {
+                pass_samples: 2,
+                target_error: Some(target_error),
+            }),
+            ..Default::default()
+        };
+        let img_params = ImageParams {
+            width: 8,
+            height: 8,
+        };
+        let mut renderer = TileRenderer::new(Arc::new(RwLock::new(scene)), 2, settings);
+        let mut image = vec![Vector3d::zero(); 8 * 8];
+        renderer.start_rendering(Arc::new(RwLock::new(camera)), &img_params, 4096);
+        while !renderer.render_step(&mut image) {
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+
+        let convergence = renderer.convergence();
+        assert!(convergence.relative_rmse <= target_error);
+        assert!(convergence.samples_per_pixel < 4096.0);
+        let counts = renderer.accumulation().sample_counts();
+        assert!(counts.iter().all(|&count| count == counts[0]));
+        assert_eq!(counts[0], convergence.passes * 2);
+        assert_eq!(image, renderer.accumulation().mean());
+        assert_eq!(renderer.rendered_pixels(), 8 * 8);
+    }
+}
@@ -0,0 +1,150 @@
+use serde::{Deserialize, Serialize};
+
+use crate::algebra::Vector3d;
+
+/// Traces pixels in passes and keeps adding samples to the pixels whose estimated
+/// error is above `threshold`, until they reach the samples number of the render.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct AdaptiveSampling {
+    /// Samples of the first pass, also added by every following pass.
+    pub min_samples: u32,
+    /// Standard error of the mean luminance relative to the luminance.
+    pub threshold: f64,
+}
+
+impl Default for AdaptiveSampling {
+    fn default() -> Self {
+        Self {
+            min_samples: 16,
+            threshold: 0.02,
+        }
+    }
+}
+
+impl AdaptiveSampling {
+    /// Whether the pixel still needs samples, `max_samples` being the most it may get.
+    pub fn needs_samples(&self, stats: &PixelStats, max_samples: u32) -> bool {
+        stats.samples < max_samples && stats.relative_error() > self.threshold
+    }
+}
+
+/// Running mean of the samples of a pixel and variance of their luminance.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct PixelStats {
+    pub samples: u32,
+    pub mean: Vector3d,
+    luminance_mean: f64,
+    /// Sum of the squared differences from the mean luminance.
+    luminance_m2: f64,
+}
+
+impl Default for PixelStats {
+    fn default() -> Self {
+        Self {
+            samples: 0,
+            mean: Vector3d::zero(),
+            luminance_mean: 0.0,
+            luminance_m2: 0.0,
+        }
+    }
+}
+
+impl PixelStats {
+    pub fn add_sample(&mut self, color: &Vector3d) {
+        self.merge(&PixelStats {
+            samples: 1,
+            mean: *color,
+            luminance_mean: color.luminance(),
+            luminance_m2: 0.0,
+        });
+    }
+
+    /// Combines the statistics of two disjoint sets of samples (Chan et al.).
+    pub fn merge(&mut self, other: &PixelStats) {
+        if other.samples == 0 {
+            return;
+        }
+        let count = (self.samples + other.samples) as f64;
+        let weight = other.samples as f64 / count;
+        let delta = other.luminance_mean - self.luminance_mean;
+
+        self.luminance_m2 += other.luminance_m2 + delta * delta * self.samples as f64 * weight;
+        self.luminance_mean += delta * weight;
+        self.mean = self.mean + (other.mean - self.mean) * weight;
+        self.samples += other.samples;
+    }
+
+    /// Unbiased variance of the sample luminance.
+    pub fn variance(&self) -> f64 {
+        if self.samples < 2 {
+            return 0.0;
+        }
+        self.luminance_m2 / (self.samples - 1) as f64
+    }
+
+    /// Standard error of the mean luminance, relative to it. Dark pixels are compared
+    /// with a floor so noise in the black isn't chased forever.
+    pub fn relative_error(&self) -> f64 {
+        if self.samples < 2 {
+            return f64::INFINITY;
+        }
+        let standard_error = (self.variance() / self.samples as f64).sqrt();
+        standard_error / self.luminance_mean.abs().max(0.01)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        camera::ray_caster::ImageParams,
+        renderer::{tests::ball_on_ground, tiled::TileRenderer, Renderer, TraceSettings},
+    };
+    use std::sync::{Arc, RwLock};
+
+    #[test]
+    fn test_adaptive_sampling() {
+        let values = [0.1, 0.5, 0.2, 0.9, 0.4];
+        let mut whole = PixelStats::default();
+        let mut parts = [PixelStats::default(), PixelStats::default()];
+        for (i, value) in values.iter().enumerate() {
+            let color = Vector3d::new(*value, *value, *value);
+            whole.add_sample(&color);
+            parts[i / 3].add_sample(&color);
+        }
+        let [mut merged, rest] = parts;
+        merged.merge(&rest);
+        assert_eq!(merged.samples, 5);
+        assert!((merged.mean.x - 0.42).abs() < 1e-12);
+        assert!((merged.variance() - whole.variance()).abs() < 1e-12);
+        assert!((whole.variance() - 0.097).abs() < 1e-12);
+
+        // a diffuse ball on the ground under the sky, the sky needs no more than the first pass
+        let (scene, camera) = ball_on_ground();
+        let adaptive = AdaptiveSampling {
+            min_samples: 4,
+            threshold: 0.01,
+        };
+        let settings = TraceSettings {
+            adaptive: Some(adaptive),
+            ..Default::default()
+        };
+        let img_params = ImageParams {
+            width: 16,
+            height: 16,
+        };
+        let mut renderer = TileRenderer::new(Arc::new(RwLock::new(scene)), 2, settings);
+        let mut buffer = vec![Vector3d::zero(); 16 * 16];
+        renderer.start_rendering(Arc::new(RwLock::new(camera)), &img_params, 64);
+        while !renderer.render_step(&mut buffer) {
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+
+        let counts = renderer.accumulation().sample_counts();
+        assert_eq!(counts[0], 4);
+        assert_eq!(buffer[0], Vector3d::new(1.0, 1.0, 1.0));
+        let center = 8 * 16 + 8;
+        assert!(counts[center] > 4 && counts[center] <= 64);
+        assert_eq!(renderer.rendered_pixels(), 16 * 16);
+    }
+}
@@ -0,0 +1,246 @@
+use std::{fmt::Display, str::FromStr};
+
+use crate::{
+    algebra::Vector3d,
+    camera::Camera,
+    world::{ray::Ray, Scene},
+};
+
+/// Arbitrary output variable, a buffer describing the first surface seen through each pixel.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Aov {
+    /// Surface color, the background color where nothing is hit.
+    Albedo,
+    /// World space shading normal facing the camera.
+    Normal,
+    /// Distance along the camera direction, infinite where nothing is hit.
+    Depth,
+    /// World space hit point.
+    Position,
+    /// Texture coordinates in the red and green channels.
+    Uv,
+    /// Hash of the name of the shape seen by the first sample, zero for the background.
+    ObjectId,
+}
+
+impl Aov {
+    pub const ALL: [Aov; 6] = [
+        Aov::Albedo,
+        Aov::Normal,
+        Aov::Depth,
+        Aov::Position,
+        Aov::Uv,
+        Aov::ObjectId,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Aov::Albedo => "albedo",
+            Aov::Normal => "normal",
+            Aov::Depth => "depth",
+            Aov::Position => "position",
+            Aov::Uv => "uv",
+            Aov::ObjectId => "object_id",
+        }
+    }
+
+    /// Values of the output for every pixel, `camera` being the one the image was rendered with.
+    pub fn buffer(&self, pixels: &[PixelAovs], camera: &Camera) -> AovBuffer {
+        let rgb = |f: fn(&PixelAovs) -> Vector3d| AovBuffer::Rgb(pixels.iter().map(f).collect());
+        match self {
+            Aov::Albedo => rgb(|pixel| pixel.albedo),
+            Aov::Normal => rgb(|pixel| pixel.normal),
+            Aov::Position => rgb(|pixel| pixel.position),
+            Aov::Uv => rgb(|pixel| Vector3d::new(pixel.uv.0, pixel.uv.1, 0.0)),
+            Aov::Depth => AovBuffer::Scalar(
+                pixels
+                    .iter()
+                    .map(|pixel| {
+                        if pixel.coverage > 0.0 {
+                            (pixel.position - camera.position()) * camera.direction()
+                        } else {
+                            f64::INFINITY
+                        }
+                    })
+                    .collect(),
+            ),
+            Aov::ObjectId => AovBuffer::Id(pixels.iter().map(|pixel| pixel.object_id).collect()),
+        }
+    }
+}
+
+impl Display for Aov {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for Aov {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Aov::ALL
+            .into_iter()
+            .find(|aov| aov.name() == s.to_lowercase())
+            .ok_or_else(|| format!("Unknown output variable: {}", s))
+    }
+}
+
+/// Image of an output variable, rows from the top.
+#[derive(Clone, Debug, PartialEq)]
+pub enum AovBuffer {
+    Rgb(Vec<Vector3d>),
+    Scalar(Vec<f64>),
+    Id(Vec<u32>),
+}
+
+/// First hit data of a pixel, averaged over its camera samples.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PixelAovs {
+    pub albedo: Vector3d,
+    pub normal: Vector3d,
+    pub position: Vector3d,
+    pub uv: (f64, f64),
+    pub object_id: u32,
+    /// Fraction of the samples hitting a shape, the other values are averaged over these.
+    pub coverage: f64,
+}
+
+impl Default for PixelAovs {
+    fn default() -> Self {
+        Self {
+            albedo: Vector3d::zero(),
+            normal: Vector3d::zero(),
+            position: Vector3d::zero(),
+            uv: (0.0, 0.0),
+            object_id: 0,
+            coverage: 0.0,
+        }
+    }
+}
+
+/// Identifier written to the object id output for the shape called `name`.
+pub fn object_id(name: &str) -> u32 {
+    // FNV-1a, zero is left for the background
+    let hash = name.bytes().fold(0x811c9dc5_u32, |hash, byte| {
+        (hash ^ byte as u32).wrapping_mul(0x01000193)
+    });
+    hash.max(1)
+}
+
+/// Follows the camera samples of a pixel to their first hit.
+pub fn trace_aovs(world: &Scene, rays: &[Ray]) -> PixelAovs {
+    let mut result = PixelAovs::default();
+    let mut hits = 0;
+    for (index, ray) in rays.iter().enumerate() {
+        let ray_hit = match world.closest_hit(ray, 0.001, f64::INFINITY) {
+            Some(ray_hit) => ray_hit,
+            None => {
+                result.albedo += world.background(ray).map(|c| c.clamp(0.0, 1.0));
+                continue;
+            }
+        };
+
+        let normal = ray_hit.normal();
+        result.albedo += ray_hit.material.albedo(&ray_hit);
+        result.normal += if normal * ray.direction > 0.0 {
+            -normal
+        } else {
+            *normal
+        };
+        result.position += ray_hit.point;
+        result.uv = (result.uv.0 + ray_hit.u, result.uv.1 + ray_hit.v);
+        if index == 0 {
+            result.object_id = object_id(ray_hit.object.unwrap_or_default());
+        }
+        hits += 1;
+    }
+
+    result.albedo = result.albedo / rays.len() as f64;
+    result.coverage = hits as f64 / rays.len() as f64;
+    if hits > 0 {
+        if !result.normal.is_zero() {
+            result.normal = result.normal.normalize();
+        }
+        result.position = result.position / hits as f64;
+        result.uv = (result.uv.0 / hits as f64, result.uv.1 / hits as f64);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        algebra::transform::InversableTransform,
+        world::{
+            background::Background,
+            material::{Lambertian, MaterialPtr},
+            shapes::{Shape, Sphere},
+            texture::SolidColor,
+        },
+    };
+    use std::{collections::HashMap, sync::Arc};
+
+    #[test]
+    fn test_first_hit_aovs() {
+        let material: MaterialPtr = Arc::new(Box::new(Lambertian {
+            albedo: Box::new(SolidColor {
+                color: Vector3d::new(0.2, 0.4, 0.6),
+            }),
+        }));
+        let sphere: Box<dyn Shape> = Box::new(Sphere::new(
+            "ball".into(),
+            InversableTransform::new(
+                Vector3d::zero(),
+                Vector3d::zero(),
+                Vector3d::new(1.0, 1.0, 1.0),
+            ),
+            material,
+            false,
+        ));
+        let camera = Camera::new(
+            &Vector3d::new(0.0, 0.0, 5.0),
+            &Vector3d::new(0.0, 0.0, -1.0),
+            &Vector3d::new(0.0, 1.0, 0.0),
+            1.0,
+            90.0_f64.to_radians(),
+        );
+        let background = Vector3d::new(0.5, 2.0, 0.0);
+        let scene = Scene::new(
+            vec![sphere],
+            HashMap::new(),
+            Vec::new(),
+            camera.clone(),
+            Background::Solid(background),
+        );
+
+        let origin = Vector3d::new(0.0, 0.0, 5.0);
+        let hit = Ray::new(origin, Vector3d::new(0.0, 0.0, -1.0));
+        let miss = Ray::new(origin, Vector3d::new(0.0, 1.0, 0.0));
+        let pixels = [
+            trace_aovs(&scene, &[hit.clone(), miss.clone()]),
+            trace_aovs(&scene, &[miss]),
+        ];
+
+        let covered = &pixels[0];
+        assert_eq!(covered.coverage, 0.5);
+        assert_eq!(covered.object_id, object_id("ball"));
+        assert!((covered.normal - Vector3d::new(0.0, 0.0, 1.0)).length() < 1e-9);
+        assert!((covered.position - Vector3d::new(0.0, 0.0, 1.0)).length() < 1e-9);
+        let albedo = (Vector3d::new(0.2, 0.4, 0.6) + Vector3d::new(0.5, 1.0, 0.0)) / 2.0;
+        assert!((covered.albedo - albedo).length() < 1e-9);
+
+        let empty = &pixels[1];
+        assert_eq!((empty.coverage, empty.object_id), (0.0, 0));
+
+        match Aov::Depth.buffer(&pixels, &camera) {
+            AovBuffer::Scalar(depth) => {
+                assert!((depth[0] - 4.0).abs() < 1e-9);
+                assert!(depth[1].is_infinite());
+            }
+            buffer => panic!("{:?}", buffer),
+        }
+        assert_eq!("object_id".parse(), Ok(Aov::ObjectId));
+    }
+}
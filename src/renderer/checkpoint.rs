@@ -0,0 +1,356 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{self, File},
+    io::{BufReader, BufWriter},
+    path::Path,
+};
+
+use crate::{
+    algebra::sampler::SamplerType,
+    camera::{ray_caster::ImageParams, Camera},
+};
+
+use super::{accumulation::AccumulationBuffer, adaptive::AdaptiveSampling, TraceSettings};
+
+/// Changed when the layout of the checkpoints does, older ones can't be resumed.
+const VERSION: u32 = 2;
+
+/// What the samples of a checkpoint were traced from, all of it has to match to resume.
+/// Progressive passes only change when the image is shown, not the samples.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct RenderKey {
+    pub scene: u64,
+    pub camera: u64,
+    pub width: u32,
+    pub height: u32,
+    pub sampler: SamplerType,
+    pub seed: u32,
+    pub rr_min_depth: u32,
+    pub max_depth: Option<u32>,
+    pub adaptive: Option<AdaptiveSampling>,
+}
+
+impl RenderKey {
+    pub fn new(
+        scene_hash: u64,
+        camera: &Camera,
+        img_params: &ImageParams,
+        settings: &TraceSettings,
+    ) -> Self {
+        Self {
+            scene: scene_hash,
+            camera: hash(serde_json::to_string(camera).unwrap().as_bytes()),
+            width: img_params.width,
+            height: img_params.height,
+            sampler: settings.sampler,
+            seed: settings.seed,
+            rr_min_depth: settings.rr_min_depth,
+            max_depth: settings.max_depth,
+            adaptive: settings.adaptive,
+        }
+    }
+}
+
+/// Samples of a render at the end of a pass.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Checkpoint {
+    version: u32,
+    pub key: RenderKey,
+    /// Samples per pixel the sampler was set up for.
+    pub samples_number: u32,
+    /// Sample the next pass starts with, the pixels have all the samples before it
+    /// unless adaptive sampling stopped them earlier.
+    pub next_sample: u32,
+    pub accumulation: AccumulationBuffer,
+}
+
+impl Checkpoint {
+    pub fn new(
+        key: RenderKey,
+        samples_number: u32,
+        next_sample: u32,
+        accumulation: AccumulationBuffer,
+    ) -> Self {
+        Self {
+            version: VERSION,
+            key,
+            samples_number,
+            next_sample,
+            accumulation,
+        }
+    }
+
+    /// Writes a temporary file first, so a crash while saving keeps the previous checkpoint.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), String> {
+        let path = path.as_ref();
+        let error = |err: &dyn std::fmt::Display| {
+            format!("Could not save checkpoint {}: {}", path.display(), err)
+        };
+        let temporary = path.with_extension("tmp");
+        let file = File::create(&temporary).map_err(|err| error(&err))?;
+        bincode::serialize_into(BufWriter::new(file), self).map_err(|err| error(&err))?;
+        fs::rename(&temporary, path).map_err(|err| error(&err))
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+        let path = path.as_ref();
+        let error = |err: &dyn std::fmt::Display| {
+            format!("Could not read checkpoint {}: {}", path.display(), err)
+        };
+        let file = File::open(path).map_err(|err| error(&err))?;
+        let checkpoint: Checkpoint =
+            bincode::deserialize_from(BufReader::new(file)).map_err(|err| error(&err))?;
+        if checkpoint.version != VERSION {
+            return Err(error(&format!(
+                "unsupported version {}",
+                checkpoint.version
+            )));
+        }
+        Ok(checkpoint)
+    }
+
+    /// Whether the render of `key` with `samples_number` samples per pixel can go on
+    /// from the checkpoint.
+    pub fn check(&self, key: &RenderKey, samples_number: u32) -> Result<(), String> {
+        if self.key.scene != key.scene {
+            return Err("The scene changed since the checkpoint".into());
+        }
+        if self.key.camera != key.camera {
+            return Err("The camera changed since the checkpoint".into());
+        }
+        if (self.key.width, self.key.height) != (key.width, key.height) {
+            return Err(format!(
+                "The checkpoint is {}x{}, not {}x{}",
+                self.key.width, self.key.height, key.width, key.height
+            ));
+        }
+        if (self.key.sampler, self.key.seed) != (key.sampler, key.seed) {
+            return Err(format!(
+                "The checkpoint was sampled by {:?} with seed {}",
+                self.key.sampler, self.key.seed
+            ));
+        }
+        if (self.key.rr_min_depth, self.key.max_depth) != (key.rr_min_depth, key.max_depth) {
+            return Err(format!(
+                "The checkpoint was traced with Russian roulette from depth {} and {}",
+                self.key.rr_min_depth,
+                match self.key.max_depth {
+                    Some(max_depth) => format!("depth limit {}", max_depth),
+                    None => "no depth limit".into(),
+                }
+            ));
+        }
+        if self.key.adaptive != key.adaptive {
+            return Err(match self.key.adaptive {
+                Some(adaptive) => format!(
+                    "The checkpoint was sampled adaptively with {} samples per pass and error {}",
+                    adaptive.min_samples, adaptive.threshold
+                ),
+                None => "The checkpoint wasn't sampled adaptively".into(),
+            });
+        }
+        // the strata depend on the samples number, new samples wouldn't fit the ones taken
+        if self.key.sampler == SamplerType::Stratified && samples_number != self.samples_number {
+            return Err(format!(
+                "The stratified sampler of the checkpoint needs {} samples",
+                self.samples_number
+            ));
+        }
+        if self.next_sample >= samples_number {
+            return Err(format!(
+                "The checkpoint already has {} samples per pixel",
+                self.next_sample
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Hash of the scene description, the same whatever the formatting and order of the keys.
+/// Only the JSON is hashed, changes to the textures or environment maps it refers to by
+/// path aren't noticed.
+pub fn scene_hash(json: &str) -> Result<u64, serde_json::Error> {
+    let value: serde_json::Value = serde_json::from_str(json)?;
+    Ok(hash(value.to_string().as_bytes()))
+}
+
+/// FNV-1a, stable between builds unlike the hashers of the standard library.
+fn hash(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        algebra::Vector3d,
+        renderer::{
+            accumulation::Progressive, progress::RenderState, tests::ball_on_ground,
+            tiled::TileRenderer, Renderer,
+        },
+        world::Scene,
+    };
+    use std::{
+        env,
+        sync::{Arc, RwLock},
+        thread,
+        time::Duration,
+    };
+
+    const IMAGE: ImageParams = ImageParams {
+        width: 8,
+        height: 8,
+    };
+
+    fn settings() -> TraceSettings {
+        TraceSettings {
+            progressive: Some(Progressive {
+                pass_samples: 4,
+                target_error: None,
+            }),
+            ..Default::default()
+        }
+    }
+
+    fn renderer(scene: &Arc<RwLock<Scene>>) -> TileRenderer {
+        TileRenderer::new(scene.clone(), 2, settings())
+            .with_checkpoints(42, Duration::from_secs(3600))
+    }
+
+    fn render(renderer: &mut TileRenderer, image: &mut Vec<Vector3d>) {
+        while !renderer.render_step(image) {
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    /// The checkpoint of a render of the ball on the ground with 8 samples per pixel.
+    fn checkpoint(scene: &Arc<RwLock<Scene>>, camera: &Camera) -> Checkpoint {
+        let mut renderer = renderer(scene);
+        let mut image = vec![Vector3d::zero(); 8 * 8];
+        renderer.start_rendering(Arc::new(RwLock::new(camera.clone())), &IMAGE, 8);
+        render(&mut renderer, &mut image);
+        renderer.take_checkpoint().unwrap()
+    }
+
+    #[test]
+    fn test_scene_hash() {
+        assert_eq!(
+            scene_hash(r#"{"a": 1, "b": [2, 3]}"#).unwrap(),
+            scene_hash("{\n  \"b\": [2,3],\n  \"a\": 1\n}").unwrap()
+        );
+        assert_ne!(
+            scene_hash(r#"{"a": 1}"#).unwrap(),
+            scene_hash(r#"{"a": 2}"#).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_resume_from_checkpoint() {
+        let (scene, camera) = ball_on_ground();
+        let scene = Arc::new(RwLock::new(scene));
+
+        // 8 samples, then 8 more from the saved checkpoint, the same as 16 at once
+        let path = env::temp_dir().join("test_resume_from_checkpoint.checkpoint");
+        checkpoint(&scene, &camera).save(&path).unwrap();
+        let checkpoint = Checkpoint::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(checkpoint.next_sample, 8);
+        assert_eq!(checkpoint.accumulation.sample_counts(), vec![8; 8 * 8]);
+
+        let mut resumed = renderer(&scene);
+        let mut image = vec![Vector3d::zero(); 8 * 8];
+        resumed
+            .resume_rendering(
+                Arc::new(RwLock::new(camera.clone())),
+                &IMAGE,
+                16,
+                checkpoint,
+            )
+            .unwrap();
+        render(&mut resumed, &mut image);
+        assert_eq!(resumed.convergence().passes, 4);
+
+        let mut full = TileRenderer::new(scene.clone(), 2, settings());
+        let mut expected = vec![Vector3d::zero(); 8 * 8];
+        full.start_rendering(Arc::new(RwLock::new(camera)), &IMAGE, 16);
+        render(&mut full, &mut expected);
+        assert_eq!(image, expected);
+        assert_eq!(resumed.accumulation().sample_counts(), vec![16; 8 * 8]);
+    }
+
+    #[test]
+    fn test_checkpoint_refusals() {
+        let (scene, camera) = ball_on_ground();
+        let scene = Arc::new(RwLock::new(scene));
+        let checkpoint = checkpoint(&scene, &camera);
+        let settings = settings();
+
+        // refused when anything the samples depend on changed
+        let key = RenderKey::new(42, &camera, &IMAGE, &settings);
+        assert!(checkpoint.check(&key, 16).is_ok());
+        assert!(checkpoint.check(&key, 8).is_err());
+        let changed = RenderKey::new(7, &camera, &IMAGE, &settings);
+        assert!(checkpoint.check(&changed, 16).is_err());
+        let moved = Camera::new(
+            &Vector3d::new(0.0, 1.0, 5.0),
+            &Vector3d::new(0.0, 0.0, -1.0),
+            &Vector3d::new(0.0, 1.0, 0.0),
+            1.0,
+            60.0_f64.to_radians(),
+        );
+        let changed = RenderKey::new(42, &moved, &IMAGE, &settings);
+        assert!(checkpoint.check(&changed, 16).is_err());
+        for changed_settings in [
+            TraceSettings {
+                max_depth: Some(4),
+                ..settings
+            },
+            TraceSettings {
+                rr_min_depth: settings.rr_min_depth + 1,
+                ..settings
+            },
+            TraceSettings {
+                adaptive: Some(Default::default()),
+                ..settings
+            },
+        ] {
+            let changed = RenderKey::new(42, &camera, &IMAGE, &changed_settings);
+            assert!(checkpoint.check(&changed, 16).is_err());
+        }
+        assert!(renderer(&scene)
+            .resume_rendering(Arc::new(RwLock::new(moved)), &IMAGE, 16, checkpoint)
+            .is_err());
+    }
+
+    #[test]
+    fn test_time_budget_checkpoint() {
+        let (scene, camera) = ball_on_ground();
+        let scene = Arc::new(RwLock::new(scene));
+        let mut renderer = renderer(&scene);
+        let mut image = vec![Vector3d::zero(); 8 * 8];
+
+        // out of time before a pass is done, there is nothing to keep
+        renderer.set_time_budget(Some(Duration::ZERO));
+        renderer.start_rendering(Arc::new(RwLock::new(camera.clone())), &IMAGE, 1 << 20);
+        render(&mut renderer, &mut image);
+        assert_eq!(renderer.progress().state, RenderState::OutOfTime);
+        assert!(renderer.take_checkpoint().is_none());
+
+        // given during the second pass, the render stops after it and the checkpoint keeps both
+        renderer.set_time_budget(None);
+        renderer.start_rendering(Arc::new(RwLock::new(camera)), &IMAGE, 1 << 20);
+        while renderer.convergence().passes == 0 {
+            assert!(!renderer.render_step(&mut image));
+            thread::sleep(Duration::from_millis(1));
+        }
+        renderer.set_time_budget(Some(Duration::ZERO));
+        render(&mut renderer, &mut image);
+        assert_eq!(renderer.progress().state, RenderState::OutOfTime);
+        let checkpoint = renderer.take_checkpoint().unwrap();
+        assert!(renderer.take_checkpoint().is_none());
+        assert_eq!(checkpoint.next_sample, 8);
+        assert_eq!(checkpoint.accumulation.sample_counts(), vec![8; 8 * 8]);
+    }
+}
@@ -1,11 +1,22 @@
 use crate::{
-    algebra::Vector3d,
+    algebra::{
+        sampler::{Sampler, SamplerType, CAMERA_DIMENSIONS},
+        sampling::power_heuristic,
+        Vector3d,
+    },
     camera::{
         ray_caster::{ImageParams, MultisamplerRayCaster},
         Camera,
     },
-    world::{ray::Ray, Scene},
+    world::{
+        ray::{Ray, RayHit},
+        LightSample, Scene,
+    },
 };
+use accumulation::{Convergence, Progressive};
+use adaptive::{AdaptiveSampling, PixelStats};
+use aov::{trace_aovs, PixelAovs};
+use progress::{Progress, ProgressCallback};
 use itertools::Itertools;
 use std::{
     sync::{
@@ -13,35 +24,172 @@ use std::{
         Arc, Condvar, Mutex, RwLock,
     },
     thread::{spawn, JoinHandle},
+    time::Duration,
 };
 
+pub mod accumulation;
+pub mod adaptive;
+pub mod aov;
+pub mod checkpoint;
+pub mod progress;
 pub mod step_by_step;
-pub mod thread_pool;
-pub mod thread_pool_new;
-pub mod threaded;
-
-pub fn ray_color(world: &Scene, ray: &Ray, depth: u32) -> Vector3d {
-    match world.closest_hit(&ray, 0.001, f64::INFINITY) {
-        Some(ray_hit) => {
-            if depth == 0 {
-                Vector3d::new(0.0, 0.0, 0.0)
-            } else {
-                if let Some(scatter) = ray_hit.material.scatter(ray, &ray_hit) {
-                    scatter
-                        .attenuation
-                        .product(&ray_color(world, &scatter.ray, depth - 1))
-                } else {
-                    ray_hit
-                        .material
-                        .emitted(ray_hit.u, ray_hit.v, &ray_hit.point)
-                }
+pub mod tiled;
+
+/// Limits on path length used by `ray_color` and the sampler of the pixel samples.
+#[derive(Debug, Clone, Copy)]
+pub struct TraceSettings {
+    /// Number of bounces after which paths are terminated by Russian roulette.
+    pub rr_min_depth: u32,
+    /// Hard limit on the number of bounces, `None` to rely on Russian roulette only.
+    pub max_depth: Option<u32>,
+    pub sampler: SamplerType,
+    /// Renders with the same seed are identical, whatever the number of threads.
+    pub seed: u32,
+    /// Also trace the first hits of the samples for the output variables.
+    pub aovs: bool,
+    /// Spend the samples number of the render only where it is needed, `None` to give
+    /// it to every pixel.
+    pub adaptive: Option<AdaptiveSampling>,
+    /// Render in passes refining the whole image, `None` for a single pass unless
+    /// sampling adaptively.
+    pub progressive: Option<Progressive>,
+}
+
+impl Default for TraceSettings {
+    fn default() -> Self {
+        Self {
+            rr_min_depth: 3,
+            max_depth: None,
+            sampler: SamplerType::default(),
+            seed: 0,
+            aovs: false,
+            adaptive: None,
+            progressive: None,
+        }
+    }
+}
+
+/// Highest probability for a path to survive Russian roulette, so every path ends eventually.
+const MAX_SURVIVAL: f64 = 0.95;
+
+/// Radiance arriving along `ray`, the random numbers of the path are taken from `sampler`.
+pub fn ray_color(
+    world: &Scene,
+    ray: &Ray,
+    settings: &TraceSettings,
+    sampler: &mut dyn Sampler,
+) -> Vector3d {
+    let mut radiance = Vector3d::zero();
+    let mut throughput = Vector3d::new(1.0, 1.0, 1.0);
+    let mut ray = ray.clone();
+    // Density with which the previous non-specular bounce sampled `ray`. Emitters hit by
+    // such rays were also sampled directly, so their light is MIS weighted.
+    let mut bsdf_pdf: Option<f64> = None;
+    let mut depth = 0;
+
+    loop {
+        let ray_hit = match world.closest_hit(&ray, 0.001, f64::INFINITY) {
+            Some(ray_hit) => ray_hit,
+            None => {
+                let weight = match bsdf_pdf {
+                    Some(pdf) => power_heuristic(pdf, world.background_pdf(&ray)),
+                    None => 1.0,
+                };
+                radiance += throughput.product(&world.background(&ray)) * weight;
+                break;
             }
-            // 0.5 * (ray_hit.normal.normalize() + Vector3d::new(1.0, 1.0, 1.0))
+        };
+
+        let emitted = ray_hit
+            .material
+            .emitted(ray_hit.u, ray_hit.v, &ray_hit.point);
+        let weight = match bsdf_pdf {
+            Some(pdf) if !emitted.is_zero() => power_heuristic(pdf, world.light_pdf(&ray, &ray_hit)),
+            _ => 1.0,
+        };
+        radiance += throughput.product(&emitted) * weight;
+
+        let scatter = match ray_hit.material.scatter(&ray, &ray_hit, sampler) {
+            Some(scatter) => scatter,
+            None => break,
+        };
+
+        if scatter.is_specular {
+            bsdf_pdf = None;
+        } else {
+            let direct = [
+                world.sample_light(&ray_hit.point, sampler),
+                world.sample_background(sampler),
+            ]
+            .into_iter()
+            .flatten()
+            .map(|light| direct_light(world, &ray, &ray_hit, &light))
+            .sum::<Vector3d>()
+                + delta_light(world, &ray, &ray_hit);
+            radiance += throughput.product(&direct);
+            bsdf_pdf = Some(scatter.pdf);
         }
-        None => {
-            world.background(&ray)
+        throughput = throughput.product(&scatter.attenuation);
+
+        depth += 1;
+        if settings.max_depth.is_some_and(|max_depth| depth >= max_depth) {
+            break;
+        }
+        if depth >= settings.rr_min_depth {
+            let survival = throughput.max_component().min(MAX_SURVIVAL);
+            if sampler.get_1d() >= survival {
+                break;
+            }
+            throughput = throughput / survival;
         }
+
+        ray = scatter.ray;
+    }
+
+    radiance
+}
+
+/// Light reflected along `ray` from a sampled point on an emitter or the background.
+fn direct_light(world: &Scene, ray: &Ray, ray_hit: &RayHit, light: &LightSample) -> Vector3d {
+    let bsdf = ray_hit.material.eval(ray, ray_hit, &light.direction);
+    if bsdf.is_zero() {
+        return Vector3d::new(0.0, 0.0, 0.0);
     }
+
+    let shadow_ray = Ray::with_time(ray_hit.point, light.direction, ray.time);
+    let emitted = match world.closest_hit(&shadow_ray, 0.001, light.distance * (1.0 + 1e-4)) {
+        None if light.distance.is_infinite() => world.background(&shadow_ray),
+        Some(hit) if hit.distance > light.distance * (1.0 - 1e-4) => {
+            hit.material.emitted(hit.u, hit.v, &hit.point)
+        }
+        _ => return Vector3d::new(0.0, 0.0, 0.0),
+    };
+
+    let weight = power_heuristic(
+        light.pdf,
+        ray_hit.material.pdf(ray, ray_hit, &light.direction),
+    );
+    bsdf.product(&emitted) * (weight / light.pdf)
+}
+
+/// Light reflected along `ray` from all point, spot and directional lights.
+fn delta_light(world: &Scene, ray: &Ray, ray_hit: &RayHit) -> Vector3d {
+    world
+        .delta_lights()
+        .iter()
+        .filter_map(|light| light.sample(&ray_hit.point))
+        .filter_map(|light| {
+            let bsdf = ray_hit.material.eval(ray, ray_hit, &light.direction);
+            if bsdf.is_zero() {
+                return None;
+            }
+            let shadow_ray = Ray::with_time(ray_hit.point, light.direction, ray.time);
+            match world.closest_hit(&shadow_ray, 0.001, light.distance * (1.0 - 1e-4)) {
+                Some(_) => None,
+                None => Some(bsdf.product(&light.irradiance)),
+            }
+        })
+        .sum()
 }
 
 pub trait Renderer {
@@ -52,36 +200,84 @@ pub trait Renderer {
         samples_number: u32,
     );
     fn render_step(&mut self, buffer: &mut Vec<Vector3d>) -> bool;
+    /// Cancels the render, the workers stop after the pixel they are tracing.
     fn stop_rendering(&mut self);
+
+    /// Wall clock time after which the renders stop keeping the samples taken so far,
+    /// `None` for no limit. Applies from the next `start_rendering` call, or from the next
+    /// pass of a running render, counted from the call.
+    fn set_time_budget(&mut self, budget: Option<Duration>);
+    fn on_progress(&mut self, callback: ProgressCallback);
+    fn progress(&self) -> Progress;
+
+    /// First hit data of the pixels, empty unless enabled in the trace settings.
+    fn aovs(&self) -> &[PixelAovs] {
+        &[]
+    }
+
+    /// Samples taken so far, their estimated error and the time spent.
+    fn convergence(&self) -> Convergence;
+}
+
+/// Sample indices traced by one run of the workers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PassSamples {
+    pub first: u32,
+    pub count: u32,
+    /// Samples per pixel the sampler is set up for.
+    pub per_pixel: u32,
+}
+
+impl PassSamples {
+    pub fn all(samples_number: u32) -> Self {
+        Self {
+            first: 0,
+            count: samples_number,
+            per_pixel: samples_number,
+        }
+    }
 }
 
 type InputData = (u32, Vec<Ray>);
 type InputDataVec = Vec<InputData>;
-type InputDataVecOption = Option<InputDataVec>;
+type InputDataVecOption = Option<(PassSamples, InputDataVec)>;
 
-type OutputData = (u32, Vector3d);
+type OutputData = (u32, PixelStats, Option<PixelAovs>);
 type OutputDataVec = Vec<OutputData>;
 type OutputDataVecOption = Option<OutputDataVec>;
 
+/// Sends the rays of `pixels`, or of the whole image if `None`, to the workers.
 fn new_dispatcher_thread(
     camera: Arc<RwLock<Camera>>,
-    width: u32,
-    height: u32,
-    samples_number: u32,
+    img_params: ImageParams,
+    samples: PassSamples,
+    pixels: Option<Vec<u32>>,
+    settings: TraceSettings,
     input_sender: Arc<Mutex<Sender<InputDataVecOption>>>,
     threads_num: u32,
 ) -> JoinHandle<()> {
-    let chunk_size = ((width * height) / threads_num / 8) as usize;
-    let img_params = ImageParams { width, height };
+    let width = img_params.width;
+    let pixels = pixels.unwrap_or_else(|| (0..width * img_params.height).collect());
+    let chunk_size = (pixels.len() / threads_num as usize / 8).max(1);
 
     spawn(move || {
-        let rays =
-            MultisamplerRayCaster::new(&*camera.read().unwrap(), &img_params, samples_number);
-        for chunk in &rays.chunks(chunk_size) {
+        let mut rays =
+            MultisamplerRayCaster::new(&camera.read().unwrap(), &img_params, samples.per_pixel)
+                .with_sampler(settings.sampler, settings.seed);
+        let range = samples.first..samples.first + samples.count;
+        for chunk in pixels.chunks(chunk_size) {
             let chunk_vec = chunk
-                .map(|(x, y, rays)| (x + y * width, rays))
+                .iter()
+                .map(|&index| {
+                    let rays = rays.get_pixel_samples(index % width, index / width, range.clone());
+                    (index, rays)
+                })
                 .collect_vec();
-            input_sender.lock().unwrap().send(Some(chunk_vec)).unwrap();
+            input_sender
+                .lock()
+                .unwrap()
+                .send(Some((samples, chunk_vec)))
+                .unwrap();
         }
         for _ in 0..threads_num {
             input_sender.lock().unwrap().send(None).unwrap();
@@ -95,7 +291,7 @@ fn new_worker_thread(
     output_sender: Arc<Mutex<Sender<OutputDataVecOption>>>,
     world: Arc<RwLock<Scene>>,
     parking: Arc<(Mutex<bool>, Condvar)>,
-    depth: u32,
+    settings: TraceSettings,
 ) -> JoinHandle<()> {
     spawn(move || {
         let (lock, cvar) = &*parking;
@@ -109,14 +305,14 @@ fn new_worker_thread(
                 }
             };
             match input {
-                Some(v) => {
-                    let result = trace_pixel_samples_group(v, world, depth);
+                Some((samples, v)) => {
+                    let result = trace_pixel_samples_group(v, samples, world, &settings);
                     output_sender.lock().unwrap().send(Some(result)).unwrap();
                 }
                 None => {
-                    output_sender.lock().unwrap().send(None).unwrap();
-
+                    // taken before reporting, so the next start can't notify before the wait
                     let running = lock.lock().unwrap();
+                    output_sender.lock().unwrap().send(None).unwrap();
                     cvar.wait(running).unwrap();
                 }
             }
@@ -124,7 +320,12 @@ fn new_worker_thread(
     })
 }
 
-pub fn trace_pixel_samples_group(input: InputDataVec, world: &Scene, depth: u32) -> OutputDataVec {
+pub fn trace_pixel_samples_group(
+    input: InputDataVec,
+    samples: PassSamples,
+    world: &Scene,
+    settings: &TraceSettings,
+) -> OutputDataVec {
     // let mut result = Vec::with_capacity(input.len());
     // for (index, rays) in input {
     //     let ln = rays.len() as f64;
@@ -139,8 +340,13 @@ pub fn trace_pixel_samples_group(input: InputDataVec, world: &Scene, depth: u32)
 
     input
         .iter()
-        .map(|input_data| {
-            trace_pixel_samples(input_data, world, depth)
+        .map(|(index, rays)| {
+            (
+                *index,
+                trace_samples(*index, rays, samples, world, settings),
+                // only the first pass sees the first sample, later ones would skew the average
+                (settings.aovs && samples.first == 0).then(|| trace_aovs(world, rays)),
+            )
             // let samples_colors = rays.iter().map(|ray| ray_color(world, ray, depth));
             // let ln = samples_colors.len() as f64;
             // (*index, samples_colors.sum::<Vector3d>() / ln)
@@ -148,8 +354,236 @@ pub fn trace_pixel_samples_group(input: InputDataVec, world: &Scene, depth: u32)
         .collect_vec()
 }
 
-pub fn trace_pixel_samples(input: &InputData, world: &Scene, depth: u32) -> OutputData {
-    let samples_colors = input.1.iter().map(|ray| ray_color(world, ray, depth));
-    let ln = samples_colors.len() as f64;
-    (input.0, samples_colors.sum::<Vector3d>() / ln)
+pub fn trace_pixel_samples(
+    input: &InputData,
+    world: &Scene,
+    settings: &TraceSettings,
+) -> OutputData {
+    let samples = PassSamples::all(input.1.len() as u32);
+    (
+        input.0,
+        trace_samples(input.0, &input.1, samples, world, settings),
+        settings.aovs.then(|| trace_aovs(world, &input.1)),
+    )
+}
+
+/// Mean radiance of the camera samples of `pixel`, `rays[i]` being its sample `i`.
+pub fn trace_rays(pixel: u32, rays: &[Ray], world: &Scene, settings: &TraceSettings) -> Vector3d {
+    let samples = PassSamples::all(rays.len() as u32);
+    trace_samples(pixel, rays, samples, world, settings).mean
+}
+
+/// Statistics of the camera samples of `pixel`, `rays[i]` being its sample `samples.first + i`.
+pub fn trace_samples(
+    pixel: u32,
+    rays: &[Ray],
+    samples: PassSamples,
+    world: &Scene,
+    settings: &TraceSettings,
+) -> PixelStats {
+    let mut sampler = settings.sampler.sampler(samples.per_pixel, settings.seed);
+    let mut stats = PixelStats::default();
+    for (index, ray) in rays.iter().enumerate() {
+        sampler.start_pixel_sample(pixel, samples.first + index as u32, CAMERA_DIMENSIONS);
+        stats.add_sample(&ray_color(world, ray, settings, sampler.as_mut()));
+    }
+    stats
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        algebra::transform::InversableTransform,
+        world::{
+            background::{Background, EnvironmentMap},
+            light::PointLight,
+            material::{DiffuseLight, Lambertian, MaterialPtr},
+            shapes::{Shape, Sphere},
+            texture::SolidColor,
+        },
+    };
+    use itertools::Itertools;
+    use std::{collections::HashMap, f64::consts::PI};
+
+    fn sphere(radius: f64, material: MaterialPtr) -> Box<dyn Shape> {
+        Box::new(Sphere::new(
+            "sphere".into(),
+            InversableTransform::new(
+                Vector3d::zero(),
+                Vector3d::zero(),
+                Vector3d::new(radius, radius, radius),
+            ),
+            material,
+            false,
+        ))
+    }
+
+    /// A diffuse ball on a big ground sphere under a white sky, and a camera looking at it.
+    pub(super) fn ball_on_ground() -> (Scene, Camera) {
+        let material = diffuse(0.5);
+        let sphere = |name: &str, center: Vector3d, radius: f64| -> Box<dyn Shape> {
+            Box::new(Sphere::new(
+                name.into(),
+                InversableTransform::new(
+                    center,
+                    Vector3d::zero(),
+                    Vector3d::new(radius, radius, radius),
+                ),
+                material.clone(),
+                false,
+            ))
+        };
+        let camera = Camera::new(
+            &Vector3d::new(0.0, 0.0, 5.0),
+            &Vector3d::new(0.0, 0.0, -1.0),
+            &Vector3d::new(0.0, 1.0, 0.0),
+            1.0,
+            60.0_f64.to_radians(),
+        );
+        let scene = Scene::new(
+            vec![
+                sphere("ball", Vector3d::zero(), 1.0),
+                sphere("ground", Vector3d::new(0.0, -101.0, 0.0), 100.0),
+            ],
+            HashMap::new(),
+            Vec::new(),
+            camera.clone(),
+            Background::Solid(Vector3d::new(1.0, 1.0, 1.0)),
+        );
+        (scene, camera)
+    }
+
+    fn diffuse(albedo: f64) -> MaterialPtr {
+        Arc::new(Box::new(Lambertian {
+            albedo: Box::new(SolidColor {
+                color: Vector3d::new(albedo, albedo, albedo),
+            }),
+        }))
+    }
+
+    fn camera() -> Camera {
+        Camera::new(
+            &Vector3d::new(0.0, 0.0, 5.0),
+            &Vector3d::new(0.0, 0.0, -1.0),
+            &Vector3d::new(0.0, 1.0, 0.0),
+            1.0,
+            90.0_f64.to_radians(),
+        )
+    }
+
+    fn assert_furnace(scene: &Scene, albedo: f64) {
+        let samples = 20000;
+        for target in [Vector3d::new(0.0, 0.0, 0.0), Vector3d::new(0.9, 0.0, 0.0)] {
+            let ray = Ray::new(
+                Vector3d::new(0.0, 0.0, 5.0),
+                target - Vector3d::new(0.0, 0.0, 5.0),
+            );
+            let mut sampler = SamplerType::Independent.sampler(samples, 0);
+            let sum: Vector3d = (0..samples)
+                .map(|index| {
+                    sampler.start_pixel_sample(0, index, CAMERA_DIMENSIONS);
+                    ray_color(scene, &ray, &TraceSettings::default(), sampler.as_mut())
+                })
+                .sum();
+            let mean = sum / samples as f64;
+            for c in [mean.x, mean.y, mean.z] {
+                assert!((c - albedo).abs() < 0.01, "{} != {}", mean, albedo);
+            }
+        }
+    }
+
+    /// A diffuse sphere inside a uniformly glowing one must reflect exactly its albedo.
+    #[test]
+    fn test_white_furnace() {
+        let albedo = 0.7;
+        let light: MaterialPtr = Arc::new(Box::new(DiffuseLight {
+            emit: Box::new(SolidColor {
+                color: Vector3d::new(1.0, 1.0, 1.0),
+            }),
+        }));
+        let scene = Scene::new(
+            vec![sphere(1.0, diffuse(albedo)), sphere(10.0, light)],
+            HashMap::new(),
+            Vec::new(),
+            camera(),
+            Background::Solid(Vector3d::zero()),
+        );
+        assert_eq!(scene.lights().len(), 1);
+        assert_furnace(&scene, albedo);
+    }
+
+    /// Same for a uniform environment map, which is sampled as a light.
+    #[test]
+    fn test_environment_furnace() {
+        let albedo = 0.7;
+        let pixels = vec![Vector3d::new(1.0, 1.0, 1.0); 16 * 8];
+        let scene = Scene::new(
+            vec![sphere(1.0, diffuse(albedo))],
+            HashMap::new(),
+            Vec::new(),
+            camera(),
+            Background::Environment(EnvironmentMap::from_pixels("", 0.0, 1.0, 16, 8, pixels)),
+        );
+        assert_furnace(&scene, albedo);
+    }
+
+    #[test]
+    fn test_point_light() {
+        let light = PointLight {
+            position: Vector3d::new(0.0, 0.0, 3.0),
+            intensity: Vector3d::new(8.0 * PI, 8.0 * PI, 8.0 * PI),
+        };
+        let scene = Scene::new(
+            vec![sphere(1.0, diffuse(0.5))],
+            HashMap::new(),
+            vec![Box::new(light)],
+            camera(),
+            Background::Solid(Vector3d::zero()),
+        );
+
+        let ray = Ray::new(Vector3d::new(0.0, 0.0, 5.0), Vector3d::new(0.0, 0.0, -1.0));
+        let mut sampler = SamplerType::default().sampler(1, 0);
+        let color = ray_color(&scene, &ray, &TraceSettings::default(), sampler.as_mut());
+        assert!((color - Vector3d::new(1.0, 1.0, 1.0)).length() < 1e-9, "{}", color);
+    }
+
+    /// Renders with the same seed match bit for bit whatever the number of threads.
+    #[test]
+    fn test_seeded_render() {
+        let render = |threads, seed| {
+            let pixels = (0..16 * 8)
+                .map(|i| Vector3d::new(1.0, (i % 16) as f64 / 16.0, 0.5))
+                .collect_vec();
+            let scene = Scene::new(
+                vec![sphere(1.0, diffuse(0.5))],
+                HashMap::new(),
+                Vec::new(),
+                camera(),
+                Background::Environment(EnvironmentMap::from_pixels("", 0.0, 1.0, 16, 8, pixels)),
+            );
+            let settings = TraceSettings {
+                sampler: SamplerType::Sobol,
+                seed,
+                ..Default::default()
+            };
+            let mut renderer =
+                tiled::TileRenderer::new(Arc::new(RwLock::new(scene)), threads, settings)
+                    .with_tile_size(5);
+
+            let img_params = ImageParams {
+                width: 16,
+                height: 12,
+            };
+            let mut buffer = vec![Vector3d::zero(); 16 * 12];
+            renderer.start_rendering(Arc::new(RwLock::new(camera())), &img_params, 4);
+            while !renderer.render_step(&mut buffer) {
+                std::thread::yield_now();
+            }
+            buffer.iter().map(|c| [c.x, c.y, c.z]).collect_vec()
+        };
+
+        assert_eq!(render(1, 7), render(3, 7));
+        assert_ne!(render(1, 7), render(1, 8));
+    }
 }
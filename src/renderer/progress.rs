@@ -0,0 +1,162 @@
+use std::time::Duration;
+
+use super::tiled::Tile;
+
+/// What a render is doing, or why it stopped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RenderState {
+    /// Nothing was started yet.
+    Idle,
+    Rendering,
+    /// Every pixel got the samples number of the render, or all the samples adaptive
+    /// sampling asked for.
+    Completed,
+    /// The estimated error of progressive rendering got below the target.
+    Converged,
+    /// The time budget ran out, the image has the samples taken until then.
+    OutOfTime,
+    Cancelled,
+}
+
+impl RenderState {
+    pub fn is_finished(&self) -> bool {
+        !matches!(self, RenderState::Idle | RenderState::Rendering)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Progress {
+    pub state: RenderState,
+    pub passes: u32,
+    /// Tiles of the current pass written to the image and their number.
+    pub tiles_done: u32,
+    pub tiles: u32,
+    /// Samples per pixel reached by the finished passes.
+    pub samples: u32,
+    pub elapsed: Duration,
+    /// Estimated time left, `None` until some samples are traced.
+    pub eta: Option<Duration>,
+}
+
+impl Progress {
+    /// Part of the render done, judging by the time spent and left.
+    pub fn fraction(&self) -> f64 {
+        if self.state.is_finished() {
+            return 1.0;
+        }
+        match self.eta {
+            Some(eta) if !(self.elapsed + eta).is_zero() => {
+                self.elapsed.as_secs_f64() / (self.elapsed + eta).as_secs_f64()
+            }
+            _ => 0.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RenderEvent {
+    /// Samples of the tile were written to the image.
+    TileDone(Tile),
+    /// Every pixel traced by the pass got its samples.
+    PassDone,
+    /// The render stopped, see `Progress::state` for the reason.
+    Finished,
+}
+
+/// Called by `Renderer::render_step` for each event since its previous call.
+pub type ProgressCallback = Box<dyn FnMut(RenderEvent, &Progress) + Send>;
+
+/// Time left for the `remaining` work if it goes as fast as the `done` one did in `elapsed`.
+pub fn estimate_eta(done: f64, remaining: f64, elapsed: Duration) -> Option<Duration> {
+    if done <= 0.0 || !remaining.is_finite() {
+        return None;
+    }
+    Some(elapsed.mul_f64(remaining.max(0.0) / done))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        algebra::Vector3d,
+        camera::ray_caster::ImageParams,
+        renderer::{
+            accumulation::Progressive, tests::ball_on_ground, tiled::TileRenderer, Renderer,
+            TraceSettings,
+        },
+    };
+    use std::{
+        sync::{Arc, Mutex, RwLock},
+        thread,
+        time::Instant,
+    };
+
+    #[test]
+    fn test_time_budget_and_cancellation() {
+        assert_eq!(
+            estimate_eta(10.0, 30.0, Duration::from_secs(2)),
+            Some(Duration::from_secs(6))
+        );
+        assert_eq!(estimate_eta(0.0, 30.0, Duration::from_secs(2)), None);
+
+        let (scene, camera) = ball_on_ground();
+        let settings = TraceSettings {
+            progressive: Some(Progressive::default()),
+            ..Default::default()
+        };
+        let img_params = ImageParams {
+            width: 16,
+            height: 16,
+        };
+        let scene = Arc::new(RwLock::new(scene));
+        let camera = Arc::new(RwLock::new(camera));
+        let mut renderer = TileRenderer::new(scene.clone(), 2, settings).with_tile_size(8);
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let recorded = events.clone();
+        renderer.on_progress(Box::new(move |event, progress| {
+            recorded.lock().unwrap().push((event, *progress));
+        }));
+
+        // far too many samples for the budget, the image keeps the passes done in time
+        renderer.set_time_budget(Some(Duration::from_millis(200)));
+        let mut image = vec![Vector3d::zero(); 16 * 16];
+        renderer.start_rendering(camera.clone(), &img_params, 1 << 20);
+        while !renderer.render_step(&mut image) {
+            thread::sleep(Duration::from_millis(1));
+        }
+        let progress = renderer.progress();
+        assert_eq!(progress.state, RenderState::OutOfTime);
+        assert!(progress.elapsed < Duration::from_secs(2));
+        assert!(progress.passes > 0);
+        assert_eq!(image, renderer.accumulation().mean());
+
+        let events = std::mem::take(&mut *events.lock().unwrap());
+        let tiles = events
+            .iter()
+            .filter(|(event, _)| matches!(event, RenderEvent::TileDone(_)))
+            .count();
+        let passes = events
+            .iter()
+            .filter(|(event, _)| *event == RenderEvent::PassDone)
+            .count();
+        assert!(tiles >= 4 * passes);
+        assert_eq!(passes as u32, progress.passes);
+        let (last, last_progress) = events.last().unwrap();
+        assert_eq!(*last, RenderEvent::Finished);
+        assert_eq!(last_progress.state, RenderState::OutOfTime);
+        assert!(events[..events.len() - 1]
+            .iter()
+            .all(|(event, progress)| *event != RenderEvent::Finished
+                && progress.state == RenderState::Rendering));
+
+        // a single pass, cancelling doesn't wait for the pixels to get their samples
+        let mut renderer = TileRenderer::new(scene, 2, TraceSettings::default());
+        renderer.start_rendering(camera, &img_params, 1 << 20);
+        thread::sleep(Duration::from_millis(50));
+        let cancelled = Instant::now();
+        renderer.stop_rendering();
+        assert!(cancelled.elapsed() < Duration::from_millis(500));
+        assert_eq!(renderer.progress().state, RenderState::Cancelled);
+        assert!(renderer.render_step(&mut image));
+    }
+}
@@ -4,6 +4,7 @@ use std::{
         Arc, Condvar, Mutex, RwLock,
     },
     thread::JoinHandle,
+    time::Duration,
 };
 
 use crate::{algebra::Vector3d, camera::ray_caster::ImageParams};
@@ -12,12 +13,17 @@ use crate::world::Scene;
 use itertools::Itertools;
 
 use super::{
-    new_dispatcher_thread, new_worker_thread, InputDataVecOption, OutputDataVecOption, Renderer,
+    accumulation::{AccumulationBuffer, Convergence},
+    adaptive::PixelStats,
+    aov::PixelAovs,
+    new_dispatcher_thread, new_worker_thread,
+    progress::{Progress, ProgressCallback, RenderState},
+    InputDataVecOption, OutputDataVecOption, PassSamples, Renderer, TraceSettings,
 };
 
 pub struct ThreadPoolRenderer {
     thread_number: u32,
-    depth: u32,
+    settings: TraceSettings,
     worker_threads: Option<Vec<JoinHandle<()>>>,
 
     input_sender: Arc<Mutex<Sender<InputDataVecOption>>>,
@@ -31,16 +37,24 @@ pub struct ThreadPoolRenderer {
     world: Arc<RwLock<Scene>>,
     is_started: bool,
     num_finished: u32,
+    rendered_pixels: u32,
+    aovs: Vec<PixelAovs>,
+
+    camera: Option<Arc<RwLock<Camera>>>,
+    img_params: ImageParams,
+    samples_number: u32,
+    pass: PassSamples,
+    stats: Vec<PixelStats>,
 }
 
 impl ThreadPoolRenderer {
-    pub fn new(scene: Arc<RwLock<Scene>>, thread_number: u32, depth: u32) -> ThreadPoolRenderer {
+    pub fn new(scene: Arc<RwLock<Scene>>, thread_number: u32, settings: TraceSettings) -> ThreadPoolRenderer {
         let (input_sender, input_receiver) = channel();
         let (output_sender, output_receiver) = channel();
         
         let mut result = ThreadPoolRenderer {
             thread_number,
-            depth,
+            settings,
             worker_threads: None,
             input_sender: Arc::new(Mutex::new(input_sender)),
             input_receiver: Arc::new(Mutex::new(input_receiver)),
@@ -50,6 +64,16 @@ impl ThreadPoolRenderer {
             world: scene,
             is_started: false,
             num_finished: 0,
+            rendered_pixels: 0,
+            aovs: Vec::new(),
+            camera: None,
+            img_params: ImageParams {
+                width: 0,
+                height: 0,
+            },
+            samples_number: 0,
+            pass: PassSamples::all(0),
+            stats: Vec::new(),
         };
 
         let threads = (0..thread_number)
@@ -60,7 +84,7 @@ impl ThreadPoolRenderer {
                     result.output_sender.clone(),
                     result.world.clone(),
                     result.parking.clone(),
-                    result.depth,
+                    result.settings,
                 )
             })
             .collect_vec();
@@ -69,23 +93,53 @@ impl ThreadPoolRenderer {
 
         result
     }
-}
 
-impl Renderer for ThreadPoolRenderer {
-    fn stop_rendering(&mut self) {
-        self.is_started = false;
+    /// Number of pixels finished since the last `start_rendering` call.
+    pub fn rendered_pixels(&self) -> u32 {
+        self.rendered_pixels
     }
 
-    fn start_rendering(&mut self, camera: Arc<RwLock<Camera>>, img_params: &ImageParams, samples_number: u32) {
-        let width = img_params.width;
-        let height = img_params.height;
+    /// Samples traced for every pixel of the last render and their variance.
+    pub fn pixel_stats(&self) -> &[PixelStats] {
+        &self.stats
+    }
+
+    fn is_converged(&self, stats: &PixelStats) -> bool {
+        match self.settings.adaptive {
+            Some(adaptive) => !adaptive.needs_samples(stats, self.samples_number),
+            None => true,
+        }
+    }
+
+    /// Samples of the next adaptive pass and the pixels needing them, `None` when done.
+    fn next_pass(&self) -> Option<(PassSamples, Vec<u32>)> {
+        let adaptive = self.settings.adaptive?;
+        let pixels = (0..self.stats.len() as u32)
+            .filter(|&index| !self.is_converged(&self.stats[index as usize]))
+            .collect_vec();
+        if pixels.is_empty() {
+            return None;
+        }
+
+        let first = self.pass.first + self.pass.count;
+        let samples = PassSamples {
+            first,
+            count: adaptive.min_samples.min(self.samples_number - first),
+            per_pixel: self.samples_number,
+        };
+        Some((samples, pixels))
+    }
+
+    fn start_pass(&mut self, samples: PassSamples, pixels: Option<Vec<u32>>) {
         self.num_finished = 0;
+        self.pass = samples;
 
         new_dispatcher_thread(
-            camera,
-            width,
-            height,
-            samples_number,
+            self.camera.clone().unwrap(),
+            self.img_params.clone(),
+            samples,
+            pixels,
+            self.settings,
             self.input_sender.clone(),
             self.thread_number,
         );
@@ -97,26 +151,99 @@ impl Renderer for ThreadPoolRenderer {
             cvar.notify_all();
         }
     }
+}
+
+impl Renderer for ThreadPoolRenderer {
+    fn stop_rendering(&mut self) {
+        self.is_started = false;
+    }
+
+    fn start_rendering(&mut self, camera: Arc<RwLock<Camera>>, img_params: &ImageParams, samples_number: u32) {
+        let pixel_count = (img_params.width * img_params.height) as usize;
+        self.rendered_pixels = 0;
+        self.aovs.clear();
+        if self.settings.aovs {
+            self.aovs.resize(pixel_count, PixelAovs::default());
+        }
+        self.stats.clear();
+        self.stats.resize(pixel_count, PixelStats::default());
+        self.camera = Some(camera);
+        self.img_params = img_params.clone();
+        self.samples_number = samples_number;
+
+        let samples = match self.settings.adaptive {
+            Some(adaptive) => PassSamples {
+                first: 0,
+                count: adaptive.min_samples.min(samples_number),
+                per_pixel: samples_number,
+            },
+            None => PassSamples::all(samples_number),
+        };
+        self.start_pass(samples, None);
+    }
 
     fn render_step(&mut self, buffer: &mut Vec<Vector3d>) -> bool {
-        for msg in self.output_receiver.try_iter() {
+        for msg in self.output_receiver.try_iter().collect_vec() {
             // (pixel_color, x, y)
             let results = match msg {
                 Some(v) => v,
                 None => {
                     self.num_finished += 1;
                     if self.num_finished == self.thread_number {
-                        return true;
+                        match self.next_pass() {
+                            Some((samples, pixels)) => self.start_pass(samples, Some(pixels)),
+                            None => return true,
+                        }
                     }
                     continue;
                 }
             };
 
-            for (index, color) in results {
-                buffer[index as usize] = color;
+            for (index, stats, aovs) in results {
+                let pixel = &mut self.stats[index as usize];
+                pixel.merge(&stats);
+                buffer[index as usize] = pixel.mean;
+                if self.is_converged(&self.stats[index as usize]) {
+                    self.rendered_pixels += 1;
+                }
+                if let Some(aovs) = aovs {
+                    self.aovs[index as usize] = aovs;
+                }
             }
         }
 
         return false;
     }
+
+    fn aovs(&self) -> &[PixelAovs] {
+        &self.aovs
+    }
+
+    fn set_time_budget(&mut self, _budget: Option<Duration>) {}
+
+    fn on_progress(&mut self, _callback: ProgressCallback) {}
+
+    fn progress(&self) -> Progress {
+        Progress {
+            state: if self.is_started {
+                RenderState::Rendering
+            } else {
+                RenderState::Idle
+            },
+            passes: 0,
+            tiles_done: 0,
+            tiles: 0,
+            samples: self.pass.first,
+            elapsed: Duration::ZERO,
+            eta: None,
+        }
+    }
+
+    fn convergence(&self) -> Convergence {
+        let mut accumulation = AccumulationBuffer::new(self.img_params.width, self.img_params.height);
+        for (index, stats) in self.stats.iter().enumerate() {
+            accumulation.add(index, stats);
+        }
+        accumulation.convergence()
+    }
 }
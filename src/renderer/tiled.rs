@@ -0,0 +1,950 @@
+use std::{
+    mem,
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Condvar, Mutex, RwLock,
+    },
+    thread::{spawn, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    algebra::Vector3d,
+    camera::{
+        ray_caster::{ImageParams, MultisamplerRayCaster},
+        Camera,
+    },
+    world::Scene,
+};
+
+use super::{
+    accumulation::{AccumulationBuffer, Convergence},
+    adaptive::PixelStats,
+    aov::{trace_aovs, PixelAovs},
+    checkpoint::{Checkpoint, RenderKey},
+    progress::{estimate_eta, Progress, ProgressCallback, RenderEvent, RenderState},
+    trace_samples, PassSamples, Renderer, TraceSettings,
+};
+
+/// Rectangle of pixels traced by one worker at a time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Tile {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Tile {
+    /// Tiles of `size` pixels covering the image row by row, smaller at the right and
+    /// bottom edges.
+    pub fn split(img_params: &ImageParams, size: u32) -> Vec<Tile> {
+        let size = size.max(1);
+        (0..img_params.height)
+            .step_by(size as usize)
+            .flat_map(|y| {
+                (0..img_params.width)
+                    .step_by(size as usize)
+                    .map(move |x| Tile {
+                        x,
+                        y,
+                        width: size.min(img_params.width - x),
+                        height: size.min(img_params.height - y),
+                    })
+            })
+            .collect()
+    }
+
+    /// Indices of the pixels in an image `width` pixels wide.
+    pub fn pixels(&self, width: u32) -> impl Iterator<Item = u32> + '_ {
+        (self.y..self.y + self.height)
+            .flat_map(move |y| (self.x..self.x + self.width).map(move |x| x + y * width))
+    }
+}
+
+/// Results of the workers, every pixel is only written by the worker owning its tile.
+struct Framebuffer {
+    accumulation: AccumulationBuffer,
+    aovs: Vec<PixelAovs>,
+    /// Tiles written since the last `render_step`.
+    updated: Vec<Tile>,
+    rendered_pixels: u32,
+    /// Tiles of the current pass traced to the end.
+    tiles_done: u32,
+    /// Samples traced by the render and by the current pass.
+    traced_samples: u64,
+    pass_traced_samples: u64,
+}
+
+/// Work of one pass, the workers take the tiles in order until none is left.
+struct Pass {
+    camera: Camera,
+    img_params: ImageParams,
+    samples: PassSamples,
+    /// Pixels traced by the pass, `None` for all of them.
+    active: Option<Vec<bool>>,
+    active_pixels: u32,
+    /// The first pass of a render also traces the output variables.
+    trace_aovs: bool,
+    tiles: Vec<Tile>,
+    next_tile: AtomicUsize,
+    is_cancelled: AtomicBool,
+    deadline: Option<Instant>,
+}
+
+/// Pass given to the workers, which wait for the next one once theirs is done.
+struct WorkQueue {
+    pass: Option<Arc<Pass>>,
+    /// Incremented by every pass, so each worker takes it once.
+    generation: u64,
+    /// Workers still tracing the current pass.
+    running: u32,
+    /// Set by a worker which panicked, its thread has ended and takes no more passes.
+    has_panicked: bool,
+    is_shut_down: bool,
+}
+
+struct Workers {
+    queue: Mutex<WorkQueue>,
+    changed: Condvar,
+}
+
+impl Workers {
+    fn is_idle(&self) -> bool {
+        self.queue.lock().unwrap().running == 0
+    }
+
+    /// Returns early when a worker panicked, the others may still end the pass.
+    fn wait_idle(&self) {
+        let mut queue = self.queue.lock().unwrap();
+        while queue.running > 0 && !queue.has_panicked {
+            queue = self.changed.wait(queue).unwrap();
+        }
+    }
+}
+
+impl Pass {
+    fn is_stopped(&self) -> bool {
+        self.is_cancelled.load(Ordering::Relaxed)
+            || self
+                .deadline
+                .is_some_and(|deadline| Instant::now() >= deadline)
+    }
+}
+
+pub struct TileRenderer {
+    world: Arc<RwLock<Scene>>,
+    thread_number: u32,
+    settings: TraceSettings,
+    tile_size: u32,
+    time_budget: Option<Duration>,
+    callback: Option<ProgressCallback>,
+    /// Hash of the scene stored in the checkpoints, see `with_checkpoints`.
+    scene_hash: u64,
+    checkpoint_interval: Option<Duration>,
+
+    framebuffer: Arc<Mutex<Framebuffer>>,
+    /// The running pass, or the last one when the render is finished.
+    pass: Option<Arc<Pass>>,
+    workers: Arc<Workers>,
+    /// Started with the first pass and kept until the renderer is dropped.
+    threads: Vec<JoinHandle<()>>,
+    state: RenderState,
+    samples_number: u32,
+    /// Relative RMSE after the last finished pass.
+    relative_rmse: f64,
+    /// Copied from the framebuffer once the first pass is done.
+    aovs: Vec<PixelAovs>,
+    /// When this run of the render started, earlier ones don't count for its speed.
+    started: Instant,
+    /// When the running render stops, from the time budget.
+    deadline: Option<Instant>,
+    /// Samples after the last complete pass, kept while making checkpoints.
+    checkpoint: Option<Checkpoint>,
+    /// Whether `take_checkpoint` gives out `checkpoint`.
+    is_checkpoint_due: bool,
+    checkpointed: Instant,
+}
+
+impl TileRenderer {
+    pub fn new(scene: Arc<RwLock<Scene>>, thread_number: u32, settings: TraceSettings) -> Self {
+        Self {
+            world: scene,
+            thread_number: thread_number.max(1),
+            settings,
+            tile_size: 16,
+            time_budget: None,
+            callback: None,
+            scene_hash: 0,
+            checkpoint_interval: None,
+            framebuffer: Arc::new(Mutex::new(Framebuffer {
+                accumulation: AccumulationBuffer::new(0, 0),
+                aovs: Vec::new(),
+                updated: Vec::new(),
+                rendered_pixels: 0,
+                tiles_done: 0,
+                traced_samples: 0,
+                pass_traced_samples: 0,
+            })),
+            pass: None,
+            workers: Arc::new(Workers {
+                queue: Mutex::new(WorkQueue {
+                    pass: None,
+                    generation: 0,
+                    running: 0,
+                    has_panicked: false,
+                    is_shut_down: false,
+                }),
+                changed: Condvar::new(),
+            }),
+            threads: Vec::new(),
+            state: RenderState::Idle,
+            samples_number: 0,
+            relative_rmse: f64::INFINITY,
+            aovs: Vec::new(),
+            started: Instant::now(),
+            deadline: None,
+            checkpoint: None,
+            is_checkpoint_due: false,
+            checkpointed: Instant::now(),
+        }
+    }
+
+    pub fn with_tile_size(mut self, tile_size: u32) -> Self {
+        self.tile_size = tile_size.max(1);
+        self
+    }
+
+    /// Makes a checkpoint at the end of the first pass after every `interval` and at the
+    /// end of the render, see `take_checkpoint`. `scene_hash` identifies the scene, as
+    /// given by `checkpoint::scene_hash`.
+    pub fn with_checkpoints(mut self, scene_hash: u64, interval: Duration) -> Self {
+        self.scene_hash = scene_hash;
+        self.checkpoint_interval = Some(interval);
+        self
+    }
+
+    /// The checkpoint made since the last call. A render stopped in the middle of a
+    /// pass gives the samples of the last complete one, none if the first didn't end.
+    pub fn take_checkpoint(&mut self) -> Option<Checkpoint> {
+        if !mem::take(&mut self.is_checkpoint_due) {
+            return None;
+        }
+        self.checkpoint.clone()
+    }
+
+    /// Starts a render adding samples to the ones of `checkpoint`, if it was made with the
+    /// same scene, camera and sampler.
+    pub fn resume_rendering(
+        &mut self,
+        camera: Arc<RwLock<Camera>>,
+        img_params: &ImageParams,
+        samples_number: u32,
+        checkpoint: Checkpoint,
+    ) -> Result<(), String> {
+        let key = self.render_key(&camera.read().unwrap(), img_params);
+        checkpoint.check(&key, samples_number)?;
+        self.start(camera, img_params, samples_number, Some(checkpoint));
+        Ok(())
+    }
+
+    /// Number of pixels finished since the last `start_rendering` call.
+    pub fn rendered_pixels(&self) -> u32 {
+        self.framebuffer.lock().unwrap().rendered_pixels
+    }
+
+    /// Samples accumulated by the last render.
+    pub fn accumulation(&self) -> AccumulationBuffer {
+        self.framebuffer.lock().unwrap().accumulation.clone()
+    }
+
+    fn target_error(&self) -> Option<f64> {
+        self.settings
+            .progressive
+            .and_then(|progressive| progressive.target_error)
+    }
+
+    /// Samples of the pass starting with sample `first`.
+    fn pass_samples(&self, first: u32) -> PassSamples {
+        let count = match (self.settings.progressive, self.settings.adaptive) {
+            (Some(progressive), _) => progressive.pass_samples,
+            (None, Some(adaptive)) => adaptive.min_samples,
+            (None, None) => self.samples_number,
+        };
+        PassSamples {
+            first,
+            count: count.max(1).min(self.samples_number - first),
+            per_pixel: self.samples_number,
+        }
+    }
+
+    /// Samples of the pass following `pass` and the pixels needing them, `None` for
+    /// all of them. The reason to stop when the render is done.
+    fn next_pass(&self, pass: &Pass) -> Result<(PassSamples, Option<Vec<bool>>), RenderState> {
+        if self
+            .deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+        {
+            return Err(RenderState::OutOfTime);
+        }
+        let first = pass.samples.first + pass.samples.count;
+        if first >= self.samples_number {
+            return Err(RenderState::Completed);
+        }
+        if self
+            .target_error()
+            .is_some_and(|target| self.relative_rmse <= target)
+        {
+            return Err(RenderState::Converged);
+        }
+
+        let active = self.active_pixels();
+        if active
+            .as_ref()
+            .is_some_and(|active| !active.contains(&true))
+        {
+            return Err(RenderState::Completed);
+        }
+        Ok((self.pass_samples(first), active))
+    }
+
+    /// Pixels needing more samples, `None` for all of them.
+    fn active_pixels(&self) -> Option<Vec<bool>> {
+        let adaptive = self.settings.adaptive?;
+        let framebuffer = self.framebuffer.lock().unwrap();
+        let pixels = framebuffer.accumulation.width * framebuffer.accumulation.height;
+        let active = (0..pixels as usize)
+            .map(|index| {
+                let stats = framebuffer.accumulation.pixel(index);
+                adaptive.needs_samples(stats, self.samples_number)
+            })
+            .collect();
+        Some(active)
+    }
+
+    fn render_key(&self, camera: &Camera, img_params: &ImageParams) -> RenderKey {
+        RenderKey::new(self.scene_hash, camera, img_params, &self.settings)
+    }
+
+    /// Keeps the samples of the complete `pass`, the next one would repeat the samples of
+    /// an unfinished pass.
+    fn keep_checkpoint(&mut self, pass: &Pass) {
+        let mut accumulation = self.accumulation();
+        accumulation.finish();
+        self.checkpoint = Some(Checkpoint::new(
+            self.render_key(&pass.camera, &pass.img_params),
+            self.samples_number,
+            pass.samples.first + pass.samples.count,
+            accumulation,
+        ));
+        if self
+            .checkpoint_interval
+            .is_some_and(|interval| self.checkpointed.elapsed() >= interval)
+        {
+            self.is_checkpoint_due = true;
+            self.checkpointed = Instant::now();
+        }
+    }
+
+    /// Starts a new render, or goes on with the samples of `checkpoint`.
+    fn start(
+        &mut self,
+        camera: Arc<RwLock<Camera>>,
+        img_params: &ImageParams,
+        samples_number: u32,
+        checkpoint: Option<Checkpoint>,
+    ) {
+        self.stop_rendering();
+
+        let pixel_count = (img_params.width * img_params.height) as usize;
+        let first = checkpoint
+            .as_ref()
+            .map_or(0, |checkpoint| checkpoint.next_sample);
+        {
+            let mut framebuffer = self.framebuffer.lock().unwrap();
+            framebuffer.accumulation = match checkpoint {
+                Some(checkpoint) => {
+                    let mut accumulation = checkpoint.accumulation;
+                    accumulation.resume();
+                    accumulation
+                }
+                None => AccumulationBuffer::new(img_params.width, img_params.height),
+            };
+            framebuffer.aovs = if self.settings.aovs {
+                vec![PixelAovs::default(); pixel_count]
+            } else {
+                Vec::new()
+            };
+            // the samples of the checkpoint are shown right away
+            framebuffer.updated = if first > 0 {
+                Tile::split(img_params, self.tile_size)
+            } else {
+                Vec::new()
+            };
+            framebuffer.rendered_pixels = (0..pixel_count)
+                .filter(|&index| {
+                    is_finished(
+                        &self.settings,
+                        framebuffer.accumulation.pixel(index),
+                        samples_number,
+                    )
+                })
+                .count() as u32;
+            framebuffer.traced_samples = 0;
+            self.relative_rmse = framebuffer.accumulation.relative_rmse();
+        }
+        self.state = RenderState::Rendering;
+        self.samples_number = samples_number;
+        self.aovs.clear();
+        self.started = Instant::now();
+        self.checkpoint = None;
+        self.is_checkpoint_due = false;
+        self.checkpointed = Instant::now();
+
+        let camera = camera.read().unwrap().clone();
+        self.deadline = self.time_budget.map(|budget| Instant::now() + budget);
+        let active = self.active_pixels();
+        self.start_pass(
+            camera,
+            img_params.clone(),
+            self.pass_samples(first),
+            active,
+            true,
+            self.deadline,
+        );
+    }
+
+    fn start_pass(
+        &mut self,
+        camera: Camera,
+        img_params: ImageParams,
+        samples: PassSamples,
+        active: Option<Vec<bool>>,
+        trace_aovs: bool,
+        deadline: Option<Instant>,
+    ) {
+        let tiles = Tile::split(&img_params, self.tile_size)
+            .into_iter()
+            .filter(|tile| match &active {
+                Some(active) => tile
+                    .pixels(img_params.width)
+                    .any(|index| active[index as usize]),
+                None => true,
+            })
+            .collect();
+        let active_pixels = match &active {
+            Some(active) => active.iter().filter(|&&is_active| is_active).count() as u32,
+            None => img_params.width * img_params.height,
+        };
+        let pass = Arc::new(Pass {
+            camera,
+            img_params,
+            samples,
+            active,
+            active_pixels,
+            trace_aovs,
+            tiles,
+            next_tile: AtomicUsize::new(0),
+            is_cancelled: AtomicBool::new(false),
+            deadline,
+        });
+        {
+            let mut framebuffer = self.framebuffer.lock().unwrap();
+            framebuffer.tiles_done = 0;
+            framebuffer.pass_traced_samples = 0;
+        }
+
+        if self.threads.is_empty() {
+            self.threads = (0..self.thread_number)
+                .map(|_| {
+                    let workers = self.workers.clone();
+                    let world = self.world.clone();
+                    let framebuffer = self.framebuffer.clone();
+                    let settings = self.settings;
+                    spawn(move || work(&workers, &world, &settings, &framebuffer))
+                })
+                .collect();
+        }
+        // a dead worker would never end the pass
+        assert!(
+            !self.workers.queue.lock().unwrap().has_panicked,
+            "A render worker panicked"
+        );
+        {
+            let mut queue = self.workers.queue.lock().unwrap();
+            queue.pass = Some(pass.clone());
+            queue.generation += 1;
+            queue.running = self.thread_number;
+        }
+        self.workers.changed.notify_all();
+        self.pass = Some(pass);
+    }
+
+    fn finish(&mut self, state: RenderState) {
+        self.state = state;
+        self.is_checkpoint_due = self.checkpoint.is_some();
+        {
+            let mut framebuffer = self.framebuffer.lock().unwrap();
+            framebuffer.accumulation.finish();
+            if matches!(state, RenderState::Completed | RenderState::Converged) {
+                framebuffer.rendered_pixels =
+                    framebuffer.accumulation.width * framebuffer.accumulation.height;
+            }
+        }
+        self.notify(RenderEvent::Finished);
+    }
+
+    fn notify(&mut self, event: RenderEvent) {
+        if self.callback.is_none() {
+            return;
+        }
+        let progress = self.progress();
+        if let Some(callback) = &mut self.callback {
+            callback(event, &progress);
+        }
+    }
+
+    /// Time left to trace the samples still needed, at the speed of the samples traced so far.
+    fn eta(&self, pass: &Pass, framebuffer: &Framebuffer) -> Option<Duration> {
+        let pixels = (pass.img_params.width * pass.img_params.height) as f64;
+        let pass_end = pass.samples.first + pass.samples.count;
+        // the pixels of the pass are expected to go on up to the samples number
+        let mut remaining = pass.active_pixels as f64 * pass.samples.count as f64
+            - framebuffer.pass_traced_samples as f64
+            + pass.active_pixels as f64 * (self.samples_number - pass_end) as f64;
+        if let Some(target) = self.target_error() {
+            // the error goes down with the square root of the samples number
+            let needed =
+                pass.samples.first as f64 * ((self.relative_rmse / target).powi(2) - 1.0) * pixels;
+            if needed.is_finite() {
+                remaining = remaining.min(needed - framebuffer.pass_traced_samples as f64);
+            }
+        }
+
+        let elapsed = self.started.elapsed();
+        let eta = estimate_eta(framebuffer.traced_samples as f64, remaining, elapsed)?;
+        Some(match pass.deadline {
+            Some(deadline) => eta.min(deadline.saturating_duration_since(Instant::now())),
+            None => eta,
+        })
+    }
+}
+
+/// Samples traced between the checks for cancellation, so stopping doesn't wait for
+/// pixels with many samples.
+const STOP_CHECK_SAMPLES: u32 = 64;
+
+/// Work loop of a worker thread, traces the passes of the queue until it is shut down.
+fn work(
+    workers: &Workers,
+    world: &RwLock<Scene>,
+    settings: &TraceSettings,
+    framebuffer: &Mutex<Framebuffer>,
+) {
+    let mut generation = 0;
+    loop {
+        let pass = {
+            let mut queue = workers.queue.lock().unwrap();
+            while !queue.is_shut_down && queue.generation == generation {
+                queue = workers.changed.wait(queue).unwrap();
+            }
+            if queue.is_shut_down {
+                return;
+            }
+            generation = queue.generation;
+            queue.pass.clone().unwrap()
+        };
+        // the scene is only locked during a pass, the viewers change it in between
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            render_tiles(&pass, &world.read().unwrap(), settings, framebuffer)
+        }));
+
+        {
+            let mut queue = workers.queue.lock().unwrap();
+            queue.running -= 1;
+            queue.has_panicked |= result.is_err();
+        }
+        workers.changed.notify_all();
+        // the thread ends, see `render_step`
+        if let Err(err) = result {
+            panic::resume_unwind(err);
+        }
+    }
+}
+
+/// Traces the tiles of `pass` until none is left.
+fn render_tiles(
+    pass: &Pass,
+    world: &Scene,
+    settings: &TraceSettings,
+    framebuffer: &Mutex<Framebuffer>,
+) {
+    let mut rays =
+        MultisamplerRayCaster::new(&pass.camera, &pass.img_params, pass.samples.per_pixel)
+            .with_sampler(settings.sampler, settings.seed);
+    let range = pass.samples.first..pass.samples.first + pass.samples.count;
+    let width = pass.img_params.width;
+    let mut results = Vec::new();
+
+    while !pass.is_stopped() {
+        let tile = match pass
+            .tiles
+            .get(pass.next_tile.fetch_add(1, Ordering::Relaxed))
+        {
+            Some(tile) => tile,
+            None => break,
+        };
+
+        // a stopped pass keeps the pixels traced so far, the others of the tile are skipped
+        results.clear();
+        let mut is_stopped = false;
+        for index in tile.pixels(width) {
+            if pass
+                .active
+                .as_ref()
+                .is_some_and(|active| !active[index as usize])
+            {
+                continue;
+            }
+            let mut stats = PixelStats::default();
+            let mut aovs = None;
+            for first in range.clone().step_by(STOP_CHECK_SAMPLES as usize) {
+                if pass.is_stopped() {
+                    is_stopped = true;
+                    break;
+                }
+                let samples = PassSamples {
+                    first,
+                    count: STOP_CHECK_SAMPLES.min(range.end - first),
+                    per_pixel: pass.samples.per_pixel,
+                };
+                let rays = rays.get_pixel_samples(
+                    index % width,
+                    index / width,
+                    first..first + samples.count,
+                );
+                stats.merge(&trace_samples(index, &rays, samples, world, settings));
+                // only the first samples, later ones would skew the average
+                if settings.aovs && pass.trace_aovs && first == range.start {
+                    aovs = Some(trace_aovs(world, &rays));
+                }
+            }
+            if stats.samples > 0 {
+                results.push((index as usize, stats, aovs));
+            }
+            if is_stopped {
+                break;
+            }
+        }
+
+        let mut framebuffer = framebuffer.lock().unwrap();
+        for (index, stats, aovs) in &results {
+            framebuffer.accumulation.add(*index, stats);
+            framebuffer.traced_samples += stats.samples as u64;
+            framebuffer.pass_traced_samples += stats.samples as u64;
+            if is_finished(
+                settings,
+                framebuffer.accumulation.pixel(*index),
+                pass.samples.per_pixel,
+            ) {
+                framebuffer.rendered_pixels += 1;
+            }
+            if let Some(aovs) = aovs {
+                framebuffer.aovs[*index] = *aovs;
+            }
+        }
+        if !is_stopped {
+            framebuffer.tiles_done += 1;
+        }
+        if !results.is_empty() {
+            framebuffer.updated.push(*tile);
+        }
+    }
+}
+
+/// Whether the pixel gets no more samples, a render stopped by its target error ends
+/// before that.
+fn is_finished(settings: &TraceSettings, stats: &PixelStats, samples_number: u32) -> bool {
+    match settings.adaptive {
+        Some(adaptive) => !adaptive.needs_samples(stats, samples_number),
+        None => stats.samples >= samples_number,
+    }
+}
+
+impl Renderer for TileRenderer {
+    fn stop_rendering(&mut self) {
+        if self.state != RenderState::Rendering {
+            return;
+        }
+        if let Some(pass) = &self.pass {
+            pass.is_cancelled.store(true, Ordering::Relaxed);
+        }
+        self.workers.wait_idle();
+        self.finish(RenderState::Cancelled);
+    }
+
+    fn start_rendering(
+        &mut self,
+        camera: Arc<RwLock<Camera>>,
+        img_params: &ImageParams,
+        samples_number: u32,
+    ) {
+        self.start(camera, img_params, samples_number, None);
+    }
+
+    fn render_step(&mut self, buffer: &mut Vec<Vector3d>) -> bool {
+        let pass = match (&self.pass, self.state) {
+            (Some(pass), RenderState::Rendering) => pass.clone(),
+            _ => return true,
+        };
+        // checked first, so the tiles written before the workers ended are copied below
+        let is_pass_finished = self.workers.is_idle();
+        assert!(
+            !self.workers.queue.lock().unwrap().has_panicked,
+            "A render worker panicked"
+        );
+
+        let (updated, is_pass_complete) = {
+            let mut framebuffer = self.framebuffer.lock().unwrap();
+            let updated = mem::take(&mut framebuffer.updated);
+            for tile in &updated {
+                for index in tile.pixels(pass.img_params.width) {
+                    buffer[index as usize] = framebuffer.accumulation.pixel(index as usize).mean;
+                }
+            }
+            let is_pass_complete =
+                is_pass_finished && framebuffer.tiles_done as usize == pass.tiles.len();
+            if is_pass_complete {
+                framebuffer.accumulation.end_pass();
+                self.relative_rmse = framebuffer.accumulation.relative_rmse();
+            }
+            (updated, is_pass_complete)
+        };
+        for tile in updated {
+            self.notify(RenderEvent::TileDone(tile));
+        }
+
+        if !is_pass_finished {
+            return false;
+        }
+        if self.settings.aovs && pass.trace_aovs {
+            self.aovs = self.framebuffer.lock().unwrap().aovs.clone();
+        }
+        if is_pass_complete {
+            self.notify(RenderEvent::PassDone);
+        }
+        if is_pass_complete && self.checkpoint_interval.is_some() {
+            self.keep_checkpoint(&pass);
+        }
+        match self.next_pass(&pass) {
+            Ok((samples, active)) => {
+                self.start_pass(
+                    pass.camera.clone(),
+                    pass.img_params.clone(),
+                    samples,
+                    active,
+                    false,
+                    self.deadline,
+                );
+                false
+            }
+            Err(state) => {
+                self.finish(state);
+                true
+            }
+        }
+    }
+
+    fn set_time_budget(&mut self, budget: Option<Duration>) {
+        self.time_budget = budget;
+        if self.state == RenderState::Rendering {
+            self.deadline = budget.map(|budget| Instant::now() + budget);
+        }
+    }
+
+    fn on_progress(&mut self, callback: ProgressCallback) {
+        self.callback = Some(callback);
+    }
+
+    fn progress(&self) -> Progress {
+        let framebuffer = self.framebuffer.lock().unwrap();
+        let pass = match &self.pass {
+            Some(pass) => pass,
+            None => {
+                return Progress {
+                    state: self.state,
+                    passes: 0,
+                    tiles_done: 0,
+                    tiles: 0,
+                    samples: 0,
+                    elapsed: Duration::ZERO,
+                    eta: None,
+                }
+            }
+        };
+        let is_pass_complete = framebuffer.tiles_done as usize == pass.tiles.len();
+        Progress {
+            state: self.state,
+            passes: framebuffer.accumulation.passes(),
+            tiles_done: framebuffer.tiles_done,
+            tiles: pass.tiles.len() as u32,
+            samples: if is_pass_complete {
+                pass.samples.first + pass.samples.count
+            } else {
+                pass.samples.first
+            },
+            elapsed: framebuffer.accumulation.elapsed(),
+            eta: match self.state {
+                RenderState::Rendering => self.eta(pass, &framebuffer),
+                _ => Some(Duration::ZERO),
+            },
+        }
+    }
+
+    fn aovs(&self) -> &[PixelAovs] {
+        &self.aovs
+    }
+
+    fn convergence(&self) -> Convergence {
+        self.framebuffer.lock().unwrap().accumulation.convergence()
+    }
+}
+
+impl Drop for TileRenderer {
+    fn drop(&mut self) {
+        self.stop_rendering();
+        self.workers.queue.lock().unwrap().is_shut_down = true;
+        self.workers.changed.notify_all();
+        // the panic of a worker was already raised by `render_step`
+        for thread in self.threads.drain(..) {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world::{
+        background::Background,
+        ray::{Ray, RayHit},
+        shapes::{Shape, AABB},
+    };
+    use std::{any::Any, collections::HashMap};
+
+    #[test]
+    fn test_tiles_cover_image() {
+        let img_params = ImageParams {
+            width: 37,
+            height: 20,
+        };
+        let tiles = Tile::split(&img_params, 16);
+        assert_eq!(tiles.len(), 6);
+        assert_eq!(
+            tiles[5],
+            Tile {
+                x: 32,
+                y: 16,
+                width: 5,
+                height: 4
+            }
+        );
+        let mut covered = vec![0; 37 * 20];
+        for index in tiles.iter().flat_map(|tile| tile.pixels(37)) {
+            covered[index as usize] += 1;
+        }
+        assert!(covered.iter().all(|&count| count == 1));
+
+        // a stopped render leaves the workers free for the next one
+        let camera = Camera::new(
+            &Vector3d::zero(),
+            &Vector3d::new(0.0, 0.0, -1.0),
+            &Vector3d::new(0.0, 1.0, 0.0),
+            1.0,
+            90.0_f64.to_radians(),
+        );
+        let color = Vector3d::new(0.2, 0.4, 0.6);
+        let scene = Scene::new(
+            Vec::new(),
+            HashMap::new(),
+            Vec::new(),
+            camera.clone(),
+            Background::Solid(color),
+        );
+        let camera = Arc::new(RwLock::new(camera));
+        let mut renderer = TileRenderer::new(Arc::new(RwLock::new(scene)), 3, Default::default());
+        let big = ImageParams {
+            width: 1000,
+            height: 1000,
+        };
+        renderer.start_rendering(camera.clone(), &big, 1000);
+        renderer.stop_rendering();
+        let mut buffer = vec![Vector3d::zero(); 37 * 20];
+        assert!(renderer.render_step(&mut buffer));
+
+        renderer.start_rendering(camera, &img_params, 2);
+        while !renderer.render_step(&mut buffer) {
+            std::thread::yield_now();
+        }
+        assert!(buffer.iter().all(|pixel| (*pixel - color).length() < 1e-12));
+        assert_eq!(renderer.rendered_pixels(), 37 * 20);
+        assert_eq!(renderer.threads.len(), 3);
+    }
+
+    #[derive(Debug)]
+    struct Broken;
+
+    impl Shape for Broken {
+        fn ray_intersect(&self, _ray: &Ray, _min_t: f64, _max_t: f64) -> Option<RayHit<'_>> {
+            panic!("Broken shape")
+        }
+
+        fn get_bounding_box(&self) -> AABB {
+            AABB::default()
+        }
+
+        fn as_any(&self) -> &dyn Any {
+            self
+        }
+    }
+
+    #[test]
+    fn test_worker_panic() {
+        let camera = Camera::new(
+            &Vector3d::new(0.0, 0.0, 5.0),
+            &Vector3d::new(0.0, 0.0, -1.0),
+            &Vector3d::new(0.0, 1.0, 0.0),
+            1.0,
+            90.0_f64.to_radians(),
+        );
+        let scene = Scene::new(
+            vec![Box::new(Broken)],
+            HashMap::new(),
+            Vec::new(),
+            camera.clone(),
+            Background::Solid(Vector3d::zero()),
+        );
+        let camera = Arc::new(RwLock::new(camera));
+        let img_params = ImageParams {
+            width: 4,
+            height: 4,
+        };
+        let mut renderer = TileRenderer::new(Arc::new(RwLock::new(scene)), 2, Default::default());
+        let mut buffer = vec![Vector3d::zero(); 4 * 4];
+
+        // raised by the renderer, which then neither starts a pass nor hangs when dropped
+        renderer.start_rendering(camera.clone(), &img_params, 1);
+        let stepped = panic::catch_unwind(AssertUnwindSafe(|| {
+            while !renderer.render_step(&mut buffer) {
+                std::thread::yield_now();
+            }
+        }));
+        assert!(stepped.is_err());
+        let restarted = panic::catch_unwind(AssertUnwindSafe(|| {
+            renderer.start_rendering(camera, &img_params, 1)
+        }));
+        assert!(restarted.is_err());
+        drop(renderer);
+    }
+}
@@ -0,0 +1,228 @@
+use std::{
+    collections::HashMap,
+    ops::{Add, Mul, RangeInclusive, Sub},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    algebra::{
+        interpolation::Interpolation,
+        transform::{InversableTransform, Keyframe},
+        Vector3d,
+    },
+    camera::Camera,
+};
+
+fn default_fps() -> f64 {
+    24.0
+}
+
+/// Camera state at a frame, unset fields follow the other keyframes.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CameraKeyframe {
+    pub frame: f64,
+    pub position: Option<Vector3d>,
+    pub direction: Option<Vector3d>,
+    /// Degrees.
+    pub fov: Option<f64>,
+}
+
+/// Shape pose at a frame.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TransformKeyframe {
+    pub frame: f64,
+    pub translate: Vector3d,
+    pub rotate: Vector3d,
+    pub scale: Vector3d,
+}
+
+/// Keyframed camera and shape transforms of the scene. Frame `n` starts at `n / fps`
+/// in the time of rays and animated transforms.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Animation {
+    #[serde(default = "default_fps")]
+    pub fps: f64,
+    #[serde(default)]
+    pub interpolation: Interpolation,
+    #[serde(default)]
+    pub frame_start: u32,
+    /// Last rendered frame, the last keyframe by default.
+    pub frame_end: Option<u32>,
+    #[serde(default)]
+    pub camera: Vec<CameraKeyframe>,
+    /// Keyframes by shape name.
+    #[serde(default)]
+    pub shapes: HashMap<String, Vec<TransformKeyframe>>,
+}
+
+impl Animation {
+    pub fn frame_time(&self, frame: u32) -> f64 {
+        frame as f64 / self.fps
+    }
+
+    /// Frames rendered by default.
+    pub fn frames(&self) -> RangeInclusive<u32> {
+        let frame_end = self.frame_end.unwrap_or_else(|| {
+            let camera = self.camera.iter().map(|key| key.frame);
+            let shapes = self.shapes.values().flatten().map(|key| key.frame);
+            camera.chain(shapes).fold(0.0, f64::max).ceil() as u32
+        });
+        self.frame_start..=frame_end.max(self.frame_start)
+    }
+
+    /// `camera` moved to the given frame, with its shutter interval shifted to the frame time.
+    pub fn camera_at(&self, camera: &Camera, frame: u32) -> Camera {
+        let mut result = camera.clone();
+        let frame_f = frame as f64;
+        if let Some(position) = self.camera_channel(frame_f, |key| key.position) {
+            result.set_position(position);
+        }
+        if let Some(direction) = self.camera_channel(frame_f, |key| key.direction) {
+            result.set_direction(direction);
+        }
+        if let Some(fov) = self.camera_channel(frame_f, |key| key.fov) {
+            result.set_fov(fov.to_radians());
+        }
+
+        let time = self.frame_time(frame);
+        result.set_shutter(time + camera.shutter_open(), time + camera.shutter_close());
+        result
+    }
+
+    fn camera_channel<T>(
+        &self,
+        frame: f64,
+        value: impl Fn(&CameraKeyframe) -> Option<T>,
+    ) -> Option<T>
+    where
+        T: Copy + Add<Output = T> + Sub<Output = T> + Mul<f64, Output = T>,
+    {
+        let mut keys = self
+            .camera
+            .iter()
+            .filter_map(|key| Some((key.frame, value(key)?)))
+            .collect::<Vec<_>>();
+        keys.sort_by(|a, b| a.0.total_cmp(&b.0));
+        if keys.is_empty() {
+            return None;
+        }
+        Some(self.interpolation.sample(&keys, frame, |key| *key))
+    }
+
+    /// Refuses shapes with two keyframes at the same frame, they would jump at it.
+    pub fn check(&self) -> Result<(), String> {
+        for (name, keys) in &self.shapes {
+            let mut frames = keys.iter().map(|key| key.frame).collect::<Vec<_>>();
+            frames.sort_by(f64::total_cmp);
+            if let Some(pair) = frames.windows(2).find(|pair| pair[0] == pair[1]) {
+                return Err(format!(
+                    "Animated shape {} has two keyframes at frame {}",
+                    name, pair[0]
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Animated transform of the named shape, `None` if it has no keyframes.
+    pub fn shape_transform(&self, name: &str) -> Option<InversableTransform> {
+        let keys = self.shapes.get(name).filter(|keys| !keys.is_empty())?;
+        let keyframes = keys
+            .iter()
+            .map(|key| Keyframe {
+                time: key.frame / self.fps,
+                translate: key.translate,
+                rotate: key.rotate,
+                scale: key.scale,
+            })
+            .collect();
+        Some(InversableTransform::animated(keyframes, self.interpolation))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world::{ray::Ray, Scene};
+
+    #[test]
+    fn test_camera_animation() {
+        let animation: Animation = serde_json::from_str(
+            r#"{"fps": 10, "camera": [
+                {"frame": 0, "position": [0.0, 0.0, 0.0], "fov": 40},
+                {"frame": 10, "position": [10.0, 0.0, 0.0], "direction": [1.0, 0.0, 0.0]},
+                {"frame": 20, "fov": 60}
+            ]}"#,
+        )
+        .unwrap();
+        assert_eq!(animation.frames(), 0..=20);
+
+        let mut camera = Camera::new(
+            &Vector3d::new(0.0, 5.0, 0.0),
+            &Vector3d::new(0.0, 0.0, -1.0),
+            &Vector3d::new(0.0, 1.0, 0.0),
+            1.0,
+            1.0,
+        );
+        camera.set_shutter(0.0, 0.05);
+
+        let frame = animation.camera_at(&camera, 5);
+        assert_eq!(frame.position().x, 5.0);
+        assert_eq!(frame.direction().x, 1.0);
+        assert!((frame.fov() - 45.0_f64.to_radians()).abs() < 1e-12);
+        assert_eq!(frame.shutter_open(), 0.5);
+        assert!((frame.shutter_close() - 0.55).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_shape_animation() {
+        // rectangles and ray marched shapes have optional names
+        let scene = Scene::from_json(
+            r#"{
+                "camera": {
+                    "position": [0.0, 0.0, 5.0],
+                    "direction": [0.0, 0.0, -1.0],
+                    "up": [0.0, 1.0, 0.0],
+                    "fov": 40.0,
+                    "focal_length": 1.0
+                },
+                "materials": {"White": {"type": "Lambertian",
+                    "albedo": {"type": "SolidColor", "color": [0.7, 0.7, 0.7]}}},
+                "shapes": [{
+                    "type": "Rectangle", "name": "Light",
+                    "x0": -1.0, "x1": 1.0, "y0": -1.0, "y1": 1.0,
+                    "transform": {"translate": [0.0, 0.0, 0.0], "rotate": [0.0, 0.0, 0.0],
+                        "scale": [1.0, 1.0, 1.0]},
+                    "material": "White"
+                }],
+                "animation": {"fps": 10, "shapes": {"Light": [
+                    {"frame": 0, "translate": [0.0, 0.0, 0.0], "rotate": [0.0, 0.0, 0.0],
+                        "scale": [1.0, 1.0, 1.0]},
+                    {"frame": 10, "translate": [10.0, 0.0, 0.0], "rotate": [0.0, 0.0, 0.0],
+                        "scale": [1.0, 1.0, 1.0]}
+                ]}}
+            }"#,
+        )
+        .unwrap();
+
+        let hits = |x: f64, time: f64| {
+            let ray = Ray::with_time(
+                Vector3d::new(x, 0.0, 5.0),
+                Vector3d::new(0.0, 0.0, -1.0),
+                time,
+            );
+            scene.closest_hit(&ray, 0.001, f64::INFINITY).is_some()
+        };
+        assert!(hits(0.0, 0.0));
+        assert!(!hits(10.0, 0.0));
+        assert!(!hits(0.0, 1.0));
+        assert!(hits(10.0, 1.0));
+
+        let mut animation = scene.animation().unwrap().clone();
+        assert!(animation.check().is_ok());
+        let keys = animation.shapes.get_mut("Light").unwrap();
+        keys[1].frame = keys[0].frame;
+        assert!(animation.check().is_err());
+    }
+}
@@ -0,0 +1,382 @@
+use std::f64::consts::PI;
+
+use serde::{Deserialize, Serialize};
+
+use crate::algebra::{distribution::Distribution2D, Vector3d};
+
+use super::sky::PhysicalSky;
+
+/// Radiance coming from infinity when a ray leaves the scene.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(try_from = "json_models::BackgroundJson")]
+#[serde(into = "json_models::BackgroundJson")]
+pub enum Background {
+    Solid(Vector3d),
+    /// Vertical blend between two colours.
+    Gradient { bottom: Vector3d, top: Vector3d },
+    Environment(EnvironmentMap),
+    Sky(PhysicalSky),
+}
+
+impl Default for Background {
+    fn default() -> Self {
+        Background::Gradient {
+            bottom: json_models::default_bottom(),
+            top: json_models::default_top(),
+        }
+    }
+}
+
+impl Background {
+    pub fn value(&self, direction: &Vector3d) -> Vector3d {
+        match self {
+            Background::Solid(color) => *color,
+            Background::Gradient { bottom, top } => {
+                let t = 0.5 * (direction.normalize().y + 1.0);
+                (1.0 - t) * bottom + t * top
+            }
+            Background::Environment(map) => map.value(direction),
+            Background::Sky(sky) => sky.value(direction),
+        }
+    }
+
+    /// Picks a direction with density roughly proportional to the radiance.
+    /// Returns the direction and its solid angle density, `None` if the background
+    /// is not worth sampling directly.
+    pub fn sample(&self, u: (f64, f64)) -> Option<(Vector3d, f64)> {
+        match self {
+            Background::Environment(map) => map.sample(u),
+            Background::Sky(sky) => sky.sample(u),
+            _ => None,
+        }
+    }
+
+    /// Solid angle density of `sample` for the direction.
+    pub fn pdf(&self, direction: &Vector3d) -> f64 {
+        match self {
+            Background::Environment(map) => map.pdf(direction),
+            Background::Sky(sky) => sky.pdf(direction),
+            _ => 0.0,
+        }
+    }
+}
+
+/// Equirectangular (latitude-longitude) image wrapped around the scene.
+#[derive(Debug, Clone)]
+pub struct EnvironmentMap {
+    image_filename: String,
+    /// Rotation around the vertical axis in degrees.
+    rotation: f64,
+    intensity: f64,
+
+    width: u32,
+    height: u32,
+    /// Linear radiance, rows from top to bottom.
+    pixels: Vec<Vector3d>,
+    /// Luminance weighted by the solid angle of the pixels.
+    distribution: Distribution2D,
+}
+
+impl EnvironmentMap {
+    /// Loads an HDR image as is, LDR images are converted from sRGB to linear values.
+    pub fn open(image_filename: &str, rotation: f64, intensity: f64) -> Result<Self, String> {
+        let img = image::open(image_filename).map_err(|err| {
+            format!("Could not open environment map {}: {}", image_filename, err)
+        })?;
+        let is_linear = matches!(
+            img,
+            image::DynamicImage::ImageRgb32F(_) | image::DynamicImage::ImageRgba32F(_)
+        );
+        let img = img.into_rgb32f();
+
+        let pixels = img
+            .pixels()
+            .map(|p| {
+                let color = Vector3d::new(p.0[0] as f64, p.0[1] as f64, p.0[2] as f64);
+                if is_linear {
+                    color
+                } else {
+                    Vector3d::new(
+                        srgb_to_linear(color.x),
+                        srgb_to_linear(color.y),
+                        srgb_to_linear(color.z),
+                    )
+                }
+            })
+            .collect::<Vec<Vector3d>>();
+
+        Ok(Self::from_pixels(
+            image_filename,
+            rotation,
+            intensity,
+            img.width(),
+            img.height(),
+            pixels,
+        ))
+    }
+
+    pub(crate) fn from_pixels(
+        image_filename: &str,
+        rotation: f64,
+        intensity: f64,
+        width: u32,
+        height: u32,
+        pixels: Vec<Vector3d>,
+    ) -> Self {
+        let weights = pixels
+            .iter()
+            .enumerate()
+            .map(|(i, p)| {
+                let row = (i / width as usize) as f64;
+                let sin_theta = (PI * (row + 0.5) / height as f64).sin();
+                p.luminance() * sin_theta
+            })
+            .collect::<Vec<f64>>();
+
+        Self {
+            image_filename: image_filename.to_string(),
+            rotation,
+            intensity,
+            width,
+            height,
+            pixels,
+            distribution: Distribution2D::new(&weights, width as usize, height as usize),
+        }
+    }
+
+    pub fn value(&self, direction: &Vector3d) -> Vector3d {
+        let (u, v) = self.direction_to_uv(direction);
+        let x = ((u * self.width as f64) as u32).min(self.width - 1);
+        let y = (((1.0 - v) * self.height as f64) as u32).min(self.height - 1);
+
+        self.pixels[(x + y * self.width) as usize] * self.intensity
+    }
+
+    pub fn sample(&self, u: (f64, f64)) -> Option<(Vector3d, f64)> {
+        let ((x, y), pdf) = self.distribution.sample_continuous(u);
+        if pdf == 0.0 {
+            return None;
+        }
+
+        let direction = self.uv_to_direction(x, 1.0 - y);
+        let sin_theta = (PI * y).sin();
+        if sin_theta == 0.0 {
+            return None;
+        }
+        Some((direction, pdf / (2.0 * PI * PI * sin_theta)))
+    }
+
+    pub fn pdf(&self, direction: &Vector3d) -> f64 {
+        let (u, v) = self.direction_to_uv(direction);
+        let sin_theta = (PI * v).sin();
+        if sin_theta == 0.0 {
+            return 0.0;
+        }
+        self.distribution.pdf((u, 1.0 - v)) / (2.0 * PI * PI * sin_theta)
+    }
+
+    fn uv_to_direction(&self, u: f64, v: f64) -> Vector3d {
+        let theta = v * PI;
+        let phi = 2.0 * PI * u - PI + self.rotation.to_radians();
+        Vector3d::new(
+            theta.sin() * phi.cos(),
+            -theta.cos(),
+            -theta.sin() * phi.sin(),
+        )
+    }
+
+    /// Same mapping as the texture coordinates of `Sphere`, shifted by the rotation.
+    fn direction_to_uv(&self, direction: &Vector3d) -> (f64, f64) {
+        let d = direction.normalize();
+        let theta = (-d.y).clamp(-1.0, 1.0).acos();
+        let phi = (-d.z).atan2(d.x) + PI - self.rotation.to_radians();
+
+        ((phi / (2.0 * PI)).rem_euclid(1.0), theta / PI)
+    }
+}
+
+fn srgb_to_linear(c: f64) -> f64 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+mod json_models {
+    use super::{Background, EnvironmentMap, PhysicalSky};
+    use crate::algebra::Vector3d;
+    use serde::{Deserialize, Serialize};
+
+    pub fn default_bottom() -> Vector3d {
+        Vector3d::new(1.0, 1.0, 1.0)
+    }
+
+    pub fn default_top() -> Vector3d {
+        Vector3d::new(0.5, 0.7, 1.0)
+    }
+
+    fn default_intensity() -> f64 {
+        1.0
+    }
+
+    fn default_turbidity() -> f64 {
+        3.0
+    }
+
+    fn default_ground_albedo() -> f64 {
+        0.3
+    }
+
+    fn default_sun_size() -> f64 {
+        0.53
+    }
+
+    /// A bare colour is still accepted for the older scenes.
+    #[derive(Serialize, Deserialize, Debug)]
+    #[serde(untagged)]
+    pub enum BackgroundJson {
+        Typed(TypedBackgroundJson),
+        Color(Vector3d),
+    }
+
+    #[derive(Serialize, Deserialize, Debug)]
+    #[serde(tag = "type")]
+    pub enum TypedBackgroundJson {
+        Solid {
+            color: Vector3d,
+        },
+        Gradient {
+            #[serde(default = "default_bottom")]
+            bottom: Vector3d,
+            #[serde(default = "default_top")]
+            top: Vector3d,
+        },
+        Environment {
+            image_filename: String,
+            #[serde(default)]
+            rotation: f64,
+            #[serde(default = "default_intensity")]
+            intensity: f64,
+        },
+        Sky {
+            sun_elevation: f64,
+            #[serde(default)]
+            sun_azimuth: f64,
+            #[serde(default = "default_turbidity")]
+            turbidity: f64,
+            #[serde(default = "default_ground_albedo")]
+            ground_albedo: f64,
+            #[serde(default = "default_intensity")]
+            intensity: f64,
+            #[serde(default = "default_intensity")]
+            sun_intensity: f64,
+            #[serde(default = "default_sun_size")]
+            sun_size: f64,
+        },
+    }
+
+    impl TryFrom<BackgroundJson> for Background {
+        type Error = String;
+
+        fn try_from(background: BackgroundJson) -> Result<Self, Self::Error> {
+            Ok(match background {
+                BackgroundJson::Color(color) => Background::Solid(color),
+                BackgroundJson::Typed(TypedBackgroundJson::Solid { color }) => {
+                    Background::Solid(color)
+                }
+                BackgroundJson::Typed(TypedBackgroundJson::Gradient { bottom, top }) => {
+                    Background::Gradient { bottom, top }
+                }
+                BackgroundJson::Typed(TypedBackgroundJson::Environment {
+                    image_filename,
+                    rotation,
+                    intensity,
+                }) => Background::Environment(EnvironmentMap::open(
+                    &image_filename,
+                    rotation,
+                    intensity,
+                )?),
+                BackgroundJson::Typed(TypedBackgroundJson::Sky {
+                    sun_elevation,
+                    sun_azimuth,
+                    turbidity,
+                    ground_albedo,
+                    intensity,
+                    sun_intensity,
+                    sun_size,
+                }) => Background::Sky(PhysicalSky::new(
+                    sun_elevation,
+                    sun_azimuth,
+                    turbidity,
+                    ground_albedo,
+                    intensity,
+                    sun_intensity,
+                    sun_size,
+                )),
+            })
+        }
+    }
+
+    impl From<Background> for BackgroundJson {
+        fn from(background: Background) -> Self {
+            BackgroundJson::Typed(match background {
+                Background::Solid(color) => TypedBackgroundJson::Solid { color },
+                Background::Gradient { bottom, top } => {
+                    TypedBackgroundJson::Gradient { bottom, top }
+                }
+                Background::Environment(map) => TypedBackgroundJson::Environment {
+                    image_filename: map.image_filename,
+                    rotation: map.rotation,
+                    intensity: map.intensity,
+                },
+                Background::Sky(sky) => TypedBackgroundJson::Sky {
+                    sun_elevation: sky.sun_elevation(),
+                    sun_azimuth: sky.sun_azimuth(),
+                    turbidity: sky.turbidity(),
+                    ground_albedo: sky.ground_albedo(),
+                    intensity: sky.intensity(),
+                    sun_intensity: sky.sun_intensity(),
+                    sun_size: sky.sun_size(),
+                },
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_background_json() {
+        let solid: Background = serde_json::from_str("[0.1, 0.2, 0.3]").unwrap();
+        assert!(matches!(solid, Background::Solid(c) if c == Vector3d::new(0.1, 0.2, 0.3)));
+
+        let gradient: Background = serde_json::from_str(r#"{"type": "Gradient"}"#).unwrap();
+        let up = gradient.value(&Vector3d::new(0.0, 1.0, 0.0));
+        assert_eq!(up, json_models::default_top());
+
+        let missing: Result<Background, _> = serde_json::from_str(
+            r#"{"type": "Environment", "image_filename": "no_such_file.hdr"}"#,
+        );
+        assert!(missing.is_err());
+    }
+
+    #[test]
+    fn test_environment_sampling() {
+        let mut pixels = vec![Vector3d::new(0.1, 0.1, 0.1); 8 * 4];
+        pixels[8 + 5] = Vector3d::new(50.0, 40.0, 30.0);
+        let map = EnvironmentMap::from_pixels("", 30.0, 1.0, 8, 4, pixels);
+
+        for u in [(0.1, 0.2), (0.5, 0.5), (0.9, 0.7)] {
+            let (direction, pdf) = map.sample(u).unwrap();
+            assert!((direction.length() - 1.0).abs() < 1e-9);
+            assert!((map.pdf(&direction) / pdf - 1.0).abs() < 1e-9);
+        }
+
+        // most samples land on the bright pixel
+        let (direction, _) = map.sample((0.5, 0.5)).unwrap();
+        assert_eq!(map.value(&direction), Vector3d::new(50.0, 40.0, 30.0));
+    }
+}
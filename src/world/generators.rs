@@ -0,0 +1,270 @@
+use std::{fmt::Debug, sync::Arc};
+
+use itertools::Itertools;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+use super::{
+    material::{Dielectric, Lambertian, Material, MaterialPtr, Metal},
+    shapes::{Cube, Shape, Sphere},
+    texture::SolidColor,
+};
+use crate::algebra::{transform::InversableTransform, Vector3d};
+
+/// Procedural content listed in the `generators` of a scene.
+#[typetag::serde(tag = "type")]
+pub trait Generator: Debug {
+    fn generate(&self) -> Vec<Box<dyn Shape>>;
+}
+
+/// Probabilities of the generated materials, the rest are dielectrics.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct MaterialMix {
+    pub lambertian: f64,
+    pub metal: f64,
+}
+
+enum MaterialKind {
+    Lambertian,
+    Metal,
+    Dielectric,
+}
+
+impl MaterialMix {
+    fn choose(&self, rng: &mut impl Rng) -> MaterialKind {
+        let choice: f64 = rng.gen();
+        if choice < self.lambertian {
+            MaterialKind::Lambertian
+        } else if choice < self.lambertian + self.metal {
+            MaterialKind::Metal
+        } else {
+            MaterialKind::Dielectric
+        }
+    }
+}
+
+fn glass() -> Box<dyn Material> {
+    Box::new(Dielectric {
+        index_of_refraction: 1.5,
+    })
+}
+
+/// Region left free of generated shapes.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct KeepOut {
+    pub center: Vector3d,
+    pub radius: f64,
+}
+
+fn default_extent() -> i32 {
+    11
+}
+
+fn default_sphere_radius() -> f64 {
+    0.2
+}
+
+fn default_sphere_mix() -> MaterialMix {
+    MaterialMix {
+        lambertian: 0.8,
+        metal: 0.15,
+    }
+}
+
+fn default_keep_out() -> Vec<KeepOut> {
+    vec![KeepOut {
+        center: Vector3d::new(4.0, 0.2, 0.0),
+        radius: 0.9,
+    }]
+}
+
+/// Small spheres lying on the ground at random points of the cells of a grid.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RandomSpheres {
+    /// Cells span from `-extent` to `extent` along x and z.
+    #[serde(default = "default_extent")]
+    pub extent: i32,
+    #[serde(default = "default_sphere_radius")]
+    pub radius: f64,
+    #[serde(default = "default_sphere_mix")]
+    pub materials: MaterialMix,
+    #[serde(default = "default_keep_out")]
+    pub keep_out: Vec<KeepOut>,
+    #[serde(default)]
+    pub seed: u64,
+}
+
+#[typetag::serde]
+impl Generator for RandomSpheres {
+    fn generate(&self) -> Vec<Box<dyn Shape>> {
+        let mut rng = StdRng::seed_from_u64(self.seed);
+        let rad = self.radius;
+        let mut shapes: Vec<Box<dyn Shape>> = Vec::new();
+
+        for (a, b) in (-self.extent..self.extent).cartesian_product(-self.extent..self.extent) {
+            let center = Vector3d::new(
+                a as f64 + 0.9 * rng.gen::<f64>(),
+                rad,
+                b as f64 + 0.9 * rng.gen::<f64>(),
+            );
+            if self
+                .keep_out
+                .iter()
+                .any(|area| (center - area.center).length() <= area.radius)
+            {
+                continue;
+            }
+
+            let mat: Box<dyn Material> = match self.materials.choose(&mut rng) {
+                MaterialKind::Lambertian => {
+                    let random_color = Vector3d::random_with(&mut rng, 0.0, 1.0);
+                    Box::new(Lambertian {
+                        albedo: Box::new(SolidColor {
+                            color: random_color.product(&random_color),
+                        }),
+                    })
+                }
+                MaterialKind::Metal => {
+                    let random_color = Vector3d::random_with(&mut rng, 0.0, 1.0);
+                    Box::new(Metal {
+                        albedo: Box::new(SolidColor {
+                            color: Vector3d::new(
+                                0.5 * (1.0 - random_color.x),
+                                0.5 * (1.0 - random_color.y),
+                                0.5 * (1.0 - random_color.z),
+                            ),
+                        }),
+                        fuzz: 0.5 * rng.gen::<f64>(),
+                    })
+                }
+                MaterialKind::Dielectric => glass(),
+            };
+
+            let shape = Sphere::new(
+                format!("Sphere_{}_{}", a, b),
+                InversableTransform::new(
+                    center,
+                    Vector3d::new(0.0, 0.0, 0.0),
+                    Vector3d::new(rad, rad, rad),
+                ),
+                Arc::new(mat),
+                false,
+            );
+            shapes.push(Box::new(shape));
+        }
+
+        shapes
+    }
+}
+
+fn default_cube_width() -> f64 {
+    10.0
+}
+
+fn default_cube_height() -> (f64, f64) {
+    (2.5, 5.0)
+}
+
+fn default_cube_mix() -> MaterialMix {
+    MaterialMix {
+        lambertian: 0.333,
+        metal: 0.333,
+    }
+}
+
+/// Square grid of boxes of random heights, starting at the origin.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CubeGrid {
+    /// Number of boxes along x and z.
+    pub count: u32,
+    #[serde(default = "default_cube_width")]
+    pub width: f64,
+    /// Range of the half heights.
+    #[serde(default = "default_cube_height")]
+    pub height: (f64, f64),
+    #[serde(default = "default_cube_mix")]
+    pub materials: MaterialMix,
+    /// Number of random materials shared by the boxes, twice `count` by default.
+    pub palette: Option<u32>,
+    #[serde(default)]
+    pub seed: u64,
+}
+
+#[typetag::serde]
+impl Generator for CubeGrid {
+    fn generate(&self) -> Vec<Box<dyn Shape>> {
+        let mut rng = StdRng::seed_from_u64(self.seed);
+
+        let materials = (0..self.palette.unwrap_or(self.count * 2).max(1))
+            .map(|_| {
+                let mat: Box<dyn Material> = match self.materials.choose(&mut rng) {
+                    MaterialKind::Lambertian => Box::new(Lambertian {
+                        albedo: Box::new(SolidColor {
+                            color: Vector3d::random_with(&mut rng, 0.0, 1.0),
+                        }),
+                    }),
+                    MaterialKind::Metal => Box::new(Metal {
+                        albedo: Box::new(SolidColor {
+                            color: Vector3d::random_with(&mut rng, 0.0, 1.0),
+                        }),
+                        fuzz: rng.gen(),
+                    }),
+                    MaterialKind::Dielectric => glass(),
+                };
+                Arc::new(mat)
+            })
+            .collect::<Vec<MaterialPtr>>();
+
+        let (low, high) = self.height;
+        (0..self.count)
+            .cartesian_product(0..self.count)
+            .map(|(x, z)| {
+                let material = materials[rng.gen_range(0..materials.len())].clone();
+                let half_height = if high > low {
+                    rng.gen_range(low..high)
+                } else {
+                    low
+                };
+
+                let shape = Cube::new(
+                    format!("Cube_{}_{}", x, z),
+                    InversableTransform::new(
+                        Vector3d::new(x as f64 * self.width, 0.0, z as f64 * self.width),
+                        Vector3d::zero(),
+                        Vector3d::new(self.width / 2.0, half_height, self.width / 2.0),
+                    ),
+                    material,
+                );
+                Box::new(shape) as Box<dyn Shape>
+            })
+            .collect_vec()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_random_spheres() {
+        let generator = |seed| -> Box<dyn Generator> {
+            serde_json::from_str(&format!(
+                r#"{{"type": "RandomSpheres", "extent": 3, "seed": {}}}"#,
+                seed
+            ))
+            .unwrap()
+        };
+        let boxes = |seed| {
+            generator(seed)
+                .generate()
+                .iter()
+                .map(|shape| format!("{:?}", shape.get_bounding_box()))
+                .collect_vec()
+        };
+
+        let spheres = boxes(1);
+        assert!(!spheres.is_empty() && spheres.len() <= 36);
+        assert_eq!(spheres, boxes(1));
+        assert_ne!(spheres, boxes(2));
+    }
+}
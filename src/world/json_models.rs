@@ -1,14 +1,12 @@
 use super::{
-    material::{self, Material, MaterialPtr},
-    shapes::{Shape, Sphere},
-    texture, Scene,
+    animation::Animation,
+    background::Background,
+    generators::Generator,
+    light::Light,
+    material::{Material, MaterialPtr},
+    Scene,
 };
-use crate::{
-    algebra::{transform::InversableTransform, Vector3d},
-    camera::Camera,
-};
-use itertools::Itertools;
-use rand::Rng;
+use crate::{algebra::transform::InversableTransform, camera::Camera};
 use serde::{Deserialize, Serialize};
 use std::{collections::HashMap, fmt::Debug, sync::Arc};
 
@@ -17,7 +15,27 @@ pub trait ShapeJson: Debug {
     fn make_shape(
         &self,
         materials: &HashMap<String, MaterialPtr>,
-    ) -> Box<dyn super::Shape>;
+    ) -> Result<Box<dyn super::Shape>, String>;
+
+    /// Only named shapes can be animated.
+    fn name(&self) -> Option<&str> {
+        None
+    }
+
+    fn transform_mut(&mut self) -> Option<&mut InversableTransform> {
+        None
+    }
+}
+
+/// The material a shape refers to by name.
+pub fn find_material(
+    materials: &HashMap<String, MaterialPtr>,
+    name: &str,
+) -> Result<MaterialPtr, String> {
+    materials
+        .get(name)
+        .cloned()
+        .ok_or_else(|| format!("Unknown material {}", name))
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -25,11 +43,44 @@ pub struct SceneJson {
     camera: Camera,
     shapes: Vec<Box<dyn ShapeJson>>,
     materials: HashMap<String, Box<dyn Material>>,
-    background: Vector3d,
+    #[serde(default)]
+    background: Background,
+    #[serde(default)]
+    lights: Vec<Box<dyn Light>>,
+    animation: Option<Animation>,
+    /// Procedural shapes added to the listed ones.
+    #[serde(default)]
+    generators: Vec<Box<dyn Generator>>,
+}
+
+impl SceneJson {
+    /// Derives the random state of the textures from the seed of the render.
+    pub fn reseed(&mut self, seed: u32) {
+        for material in self.materials.values_mut() {
+            material.reseed(seed);
+        }
+    }
 }
 
-impl From<SceneJson> for Scene {
-    fn from(scene: SceneJson) -> Self {
+impl TryFrom<SceneJson> for Scene {
+    type Error = String;
+
+    fn try_from(mut scene: SceneJson) -> Result<Self, Self::Error> {
+        if let Some(animation) = &scene.animation {
+            animation.check()?;
+            for name in animation.shapes.keys() {
+                let transform = scene
+                    .shapes
+                    .iter_mut()
+                    .filter(|shape| shape.name() == Some(name.as_str()))
+                    .find_map(|shape| shape.transform_mut())
+                    .ok_or_else(|| format!("Animated shape {} not found", name))?;
+                if let Some(animated) = animation.shape_transform(name) {
+                    *transform = animated;
+                }
+            }
+        }
+
         let materials: HashMap<String, MaterialPtr> = HashMap::from_iter(
             scene
                 .materials
@@ -40,94 +91,59 @@ impl From<SceneJson> for Scene {
             .shapes
             .iter()
             .map(|shape| shape.make_shape(&materials))
-            .collect_vec();
-        add_random_spheres(&mut shapes);
+            .collect::<Result<Vec<_>, _>>()?;
+        shapes.extend(
+            scene
+                .generators
+                .iter()
+                .flat_map(|generator| generator.generate()),
+        );
 
-        Scene::new(shapes, materials, scene.camera, scene.background)
+        let mut result = Scene::new(
+            shapes,
+            materials,
+            scene.lights,
+            scene.camera,
+            scene.background,
+        );
+        result.animation = scene.animation;
+        Ok(result)
     }
 }
 
-pub fn add_random_spheres(shapes: &mut Vec<Box<dyn Shape>>) {
-    let mut rng = rand::thread_rng();
+#[cfg(test)]
+mod tests {
+    use crate::world::Scene;
 
-    // let spheres = self
-    //     .world
-    //     .shapes
-    //     .iter()
-    //     .filter_map(|shape| {
-    //         let sphere = shape.as_any().downcast_ref::<Sphere>()?;
-    //         Some((
-    //             sphere
-    //                 .transform
-    //                 .direct
-    //                 .transform_point(&Vector3d::new(0.0, 0.0, 0.0)),
-    //             sphere
-    //                 .transform
-    //                 .direct
-    //                 .transform_vector(&Vector3d::new(1.0, 1.0, 1.0))
-    //                 .x,
-    //         ))
-    //     })
-    //     .collect_vec();
+    #[test]
+    fn test_scene_errors() {
+        let scene = |shape_material: &str, texture: &str| {
+            Scene::from_json(&format!(
+                r#"{{
+                    "camera": {{
+                        "position": [0.0, 0.0, 5.0],
+                        "direction": [0.0, 0.0, -1.0],
+                        "up": [0.0, 1.0, 0.0],
+                        "fov": 40.0,
+                        "focal_length": 1.0
+                    }},
+                    "materials": {{"Ball": {{"type": "Lambertian", "albedo": {}}}}},
+                    "shapes": [{{
+                        "type": "Sphere", "name": "Ball", "material": "{}",
+                        "transform": {{"translate": [0.0, 0.0, 0.0],
+                            "rotate": [0.0, 0.0, 0.0], "scale": [1.0, 1.0, 1.0]}}
+                    }}]
+                }}"#,
+                texture, shape_material
+            ))
+        };
+        let solid = r#"{"type": "SolidColor", "color": [0.5, 0.5, 0.5]}"#;
+        assert!(scene("Ball", solid).is_ok());
 
-    for (a, b) in (-11..11).cartesian_product(-11..11) {
-        // let (rad, pos) = loop {
-        //     let rad = rng.gen_range(0.2..0.7);
-        //     let pos = Vector3d::new(
-        //         rng.gen_range(-10.0..10.0),
-        //         rad / 2.0,
-        //         rng.gen_range(-10.0..10.0),
-        //     );
-        //     if spheres.iter().any(|(c, r)| (c - &pos).length() > r + rad) {
-        //         break (rad, pos);
-        //     }
-        // };
-        let center = Vector3d::new(
-            a as f64 + 0.9 * rng.gen::<f64>(),
-            0.2,
-            b as f64 + 0.9 * rng.gen::<f64>(),
-        );
-        let rad = 0.2;
-
-        if (&center - Vector3d::new(4.0, 0.2, 0.0)).length() > 0.9 {
-            let mat_choice: f64 = rng.gen();
-
-            let mat: Box<dyn material::Material> = if mat_choice < 0.8 {
-                let random_color = Vector3d::random(0.0, 1.0);
-                Box::new(material::Lambertian {
-                    albedo: Box::new(texture::SolidColor {
-                        color: random_color.product(&random_color),
-                    }),
-                })
-            } else if mat_choice < 0.95 {
-                let random_color = Vector3d::random(0.0, 1.0);
-                Box::new(material::Metal {
-                    albedo: Box::new(texture::SolidColor {
-                        color: Vector3d::new(
-                            0.5 * (1.0 - random_color.x),
-                            0.5 * (1.0 - random_color.y),
-                            0.5 * (1.0 - random_color.z),
-                        ),
-                    }),
-                    fuzz: 0.5 * rng.gen::<f64>(),
-                })
-            } else {
-                Box::new(material::Dielectric {
-                    index_of_refraction: 1.5,
-                })
-            };
-
-            let shape = Sphere::new(
-                format!("Sphere_{}_{}", a, b),
-                InversableTransform::new(
-                    center,
-                    Vector3d::new(0.0, 0.0, 0.0),
-                    Vector3d::new(rad, rad, rad),
-                ),
-                Arc::new(mat),
-                false,
-            );
-            shapes.push(Box::new(shape));
-        }
+        let error = scene("Missing", solid).unwrap_err().to_string();
+        assert!(error.contains("Unknown material Missing"));
+        let missing = r#"{"type": "ImageTexture", "image_filename": "missing.png"}"#;
+        let error = scene("Ball", missing).unwrap_err().to_string();
+        assert!(error.contains("Could not open texture file missing.png"));
     }
 }
@@ -0,0 +1,120 @@
+use std::fmt::Debug;
+
+use serde::{Deserialize, Serialize};
+
+use crate::algebra::Vector3d;
+
+/// Light arriving at a point from a delta light.
+#[derive(Debug, Clone)]
+pub struct DeltaLightSample {
+    /// Towards the light.
+    pub direction: Vector3d,
+    /// Infinite for directional lights.
+    pub distance: f64,
+    /// Irradiance on a surface facing the light.
+    pub irradiance: Vector3d,
+}
+
+/// Lights without area, which can only be reached by sampling them explicitly.
+#[typetag::serde(tag = "type")]
+pub trait Light: Debug + Send + Sync {
+    fn sample(&self, point: &Vector3d) -> Option<DeltaLightSample>;
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PointLight {
+    pub position: Vector3d,
+    pub intensity: Vector3d,
+}
+
+#[typetag::serde]
+impl Light for PointLight {
+    fn sample(&self, point: &Vector3d) -> Option<DeltaLightSample> {
+        let to_light = self.position - *point;
+        let distance_squared = to_light.squared_length();
+        if distance_squared == 0.0 {
+            return None;
+        }
+
+        Some(DeltaLightSample {
+            direction: to_light.normalize(),
+            distance: distance_squared.sqrt(),
+            irradiance: self.intensity / distance_squared,
+        })
+    }
+}
+
+fn default_falloff() -> f64 {
+    4.0
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SpotLight {
+    pub position: Vector3d,
+    /// Where the cone is pointing.
+    pub direction: Vector3d,
+    pub intensity: Vector3d,
+    /// Half angle in degrees at which the light drops to zero.
+    pub cone_angle: f64,
+    /// Half angle in degrees up to which the light has full intensity.
+    pub falloff_start: f64,
+    /// Exponent of the transition between the two angles.
+    #[serde(default = "default_falloff")]
+    pub falloff: f64,
+}
+
+impl SpotLight {
+    fn cone_factor(&self, cos_theta: f64) -> f64 {
+        let cos_outer = self.cone_angle.to_radians().cos();
+        let cos_inner = self.falloff_start.min(self.cone_angle).to_radians().cos();
+        if cos_theta < cos_outer {
+            0.0
+        } else if cos_theta >= cos_inner {
+            1.0
+        } else {
+            ((cos_theta - cos_outer) / (cos_inner - cos_outer)).powf(self.falloff)
+        }
+    }
+}
+
+#[typetag::serde]
+impl Light for SpotLight {
+    fn sample(&self, point: &Vector3d) -> Option<DeltaLightSample> {
+        let to_light = self.position - *point;
+        let distance_squared = to_light.squared_length();
+        if distance_squared == 0.0 {
+            return None;
+        }
+
+        let direction = to_light.normalize();
+        let factor = self.cone_factor(-direction * self.direction.normalize());
+        if factor == 0.0 {
+            return None;
+        }
+
+        Some(DeltaLightSample {
+            direction,
+            distance: distance_squared.sqrt(),
+            irradiance: self.intensity * (factor / distance_squared),
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DirectionalLight {
+    /// Where the light is travelling.
+    pub direction: Vector3d,
+    /// Irradiance on a surface facing the light.
+    pub intensity: Vector3d,
+}
+
+#[typetag::serde]
+impl Light for DirectionalLight {
+    fn sample(&self, _point: &Vector3d) -> Option<DeltaLightSample> {
+        Some(DeltaLightSample {
+            direction: -self.direction.normalize(),
+            distance: f64::INFINITY,
+            irradiance: self.intensity,
+        })
+    }
+}
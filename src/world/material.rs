@@ -1,33 +1,83 @@
+use std::f64::consts::PI;
 use std::fmt::Debug;
 use std::sync::Arc;
 
-use rand::Rng;
 use serde::{Deserialize, Serialize};
 
+use crate::algebra::sampler::Sampler;
+use crate::algebra::sampling::{cosine_hemisphere, power_cosine_hemisphere, Onb};
 use crate::algebra::Vector3d;
 
 use super::{texture::Texture, Ray, RayHit};
 
 pub struct Scatter {
     pub ray: Ray,
+    /// BSDF times cosine divided by `pdf`, i.e. the path throughput weight.
     pub attenuation: Vector3d,
+    /// Density of the sampled direction, zero for specular lobes.
+    pub pdf: f64,
+    /// The direction comes from a delta distribution and can't be evaluated.
+    pub is_specular: bool,
 }
 
 impl Scatter {
-    fn new(ray: Ray, attenuation: Vector3d) -> Self {
-        Self { ray, attenuation }
+    fn new(ray: Ray, attenuation: Vector3d, pdf: f64) -> Self {
+        Self {
+            ray,
+            attenuation,
+            pdf,
+            is_specular: false,
+        }
+    }
+
+    fn specular(ray: Ray, attenuation: Vector3d) -> Self {
+        Self {
+            ray,
+            attenuation,
+            pdf: 0.0,
+            is_specular: true,
+        }
     }
 }
 
 #[typetag::serde(tag = "type")]
 pub trait Material: Debug + Send + Sync {
-    fn scatter(&self, _ray: &Ray, _ray_hit: &RayHit) -> Option<Scatter> {
+    fn scatter(
+        &self,
+        _ray: &Ray,
+        _ray_hit: &RayHit,
+        _sampler: &mut dyn Sampler,
+    ) -> Option<Scatter> {
         None
     }
 
     fn emitted(&self, _u: f64, _v: f64, _p: &Vector3d) -> Vector3d {
         Vector3d::new(0.0, 0.0, 0.0)
     }
+
+    /// Whether `emitted` can return a non-zero value.
+    fn is_emissive(&self) -> bool {
+        false
+    }
+
+    /// BSDF times the cosine with the normal for light leaving along `-ray.direction`
+    /// and arriving from `direction`. Zero for specular materials.
+    fn eval(&self, _ray: &Ray, _ray_hit: &RayHit, _direction: &Vector3d) -> Vector3d {
+        Vector3d::new(0.0, 0.0, 0.0)
+    }
+
+    /// Density with which `scatter` picks `direction`. Zero for specular materials.
+    fn pdf(&self, _ray: &Ray, _ray_hit: &RayHit, _direction: &Vector3d) -> f64 {
+        0.0
+    }
+
+    /// Surface color in [0, 1] written to the albedo output.
+    fn albedo(&self, _ray_hit: &RayHit) -> Vector3d {
+        Vector3d::new(0.0, 0.0, 0.0)
+    }
+
+    /// Derives the random state of the textures from the seed of the render.
+    fn reseed(&mut self, _seed: u32) {}
 }
 
 pub type MaterialPtr = Arc<Box<dyn Material>>;
@@ -39,18 +89,40 @@ pub struct Lambertian {
 
 #[typetag::serde]
 impl Material for Lambertian {
-    fn scatter(&self, _ray: &Ray, ray_hit: &RayHit) -> Option<Scatter> {
-        let mut direction = ray_hit.normal() + Vector3d::random_unit();
-        // let mut direction = ray_hit.normal() + Vector3d::random_in_hemisphere(ray_hit.normal());
-        if direction.is_zero() {
-            direction = *ray_hit.normal()
+    fn scatter(&self, ray: &Ray, ray_hit: &RayHit, sampler: &mut dyn Sampler) -> Option<Scatter> {
+        let local = cosine_hemisphere(sampler.get_2d());
+        let direction = Onb::from_w(ray_hit.normal()).local(&local);
+        let pdf = self.pdf(ray, ray_hit, &direction);
+        if pdf <= 0.0 {
+            return None;
         }
 
         Some(Scatter::new(
-            Ray::new(ray_hit.point, direction),
+            Ray::with_time(ray_hit.point, direction, ray.time),
             self.albedo.value(ray_hit.u, ray_hit.v, &ray_hit.point),
+            pdf,
         ))
     }
+
+    fn eval(&self, _ray: &Ray, ray_hit: &RayHit, direction: &Vector3d) -> Vector3d {
+        let cosine = ray_hit.normal() * direction;
+        if cosine <= 0.0 {
+            return Vector3d::new(0.0, 0.0, 0.0);
+        }
+        self.albedo.value(ray_hit.u, ray_hit.v, &ray_hit.point) * (cosine / PI)
+    }
+
+    fn pdf(&self, _ray: &Ray, ray_hit: &RayHit, direction: &Vector3d) -> f64 {
+        (ray_hit.normal() * direction).max(0.0) / PI
+    }
+
+    fn albedo(&self, ray_hit: &RayHit) -> Vector3d {
+        self.albedo.value(ray_hit.u, ray_hit.v, &ray_hit.point)
+    }
+
+    fn reseed(&mut self, seed: u32) {
+        self.albedo.reseed(seed);
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -59,20 +131,82 @@ pub struct Metal {
     pub fuzz: f64,
 }
 
+impl Metal {
+    /// Exponent of the cos^n lobe around the mirror direction, `None` for a perfect mirror.
+    fn lobe_exponent(&self) -> Option<f64> {
+        if self.fuzz <= 0.0 {
+            None
+        } else {
+            Some((2.0 / (self.fuzz * self.fuzz) - 2.0).max(0.0))
+        }
+    }
+
+    fn lobe_pdf(exponent: f64, reflected: &Vector3d, direction: &Vector3d) -> f64 {
+        let cosine = reflected * direction;
+        if cosine <= 0.0 {
+            0.0
+        } else {
+            (exponent + 1.0) / (2.0 * PI) * cosine.powf(exponent)
+        }
+    }
+}
+
 #[typetag::serde]
 impl Material for Metal {
-    fn scatter(&self, ray: &Ray, ray_hit: &RayHit) -> Option<Scatter> {
+    fn scatter(&self, ray: &Ray, ray_hit: &RayHit, sampler: &mut dyn Sampler) -> Option<Scatter> {
         let reflected = ray.direction.reflect(ray_hit.normal());
-        let direction = if self.fuzz == 0.0 {
-            reflected
-        } else {
-            reflected + self.fuzz * Vector3d::random_in_unit_sphere()
+        let albedo = self.albedo.value(ray_hit.u, ray_hit.v, &ray_hit.point);
+        let exponent = match self.lobe_exponent() {
+            Some(exponent) => exponent,
+            None => {
+                return Some(Scatter::specular(
+                    Ray::with_time(ray_hit.point, reflected, ray.time),
+                    albedo,
+                ))
+            }
         };
+
+        let local = power_cosine_hemisphere(sampler.get_2d(), exponent);
+        let direction = Onb::from_w(&reflected).local(&local);
+        if ray_hit.normal() * direction <= 0.0 {
+            return None;
+        }
+
         Some(Scatter::new(
-            Ray::new(ray_hit.point.clone(), direction),
-            self.albedo.value(ray_hit.u, ray_hit.v, &ray_hit.point),
+            Ray::with_time(ray_hit.point, direction, ray.time),
+            albedo,
+            Metal::lobe_pdf(exponent, &reflected, &direction),
         ))
     }
+
+    fn eval(&self, ray: &Ray, ray_hit: &RayHit, direction: &Vector3d) -> Vector3d {
+        match self.lobe_exponent() {
+            Some(exponent) if ray_hit.normal() * direction > 0.0 => {
+                let reflected = ray.direction.reflect(ray_hit.normal());
+                self.albedo.value(ray_hit.u, ray_hit.v, &ray_hit.point)
+                    * Metal::lobe_pdf(exponent, &reflected, direction)
+            }
+            _ => Vector3d::new(0.0, 0.0, 0.0),
+        }
+    }
+
+    fn pdf(&self, ray: &Ray, ray_hit: &RayHit, direction: &Vector3d) -> f64 {
+        match self.lobe_exponent() {
+            Some(exponent) if ray_hit.normal() * direction > 0.0 => {
+                let reflected = ray.direction.reflect(ray_hit.normal());
+                Metal::lobe_pdf(exponent, &reflected, direction)
+            }
+            _ => 0.0,
+        }
+    }
+
+    fn albedo(&self, ray_hit: &RayHit) -> Vector3d {
+        self.albedo.value(ray_hit.u, ray_hit.v, &ray_hit.point)
+    }
+
+    fn reseed(&mut self, seed: u32) {
+        self.albedo.reseed(seed);
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -90,7 +224,7 @@ impl Dielectric {
 
 #[typetag::serde]
 impl Material for Dielectric {
-    fn scatter(&self, ray: &Ray, ray_hit: &RayHit) -> Option<Scatter> {
+    fn scatter(&self, ray: &Ray, ray_hit: &RayHit, sampler: &mut dyn Sampler) -> Option<Scatter> {
         let refract_ratio = if ray_hit.is_front_face {
             1.0 / self.index_of_refraction
         } else {
@@ -101,18 +235,22 @@ impl Material for Dielectric {
         let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
 
         let direction = if refract_ratio * sin_theta > 1.0
-            || Dielectric::reflectance(cos_theta, refract_ratio) > rand::thread_rng().gen()
+            || Dielectric::reflectance(cos_theta, refract_ratio) > sampler.get_1d()
         {
             ray.direction.reflect(ray_hit.normal())
         } else {
             ray.direction.refract(ray_hit.normal(), refract_ratio)
         };
 
-        Some(Scatter::new(
-            Ray::new(ray_hit.point.clone(), direction),
+        Some(Scatter::specular(
+            Ray::with_time(ray_hit.point, direction, ray.time),
             Vector3d::new(1.0, 1.0, 1.0),
         ))
     }
+
+    fn albedo(&self, _ray_hit: &RayHit) -> Vector3d {
+        Vector3d::new(1.0, 1.0, 1.0)
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -125,6 +263,19 @@ impl Material for DiffuseLight {
     fn emitted(&self, u: f64, v: f64, p: &Vector3d) -> Vector3d {
         self.emit.value(u, v, p)
     }
+
+    fn is_emissive(&self) -> bool {
+        true
+    }
+
+    fn albedo(&self, ray_hit: &RayHit) -> Vector3d {
+        self.emitted(ray_hit.u, ray_hit.v, &ray_hit.point)
+            .map(|c| c.clamp(0.0, 1.0))
+    }
+
+    fn reseed(&mut self, seed: u32) {
+        self.emit.reseed(seed);
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -1,41 +1,87 @@
+use self::animation::Animation;
+use self::background::Background;
 use self::json_models::SceneJson;
-use self::material::{Material, MaterialPtr};
+use self::light::Light;
+use self::material::MaterialPtr;
 use self::ray::{Ray, RayHit};
-use self::shapes::{BvhNode, Cube, Shape, ShapeCollection, Sphere};
-use crate::algebra::transform::InversableTransform;
+use self::shapes::{BvhNode, Shape, ShapeCollection};
+use crate::algebra::sampler::Sampler;
 use crate::algebra::Vector3d;
 use crate::camera::Camera;
 use itertools::Itertools;
-use rand::Rng;
 use std::{collections::HashMap, fmt::Debug, sync::Arc};
 
+pub mod animation;
+pub mod background;
+pub mod generators;
 mod json_models;
+pub mod light;
 pub mod material;
 pub mod ray;
 pub mod shapes;
+pub mod sky;
 pub mod texture;
 
+/// Direction from a point towards a sampled emitter.
+#[derive(Debug, Clone)]
+pub struct LightSample {
+    pub direction: Vector3d,
+    /// Infinite for samples of the background.
+    pub distance: f64,
+    /// Solid angle density, including the probability of choosing the emitter.
+    pub pdf: f64,
+}
+
 #[allow(dead_code)]
 #[derive(Debug)]
 pub struct Scene {
     world: Box<dyn Shape>,
+    lights: Vec<Arc<dyn Shape>>,
+    delta_lights: Vec<Box<dyn Light>>,
     camera: Camera,
     materials: HashMap<String, MaterialPtr>,
-    background: Vector3d,
+    background: Background,
+    animation: Option<Animation>,
 }
 
 impl Scene {
     pub fn new(
         shapes: Vec<Box<dyn Shape>>,
         materials: HashMap<String, MaterialPtr>,
+        delta_lights: Vec<Box<dyn Light>>,
         camera: Camera,
-        background: Vector3d,
+        background: Background,
     ) -> Self {
+        let mut lights = Vec::new();
+        let shapes = shapes
+            .into_iter()
+            .map(|shape| {
+                let is_light = shape.material().is_some_and(|mat| mat.is_emissive())
+                    && shape.sample_surface((0.5, 0.5)).is_some();
+                if is_light {
+                    let light: Arc<dyn Shape> = Arc::from(shape);
+                    lights.push(light.clone());
+                    Box::new(light) as Box<dyn Shape>
+                } else {
+                    shape
+                }
+            })
+            .collect_vec();
+
+        let world: Box<dyn Shape> = if shapes.is_empty() {
+            Box::new(ShapeCollection::new("World", shapes))
+        } else {
+            Box::new(BvhNode::new(shapes))
+        };
+
         Self {
-            world: Box::new(BvhNode::new(shapes)) as Box<dyn Shape>,
+            world,
+            lights,
+            delta_lights,
             materials,
             camera,
             background,
+            animation: None,
         }
     }
 
@@ -43,161 +89,106 @@ impl Scene {
         self.world.ray_hit(ray, min_t, max_t)
     }
 
-    pub fn from_json(data: &str) -> Result<Self, serde_json::Error> {
-        let result: SceneJson = serde_json::from_str(data)?; //.map_err(|err| format!("{}", err));
-        Ok(result.into())
+    /// Shapes with emissive materials which can be sampled directly.
+    pub fn lights(&self) -> &[Arc<dyn Shape>] {
+        &self.lights
     }
 
-    // pub fn to_json(&self) -> String {
-    //     serde_json::to_string_pretty(self.into()).unwrap()
-    // }
+    /// Point, spot and directional lights.
+    pub fn delta_lights(&self) -> &[Box<dyn Light>] {
+        &self.delta_lights
+    }
 
-    pub fn generate_cubes(&mut self, number: u32) {
-        let mut rng = rand::thread_rng();
-
-        let materials = (0..(number * 2))
-            .map(|_| {
-                let mat_choice: f64 = rng.gen();
-
-                let mat: Box<dyn material::Material> = if mat_choice < 0.333 {
-                    Box::new(material::Lambertian {
-                        albedo: Box::new(texture::SolidColor {
-                            color: Vector3d::random(0.0, 1.0),
-                        }),
-                    })
-                } else if mat_choice > 0.666 {
-                    Box::new(material::Metal {
-                        albedo: Box::new(texture::SolidColor {
-                            color: Vector3d::random(0.0, 1.0),
-                        }),
-                        fuzz: rng.gen(),
-                    })
-                } else {
-                    Box::new(material::Dielectric {
-                        index_of_refraction: 1.5,
-                    })
-                };
+    /// Picks a random point on a random emitter as seen from `origin`.
+    pub fn sample_light(&self, origin: &Vector3d, sampler: &mut dyn Sampler) -> Option<LightSample> {
+        if self.lights.is_empty() {
+            return None;
+        }
 
-                Arc::new(mat)
-            })
-            .collect_vec();
+        let choice = (sampler.get_1d() * self.lights.len() as f64) as usize;
+        let light = &self.lights[choice.min(self.lights.len() - 1)];
+        let sample = light.sample_surface(sampler.get_2d())?;
+
+        let to_light = sample.point - *origin;
+        let distance_squared = to_light.squared_length();
+        let distance = distance_squared.sqrt();
+        let direction = to_light / distance;
+        let cosine = (sample.normal * direction).abs();
+        if cosine < 1e-8 || sample.pdf <= 0.0 {
+            return None;
+        }
 
-        let cube_width = 10.0;
-        let shapes: Vec<Box<dyn Shape>> = (0..number)
-            .cartesian_product(0..number)
-            .map(|(x, z)| {
-                let mat_choice = rng.gen_range(0..(number * 2));
-
-                let shape = Cube::new(
-                    format!("Cube_{}_{}", x, z),
-                    InversableTransform::new(
-                        Vector3d::new(x as f64 * cube_width, 0.0, z as f64 * cube_width),
-                        Vector3d::zero(),
-                        Vector3d::new(cube_width / 2.0, rng.gen_range(2.5..5.0), cube_width / 2.0),
-                    ),
-                    materials[mat_choice as usize].clone(),
-                );
-
-                Box::new(shape) as Box<dyn Shape>
+        Some(LightSample {
+            direction,
+            distance,
+            pdf: sample.pdf * distance_squared / (cosine * self.lights.len() as f64),
+        })
+    }
+
+    /// Density with which `sample_light` would pick the direction of `ray` towards the hit.
+    /// Zero if the hit doesn't lie on one of the sampled emitters.
+    pub fn light_pdf(&self, ray: &Ray, ray_hit: &RayHit) -> f64 {
+        let epsilon = ray_hit.distance * 1e-6;
+        self.lights
+            .iter()
+            .find_map(|light| {
+                let hit =
+                    light.ray_hit(ray, ray_hit.distance - epsilon, ray_hit.distance + epsilon)?;
+                let cosine = (hit.normal() * ray.direction).abs();
+                if cosine < 1e-8 {
+                    return None;
+                }
+                Some(
+                    light.surface_pdf(&hit.point, hit.normal()) * hit.distance * hit.distance
+                        / (cosine * self.lights.len() as f64),
+                )
             })
-            .collect_vec();
+            .unwrap_or(0.0)
+    }
 
-        self.world = Box::new(ShapeCollection::new("World", shapes));
+    pub fn from_json(data: &str) -> Result<Self, serde_json::Error> {
+        Self::from_json_seeded(data, 0)
     }
 
-    pub fn add_random_spheres(&mut self) {
-        let mut rng = rand::thread_rng();
-
-        // let spheres = self
-        //     .world
-        //     .shapes
-        //     .iter()
-        //     .filter_map(|shape| {
-        //         let sphere = shape.as_any().downcast_ref::<Sphere>()?;
-        //         Some((
-        //             sphere
-        //                 .transform
-        //                 .direct
-        //                 .transform_point(&Vector3d::new(0.0, 0.0, 0.0)),
-        //             sphere
-        //                 .transform
-        //                 .direct
-        //                 .transform_vector(&Vector3d::new(1.0, 1.0, 1.0))
-        //                 .x,
-        //         ))
-        //     })
-        //     .collect_vec();
-
-        for (a, b) in (-11..11).cartesian_product(-11..11) {
-            // let (rad, pos) = loop {
-            //     let rad = rng.gen_range(0.2..0.7);
-            //     let pos = Vector3d::new(
-            //         rng.gen_range(-10.0..10.0),
-            //         rad / 2.0,
-            //         rng.gen_range(-10.0..10.0),
-            //     );
-            //     if spheres.iter().any(|(c, r)| (c - &pos).length() > r + rad) {
-            //         break (rad, pos);
-            //     }
-            // };
-            let center = Vector3d::new(
-                a as f64 + 0.9 * rng.gen::<f64>(),
-                0.2,
-                b as f64 + 0.9 * rng.gen::<f64>(),
-            );
-            let rad = 0.2;
-
-            if (&center - Vector3d::new(4.0, 0.2, 0.0)).length() > 0.9 {
-                let mat_choice: f64 = rng.gen();
-
-                let mat: Box<dyn material::Material> = if mat_choice < 0.8 {
-                    let random_color = Vector3d::random(0.0, 1.0);
-                    Box::new(material::Lambertian {
-                        albedo: Box::new(texture::SolidColor {
-                            color: random_color.product(&random_color),
-                        }),
-                    })
-                } else if mat_choice < 0.95 {
-                    let random_color = Vector3d::random(0.0, 1.0);
-                    Box::new(material::Metal {
-                        albedo: Box::new(texture::SolidColor {
-                            color: Vector3d::new(
-                                0.5 * (1.0 - random_color.x),
-                                0.5 * (1.0 - random_color.y),
-                                0.5 * (1.0 - random_color.z),
-                            ),
-                        }),
-                        fuzz: 0.5 * rng.gen::<f64>(),
-                    })
-                } else {
-                    Box::new(material::Dielectric {
-                        index_of_refraction: 1.5,
-                    })
-                };
-
-                let shape = Sphere::new(
-                    format!("Sphere_{}_{}", a, b),
-                    InversableTransform::new(
-                        center,
-                        Vector3d::new(0.0, 0.0, 0.0),
-                        Vector3d::new(rad, rad, rad),
-                    ),
-                    Arc::new(mat),
-                    false,
-                );
-                // self.world.shapes.push(Box::new(shape));
-            }
-        }
+    /// Loads the scene with its random textures following the seed of the render.
+    pub fn from_json_seeded(data: &str, seed: u32) -> Result<Self, serde_json::Error> {
+        let mut result: SceneJson = serde_json::from_str(data)?; //.map_err(|err| format!("{}", err));
+        result.reseed(seed);
+        Scene::try_from(result).map_err(serde::de::Error::custom)
     }
 
+    // pub fn to_json(&self) -> String {
+    //     serde_json::to_string_pretty(self.into()).unwrap()
+    // }
+
     /// Get a reference to the scene's camera.
     pub fn camera(&self) -> &Camera {
         &self.camera
     }
 
-    /// Get a reference to the scene's background.
+    /// Get a reference to the scene's animation.
+    pub fn animation(&self) -> Option<&Animation> {
+        self.animation.as_ref()
+    }
+
+    /// Radiance arriving along a ray which missed every shape.
     pub fn background(&self, ray: &Ray) -> Vector3d {
-        let t = 0.5 * (ray.direction.y + 1.0);
-        (1.0 - t) * Vector3d::new(1.0, 1.0, 1.0) + t * Vector3d::new(0.5, 0.7, 1.0)
+        self.background.value(&ray.direction)
+    }
+
+    /// Picks a direction towards the background, `None` if it can't be sampled.
+    pub fn sample_background(&self, sampler: &mut dyn Sampler) -> Option<LightSample> {
+        let (direction, pdf) = self.background.sample(sampler.get_2d())?;
+
+        Some(LightSample {
+            direction,
+            distance: f64::INFINITY,
+            pdf,
+        })
+    }
+
+    /// Density with which `sample_background` would pick the direction of the ray.
+    pub fn background_pdf(&self, ray: &Ray) -> f64 {
+        self.background.pdf(&ray.direction)
     }
 }
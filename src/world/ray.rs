@@ -6,13 +6,20 @@ use super::material::Material;
 pub struct Ray {
     pub origin: Vector3d,
     pub direction: Vector3d,
+    /// Moment within the camera shutter interval, used by animated transforms.
+    pub time: f64,
 }
 
 impl Ray {
     pub fn new(origin: Vector3d, direction: Vector3d) -> Self {
+        Self::with_time(origin, direction, 0.0)
+    }
+
+    pub fn with_time(origin: Vector3d, direction: Vector3d, time: f64) -> Self {
         Ray {
             origin: origin,
             direction: direction.normalize(),
+            time,
         }
     }
 }
@@ -26,6 +33,8 @@ pub struct RayHit<'a> {
     pub material: &'a Box<dyn Material>,
     pub u: f64,
     pub v: f64,
+    /// Name of the shape that was hit, filled in by `Shape::ray_hit`.
+    pub object: Option<&'a str>,
 }
 
 impl<'a> RayHit<'a> {
@@ -48,6 +57,7 @@ impl<'a> RayHit<'a> {
             material,
             u,
             v,
+            object: None,
         }
     }
 
@@ -1,5 +1,3 @@
-use rand::Rng;
-
 use super::{
     material::{self, Material, MaterialPtr},
     Ray, RayHit,
@@ -7,13 +5,17 @@ use super::{
 use crate::algebra::{
     approx_equal,
     equation::solve_quantic_equation,
-    transform::{InversableTransform, Transform},
+    sampling::uniform_sphere,
+    transform::{InversableTransform, Keyframe, Transform},
     Vector3d,
 };
 use std::{any::Any, f64::consts::PI, fmt::Debug, ops::Index, sync::Arc};
 
 pub mod ray_marching;
 
+/// Poses checked between two keyframes when bounding a moving shape.
+const MOTION_BOUND_STEPS: u32 = 16;
+
 #[derive(Clone, Debug)]
 pub struct AABB {
     min_p: Vector3d,
@@ -106,20 +108,110 @@ impl AABB {
 
         AABB { min_p, max_p }
     }
+
+    /// Box around the transformed box at every moment of an animated transform.
+    fn transform_motion(&self, transform: &InversableTransform) -> AABB {
+        let mut result = self.transform(&transform.direct);
+        let keys = transform.keyframes();
+        for (i, pair) in keys.windows(2).enumerate() {
+            let mut segment = self.transform(&transform.at(pair[0].time).direct);
+            for step in 1..=MOTION_BOUND_STEPS {
+                let t = step as f64 / MOTION_BOUND_STEPS as f64;
+                let time = pair[0].time + (pair[1].time - pair[0].time) * t;
+                segment.enlarge(&self.transform(&transform.at(time).direct));
+            }
+
+            // between two poses a corner strays from the chord joining them by at most
+            // its acceleration * step^2 / 8
+            let step = (pair[1].time - pair[0].time) / MOTION_BOUND_STEPS as f64;
+            let pad = self.max_acceleration(transform, i) * step * step / 8.0;
+            let pad = Vector3d::new(pad, pad, pad);
+            segment.min_p = segment.min_p - pad;
+            segment.max_p += pad;
+            result.enlarge(&segment);
+        }
+        result
+    }
+
+    /// Bound of the acceleration of the corners of the box, moved by `transform` between the
+    /// keyframes `i` and `i + 1`.
+    fn max_acceleration(&self, transform: &InversableTransform, i: usize) -> f64 {
+        let keys = transform.keyframes();
+        let dt = keys[i + 1].time - keys[i].time;
+        // largest size of a value and its first two derivatives per unit of time on the
+        // segment, from the control points of the curve and of its derivatives
+        let bounds = |value: fn(&Keyframe) -> Vector3d| {
+            let b = transform
+                .interpolation()
+                .control_points(keys, i, |key| (key.time, value(key)));
+            let largest = |points: &[Vector3d]| {
+                points
+                    .iter()
+                    .fold(Vector3d::zero(), |max, p| max.max(&p.abs()))
+            };
+            let speed = [b[1] - b[0], b[2] - b[1], b[3] - b[2]].map(|d| d * (3.0 / dt));
+            let acceleration =
+                [b[2] - b[1] * 2.0 + b[0], b[3] - b[2] * 2.0 + b[1]].map(|d| d * (6.0 / (dt * dt)));
+            (largest(&b), largest(&speed), largest(&acceleration))
+        };
+        let (_, _, translate) = bounds(|key| key.translate);
+        let (_, rotate_speed, rotate_acceleration) = bounds(|key| key.rotate);
+        let (scale, scale_speed, scale_acceleration) = bounds(|key| key.scale);
+
+        // the derivatives of the rotation in radians, a product of three rotations
+        let sum = |v: Vector3d| (v.x + v.y + v.z).to_radians();
+        let angular_speed = sum(rotate_speed);
+        let angular_acceleration = angular_speed * angular_speed + sum(rotate_acceleration);
+
+        let corner = self.min_p.abs().max(&self.max_p.abs()).length();
+        let rate = angular_acceleration * scale.max_component()
+            + 2.0 * angular_speed * scale_speed.max_component()
+            + scale_acceleration.max_component();
+        // unbounded boxes only grow when something changes
+        let spread = if rate == 0.0 { 0.0 } else { rate * corner };
+        translate.length() + spread
+    }
+}
+
+/// A point picked on a shape surface with its density per unit of world-space area.
+#[derive(Debug, Clone)]
+pub struct SurfaceSample {
+    pub point: Vector3d,
+    pub normal: Vector3d,
+    pub pdf: f64,
+}
+
+impl SurfaceSample {
+    fn transformed(
+        transform: &InversableTransform,
+        point: &Vector3d,
+        normal: &Vector3d,
+        pdf: f64,
+    ) -> Self {
+        Self {
+            point: transform.direct.transform_point(point),
+            normal: transform.inverse.transform_normal(normal).normalize(),
+            pdf: pdf / transform.area_scale(normal),
+        }
+    }
 }
 
 pub trait Shape: Debug + Send + Sync {
     fn ray_hit_transformed(&self, ray: &Ray, min_t: f64, max_t: f64) -> Option<RayHit> {
         if let Some(transform) = self.get_transform() {
+            let transform = transform.at(ray.time);
             let mut ret =
                 self.ray_intersect(&transform.inverse_transform_ray(&ray), min_t, max_t)?;
 
             ret.point = transform.direct.transform_point(&ret.point);
             ret.set_normal(transform.inverse.transform_normal(&ret.normal()), ray);
+            ret.object = ret.object.or(self.name());
 
             Some(ret)
         } else {
-            self.ray_intersect(ray, min_t, max_t)
+            let mut ret = self.ray_intersect(ray, min_t, max_t)?;
+            ret.object = ret.object.or(self.name());
+            Some(ret)
         }
     }
 
@@ -144,11 +236,70 @@ pub trait Shape: Debug + Send + Sync {
         None
     }
 
+    fn material(&self) -> Option<&MaterialPtr> {
+        None
+    }
+
+    /// Name given in the scene, groups have none so hits keep the name of the leaf.
+    fn name(&self) -> Option<&str> {
+        None
+    }
+
+    /// Picks a point on the surface, `None` if the shape can't be sampled.
+    /// Moving shapes aren't sampled, they are only found by scattered rays.
+    fn sample_surface(&self, _u: (f64, f64)) -> Option<SurfaceSample> {
+        None
+    }
+
+    /// Density of `sample_surface` per unit of world-space area at the given point.
+    fn surface_pdf(&self, _point: &Vector3d, _normal: &Vector3d) -> f64 {
+        0.0
+    }
+
     fn as_any(&self) -> &dyn Any;
 }
 
+impl Shape for Arc<dyn Shape> {
+    fn ray_hit(&self, ray: &Ray, min_t: f64, max_t: f64) -> Option<RayHit<'_>> {
+        (**self).ray_hit(ray, min_t, max_t)
+    }
+
+    fn ray_intersect(&self, ray: &Ray, min_t: f64, max_t: f64) -> Option<RayHit<'_>> {
+        (**self).ray_intersect(ray, min_t, max_t)
+    }
+
+    fn get_bounding_box(&self) -> AABB {
+        (**self).get_bounding_box()
+    }
+
+    fn get_transform(&self) -> Option<&InversableTransform> {
+        (**self).get_transform()
+    }
+
+    fn material(&self) -> Option<&MaterialPtr> {
+        (**self).material()
+    }
+
+    fn name(&self) -> Option<&str> {
+        (**self).name()
+    }
+
+    fn sample_surface(&self, u: (f64, f64)) -> Option<SurfaceSample> {
+        (**self).sample_surface(u)
+    }
+
+    fn surface_pdf(&self, point: &Vector3d, normal: &Vector3d) -> f64 {
+        (**self).surface_pdf(point, normal)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        (**self).as_any()
+    }
+}
+
 #[derive(Debug)]
 struct Rectangle {
+    name: Option<String>,
     x0: f64,
     y0: f64,
     x1: f64,
@@ -159,6 +310,7 @@ struct Rectangle {
 
 impl Rectangle {
     fn new(
+        name: Option<String>,
         x0: f64,
         y0: f64,
         x1: f64,
@@ -167,6 +319,7 @@ impl Rectangle {
         material: MaterialPtr,
     ) -> Self {
         Self {
+            name,
             x0,
             y0,
             x1,
@@ -175,6 +328,10 @@ impl Rectangle {
             material,
         }
     }
+
+    fn local_area(&self) -> f64 {
+        (self.x1 - self.x0) * (self.y1 - self.y0)
+    }
 }
 
 impl Shape for Rectangle {
@@ -207,6 +364,10 @@ impl Shape for Rectangle {
         self
     }
 
+    fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
     fn get_transform(&self) -> Option<&InversableTransform> {
         Some(&self.transform)
     }
@@ -216,7 +377,32 @@ impl Shape for Rectangle {
             min_p: Vector3d::new(self.x0, self.y0, -0.0001),
             max_p: Vector3d::new(self.x1, self.y1, 0.0001),
         }
-        .transform(&self.transform.direct)
+        .transform_motion(&self.transform)
+    }
+
+    fn material(&self) -> Option<&MaterialPtr> {
+        Some(&self.material)
+    }
+
+    fn sample_surface(&self, u: (f64, f64)) -> Option<SurfaceSample> {
+        if self.transform.is_animated() {
+            return None;
+        }
+        let point = Vector3d::new(
+            self.x0 + u.0 * (self.x1 - self.x0),
+            self.y0 + u.1 * (self.y1 - self.y0),
+            0.0,
+        );
+        Some(SurfaceSample::transformed(
+            &self.transform,
+            &point,
+            &Vector3d::new(0.0, 0.0, 1.0),
+            1.0 / self.local_area(),
+        ))
+    }
+
+    fn surface_pdf(&self, _point: &Vector3d, normal: &Vector3d) -> f64 {
+        1.0 / (self.local_area() * self.transform.world_area_scale(normal))
     }
 }
 
@@ -244,6 +430,19 @@ impl Cube {
             material: material.clone(),
         }
     }
+
+    const FACE_NORMALS: [Vector3d; 6] = [
+        Vector3d { x: 1.0, y: 0.0, z: 0.0 },
+        Vector3d { x: -1.0, y: 0.0, z: 0.0 },
+        Vector3d { x: 0.0, y: 1.0, z: 0.0 },
+        Vector3d { x: 0.0, y: -1.0, z: 0.0 },
+        Vector3d { x: 0.0, y: 0.0, z: 1.0 },
+        Vector3d { x: 0.0, y: 0.0, z: -1.0 },
+    ];
+
+    fn face_areas(&self) -> [f64; 6] {
+        Self::FACE_NORMALS.map(|normal| 4.0 * self.transform.area_scale(&normal))
+    }
 }
 
 impl Shape for Cube {
@@ -288,6 +487,10 @@ impl Shape for Cube {
         self
     }
 
+    fn name(&self) -> Option<&str> {
+        Some(&self.name)
+    }
+
     fn get_transform(&self) -> Option<&InversableTransform> {
         Some(&self.transform)
     }
@@ -297,7 +500,47 @@ impl Shape for Cube {
             min_p: self.min_p,
             max_p: self.max_p,
         }
-        .transform(&self.transform.direct)
+        .transform_motion(&self.transform)
+    }
+
+    fn material(&self) -> Option<&MaterialPtr> {
+        Some(&self.material)
+    }
+
+    fn sample_surface(&self, u: (f64, f64)) -> Option<SurfaceSample> {
+        if self.transform.is_animated() {
+            return None;
+        }
+        //  faces are picked proportionally to their world area, so the density is uniform
+        let areas = self.face_areas();
+        let total_area: f64 = areas.iter().sum();
+        let mut target = u.0 * total_area;
+        let mut face = 0;
+        while face < 5 && target > areas[face] {
+            target -= areas[face];
+            face += 1;
+        }
+        let s = (target / areas[face]).clamp(0.0, 1.0) * 2.0 - 1.0;
+        let t = u.1 * 2.0 - 1.0;
+
+        let normal = Self::FACE_NORMALS[face];
+        let point = if normal.x != 0.0 {
+            Vector3d::new(normal.x, s, t)
+        } else if normal.y != 0.0 {
+            Vector3d::new(s, normal.y, t)
+        } else {
+            Vector3d::new(s, t, normal.z)
+        };
+
+        Some(SurfaceSample {
+            point: self.transform.direct.transform_point(&point),
+            normal: self.transform.inverse.transform_normal(&normal).normalize(),
+            pdf: 1.0 / total_area,
+        })
+    }
+
+    fn surface_pdf(&self, _point: &Vector3d, _normal: &Vector3d) -> f64 {
+        1.0 / self.face_areas().iter().sum::<f64>()
     }
 }
 
@@ -377,6 +620,10 @@ impl Shape for Sphere {
         self
     }
 
+    fn name(&self) -> Option<&str> {
+        Some(&self.name)
+    }
+
     fn get_transform(&self) -> Option<&InversableTransform> {
         Some(&self.transform)
     }
@@ -394,7 +641,28 @@ impl Shape for Sphere {
                 z: 1.0,
             },
         }
-        .transform(&self.transform.direct)
+        .transform_motion(&self.transform)
+    }
+
+    fn material(&self) -> Option<&MaterialPtr> {
+        Some(&self.material)
+    }
+
+    fn sample_surface(&self, u: (f64, f64)) -> Option<SurfaceSample> {
+        if self.transform.is_animated() {
+            return None;
+        }
+        let point = uniform_sphere(u);
+        Some(SurfaceSample::transformed(
+            &self.transform,
+            &point,
+            &point,
+            1.0 / (4.0 * PI),
+        ))
+    }
+
+    fn surface_pdf(&self, _point: &Vector3d, normal: &Vector3d) -> f64 {
+        1.0 / (4.0 * PI * self.transform.world_area_scale(normal))
     }
 }
 
@@ -479,6 +747,10 @@ impl Shape for Torus {
         self
     }
 
+    fn name(&self) -> Option<&str> {
+        Some(&self.name)
+    }
+
     fn get_transform(&self) -> Option<&InversableTransform> {
         Some(&self.transform)
     }
@@ -489,7 +761,7 @@ impl Shape for Torus {
             min_p: Vector3d::new(-a, -a, -self.tube_radius),
             max_p: Vector3d::new(a, a, self.tube_radius),
         }
-        .transform(&self.transform.direct)
+        .transform_motion(&self.transform)
     }
 }
 
@@ -553,6 +825,10 @@ impl Shape for Tooth {
         self
     }
 
+    fn name(&self) -> Option<&str> {
+        Some(&self.name)
+    }
+
     fn get_transform(&self) -> Option<&InversableTransform> {
         Some(&self.transform)
     }
@@ -661,43 +937,22 @@ impl Shape for BvhNode {
 
 impl BvhNode {
     pub fn new(mut shapes: Vec<Box<dyn Shape>>) -> Self {
-        let mut rng = rand::thread_rng();
-        let axis = rng.gen_range(0..2);
-
-        if axis == 0 {
-            shapes.sort_by(|a, b| {
-                let a_bb = a.get_bounding_box();
-                let b_bb = b.get_bounding_box();
-
-                if a_bb.min_p.x < b_bb.min_p.x {
-                    std::cmp::Ordering::Less
-                } else {
-                    std::cmp::Ordering::Greater
-                }
-            });
-        } else if axis == 1 {
-            shapes.sort_by(|a, b| {
-                let a_bb = a.get_bounding_box();
-                let b_bb = b.get_bounding_box();
-
-                if a_bb.min_p.y < b_bb.min_p.y {
-                    std::cmp::Ordering::Less
-                } else {
-                    std::cmp::Ordering::Greater
-                }
-            });
-        } else {
-            shapes.sort_by(|a, b| {
-                let a_bb = a.get_bounding_box();
-                let b_bb = b.get_bounding_box();
-
-                if a_bb.min_p.z < b_bb.min_p.z {
-                    std::cmp::Ordering::Less
-                } else {
-                    std::cmp::Ordering::Greater
-                }
-            });
-        }
+        // split across the axis along which the shapes are spread the most
+        let spread = |axis: usize| {
+            let (low, high) = shapes
+                .iter()
+                .map(|shape| shape.get_bounding_box().min_p[axis])
+                .fold((f64::INFINITY, f64::NEG_INFINITY), |(low, high), x| {
+                    (low.min(x), high.max(x))
+                });
+            high - low
+        };
+        let axis = (0..3)
+            .max_by(|&a, &b| spread(a).total_cmp(&spread(b)))
+            .unwrap();
+        shapes.sort_by(|a, b| {
+            a.get_bounding_box().min_p[axis].total_cmp(&b.get_bounding_box().min_p[axis])
+        });
 
         let n = shapes.len();
         let (left, right) = if n == 1 {
@@ -729,7 +984,7 @@ impl BvhNode {
 }
 
 mod json_models {
-    use super::{super::json_models::ShapeJson};
+    use super::super::json_models::{find_material, ShapeJson};
     use crate::{algebra::transform::InversableTransform, world::material::MaterialPtr};
     use serde::{Deserialize, Serialize};
     use std::{collections::HashMap, fmt::Debug};
@@ -753,13 +1008,21 @@ mod json_models {
         fn make_shape(
             &self,
             materials: &HashMap<String, MaterialPtr>,
-        ) -> Box<dyn super::Shape> {
-            Box::new(super::Sphere::new(
+        ) -> Result<Box<dyn super::Shape>, String> {
+            Ok(Box::new(super::Sphere::new(
                 self.name.clone(),
                 self.transform.clone(),
-                materials[&self.material].clone(),
+                find_material(materials, &self.material)?,
                 self.inverse_normal,
-            ))
+            )))
+        }
+
+        fn name(&self) -> Option<&str> {
+            Some(&self.name)
+        }
+
+        fn transform_mut(&mut self) -> Option<&mut InversableTransform> {
+            Some(&mut self.transform)
         }
     }
 
@@ -777,19 +1040,28 @@ mod json_models {
         fn make_shape(
             &self,
             materials: &HashMap<String, MaterialPtr>,
-        ) -> Box<dyn super::Shape> {
-            Box::new(super::Torus::new(
+        ) -> Result<Box<dyn super::Shape>, String> {
+            Ok(Box::new(super::Torus::new(
                 self.name.clone(),
                 self.radius,
                 self.tube_radius,
                 self.transform.clone(),
-                materials[&self.material].clone(),
-            ))
+                find_material(materials, &self.material)?,
+            )))
+        }
+
+        fn name(&self) -> Option<&str> {
+            Some(&self.name)
+        }
+
+        fn transform_mut(&mut self) -> Option<&mut InversableTransform> {
+            Some(&mut self.transform)
         }
     }
 
     #[derive(Serialize, Deserialize, Debug)]
     struct Rectangle {
+        name: Option<String>,
         x0: f64,
         y0: f64,
         x1: f64,
@@ -803,15 +1075,24 @@ mod json_models {
         fn make_shape(
             &self,
             materials: &HashMap<String, MaterialPtr>,
-        ) -> Box<dyn super::Shape> {
-            Box::new(super::Rectangle::new(
+        ) -> Result<Box<dyn super::Shape>, String> {
+            Ok(Box::new(super::Rectangle::new(
+                self.name.clone(),
                 self.x0,
                 self.y0,
                 self.x1,
                 self.y1,
                 self.transform.clone(),
-                materials[&self.material].clone(),
-            ))
+                find_material(materials, &self.material)?,
+            )))
+        }
+
+        fn name(&self) -> Option<&str> {
+            self.name.as_deref()
+        }
+
+        fn transform_mut(&mut self) -> Option<&mut InversableTransform> {
+            Some(&mut self.transform)
         }
     }
 
@@ -827,21 +1108,34 @@ mod json_models {
         fn make_shape(
             &self,
             materials: &HashMap<String, MaterialPtr>,
-        ) -> Box<dyn super::Shape> {
-            Box::new(super::Cube::new(
+        ) -> Result<Box<dyn super::Shape>, String> {
+            Ok(Box::new(super::Cube::new(
                 self.name.clone(),
                 self.transform.clone(),
-                materials[&self.material].clone(),
-            ))
+                find_material(materials, &self.material)?,
+            )))
+        }
+
+        fn name(&self) -> Option<&str> {
+            Some(&self.name)
+        }
+
+        fn transform_mut(&mut self) -> Option<&mut InversableTransform> {
+            Some(&mut self.transform)
         }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::Torus;
+    use super::{Sphere, Torus};
     use crate::{
-        algebra::{approx_equal, transform::InversableTransform, Vector3d},
+        algebra::{
+            approx_equal,
+            interpolation::Interpolation,
+            transform::{InversableTransform, Keyframe},
+            Vector3d,
+        },
         world::{material::EmptyMaterial, shapes::AABB, Ray, Shape},
     };
     use std::{str::FromStr, sync::Arc};
@@ -897,4 +1191,82 @@ mod tests {
         assert!(approx_equal(7.0, b2.max_p.y));
         assert!(approx_equal(4.5, b2.max_p.z));
     }
+
+    #[test]
+    fn test_moving_sphere() {
+        let keyframe = |time: f64, x: f64| Keyframe {
+            time,
+            translate: Vector3d::new(x, 0.0, 0.0),
+            rotate: Vector3d::new(0.0, 0.0, 0.0),
+            scale: Vector3d::new(1.0, 1.0, 1.0),
+        };
+        let sphere = Sphere::new(
+            "Moving".to_string(),
+            InversableTransform::animated(
+                vec![keyframe(1.0, 4.0), keyframe(0.0, 0.0)],
+                Interpolation::Linear,
+            ),
+            Arc::new(Box::new(EmptyMaterial)),
+            false,
+        );
+
+        let bounds = sphere.get_bounding_box();
+        assert!(approx_equal(-1.0, bounds.min_p.x));
+        assert!(approx_equal(5.0, bounds.max_p.x));
+        assert!(sphere.sample_surface((0.5, 0.5)).is_none());
+
+        let ray_at = |time| {
+            Ray::with_time(
+                Vector3d::new(2.0, 0.0, 10.0),
+                Vector3d::new(0.0, 0.0, -1.0),
+                time,
+            )
+        };
+        assert!(sphere.ray_hit(&ray_at(0.0), 0.001, f64::INFINITY).is_none());
+        let hit = sphere.ray_hit(&ray_at(0.5), 0.001, f64::INFINITY).unwrap();
+        assert!(approx_equal(9.0, hit.distance));
+
+        // a transform can't jump, the box would have no segment to bound
+        let error = serde_json::from_str::<InversableTransform>(
+            r#"{"keyframes": [
+                {"time": 1.0, "translate": [0, 0, 0], "rotate": [0, 0, 0], "scale": [1, 1, 1]},
+                {"time": 1.0, "translate": [4, 0, 0], "rotate": [0, 0, 0], "scale": [1, 1, 1]}
+            ]}"#,
+        )
+        .unwrap_err();
+        assert!(error.to_string().contains("two keyframes at time 1"));
+
+        // turning along a curve, the box still holds it between the sampled poses
+        let keyframe = |time: f64, x: f64, angle: f64| Keyframe {
+            time,
+            translate: Vector3d::new(x, 0.0, 0.0),
+            rotate: Vector3d::new(angle / 2.0, 0.0, angle),
+            scale: Vector3d::new(3.0, 1.0, 1.0),
+        };
+        let spinning = Sphere::new(
+            "Spinning".to_string(),
+            InversableTransform::animated(
+                vec![
+                    keyframe(0.0, 0.0, 0.0),
+                    keyframe(1.0, 4.0, 180.0),
+                    keyframe(2.0, 4.5, 0.0),
+                ],
+                Interpolation::Smooth,
+            ),
+            Arc::new(Box::new(EmptyMaterial)),
+            false,
+        );
+        let bounds = spinning.get_bounding_box();
+        let local = AABB {
+            min_p: Vector3d::new(-1.0, -1.0, -1.0),
+            max_p: Vector3d::new(1.0, 1.0, 1.0),
+        };
+        for step in 0..=2000 {
+            let time = step as f64 / 1000.0;
+            let pose = local.transform(&spinning.transform.at(time).direct);
+            assert!(pose.min_p.min(&bounds.min_p) == bounds.min_p);
+            assert!(pose.max_p.max(&bounds.max_p) == bounds.max_p);
+        }
+        assert!(bounds.max_p.x - bounds.min_p.x < 13.0);
+    }
 }
@@ -9,6 +9,7 @@ use std::{any::Any, fmt::Debug};
 
 #[derive(Debug)]
 pub struct RayMarchingShape {
+    name: Option<String>,
     transform: InversableTransform,
     material: MaterialPtr,
     shape: Box<dyn ShapeFunction>,
@@ -77,6 +78,10 @@ impl Shape for RayMarchingShape {
         self
     }
 
+    fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
     fn get_transform(&self) -> Option<&InversableTransform> {
         Some(&self.transform)
     }
@@ -87,12 +92,13 @@ impl Shape for RayMarchingShape {
             min_p: bounds.0,
             max_p: bounds.1,
         }
-        .transform(&self.transform.direct)
+        .transform_motion(&self.transform)
     }
 }
 
 impl RayMarchingShape {
     pub fn new(
+        name: Option<String>,
         shape: Box<dyn ShapeFunction>,
         step: f64,
         transform: InversableTransform,
@@ -100,6 +106,7 @@ impl RayMarchingShape {
         depth: u8,
     ) -> Self {
         Self {
+            name,
             transform,
             material,
             shape,
@@ -520,7 +527,7 @@ impl ShapeFunction for Cushion {
 }
 
 mod serde_models {
-    use super::{super::super::json_models::ShapeJson, ShapeFunction};
+    use super::{super::super::json_models::{find_material, ShapeJson}, ShapeFunction};
     use crate::{algebra::transform::InversableTransform, world::{shapes::Shape, material::MaterialPtr}};
     use serde::{Deserialize, Serialize};
     use std::{collections::HashMap, fmt::Debug};
@@ -531,6 +538,7 @@ mod serde_models {
 
     #[derive(Serialize, Deserialize, Debug)]
     struct BruteForsableShape {
+        name: Option<String>,
         transform: InversableTransform,
         material: String,
         shape: Box<dyn BruteForceShapeJson>,
@@ -544,14 +552,23 @@ mod serde_models {
         fn make_shape(
             &self,
             materials: &HashMap<String, MaterialPtr>,
-        ) -> Box<dyn Shape> {
-            Box::new(super::RayMarchingShape::new(
+        ) -> Result<Box<dyn Shape>, String> {
+            Ok(Box::new(super::RayMarchingShape::new(
+                self.name.clone(),
                 self.shape.make_shape(),
                 self.step,
                 self.transform.clone(),
-                materials[&self.material].clone(),
+                find_material(materials, &self.material)?,
                 self.depth
-            ))
+            )))
+        }
+
+        fn name(&self) -> Option<&str> {
+            self.name.as_deref()
+        }
+
+        fn transform_mut(&mut self) -> Option<&mut InversableTransform> {
+            Some(&mut self.transform)
         }
     }
 
@@ -0,0 +1,293 @@
+use std::f64::consts::{FRAC_PI_2, PI};
+
+use crate::algebra::{sampling::Onb, Vector3d};
+
+/// Converts luminance of the model in kcd/m^2 to scene units, a clear zenith is close to 1.
+const LUMINANCE_SCALE: f64 = 0.1;
+/// Luminance of the sun disk outside the atmosphere in kcd/m^2.
+const SUN_LUMINANCE: f64 = 1.6e6;
+
+/// Daylight from the Preetham, Shirley and Smits analytic sky model with a sun disk.
+#[derive(Debug, Clone)]
+pub struct PhysicalSky {
+    /// Degrees above the horizon.
+    sun_elevation: f64,
+    /// Degrees clockwise from -z when looking down.
+    sun_azimuth: f64,
+    turbidity: f64,
+    ground_albedo: f64,
+    intensity: f64,
+    sun_intensity: f64,
+    /// Angular diameter of the sun in degrees.
+    sun_size: f64,
+
+    sun_direction: Vector3d,
+    sun_cos_radius: f64,
+    sun_radiance: Vector3d,
+    /// Perez coefficients for Y, x and y.
+    perez: [[f64; 5]; 3],
+    /// Y, x and y at the zenith.
+    zenith: [f64; 3],
+    ground_radiance: Vector3d,
+}
+
+impl PhysicalSky {
+    pub fn new(
+        sun_elevation: f64,
+        sun_azimuth: f64,
+        turbidity: f64,
+        ground_albedo: f64,
+        intensity: f64,
+        sun_intensity: f64,
+        sun_size: f64,
+    ) -> Self {
+        let elevation = sun_elevation.to_radians();
+        let azimuth = sun_azimuth.to_radians();
+        let sun_direction = Vector3d::new(
+            elevation.cos() * azimuth.sin(),
+            elevation.sin(),
+            -elevation.cos() * azimuth.cos(),
+        );
+        let t = turbidity;
+        // the model is only valid for the sun above the horizon
+        let theta_s = (FRAC_PI_2 - elevation).clamp(0.0, FRAC_PI_2);
+
+        let perez = [
+            [
+                0.1787 * t - 1.4630,
+                -0.3554 * t + 0.4275,
+                -0.0227 * t + 5.3251,
+                0.1206 * t - 2.5771,
+                -0.0670 * t + 0.3703,
+            ],
+            [
+                -0.0193 * t - 0.2592,
+                -0.0665 * t + 0.0008,
+                -0.0004 * t + 0.2125,
+                -0.0641 * t - 0.8989,
+                -0.0033 * t + 0.0452,
+            ],
+            [
+                -0.0167 * t - 0.2608,
+                -0.0950 * t + 0.0092,
+                -0.0079 * t + 0.2102,
+                -0.0441 * t - 1.6537,
+                -0.0109 * t + 0.0529,
+            ],
+        ];
+
+        let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * theta_s);
+        let zenith_luminance = (4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192;
+        let chromaticity = |m: [[f64; 4]; 3]| {
+            let angles = [theta_s.powi(3), theta_s.powi(2), theta_s, 1.0];
+            let turbidities = [t * t, t, 1.0];
+            (0..3)
+                .map(|i| turbidities[i] * (0..4).map(|j| m[i][j] * angles[j]).sum::<f64>())
+                .sum::<f64>()
+        };
+        let zenith_x = chromaticity([
+            [0.00166, -0.00375, 0.00209, 0.0],
+            [-0.02903, 0.06377, -0.03202, 0.00394],
+            [0.11693, -0.21196, 0.06052, 0.25886],
+        ]);
+        let zenith_y = chromaticity([
+            [0.00275, -0.00610, 0.00317, 0.0],
+            [-0.04214, 0.08970, -0.04153, 0.00516],
+            [0.15346, -0.26756, 0.06670, 0.26688],
+        ]);
+
+        let sun_cos_radius = (sun_size.to_radians() / 2.0).cos();
+        let sun_radiance = if sun_elevation > 0.0 {
+            sun_transmittance(theta_s, t) * (SUN_LUMINANCE * LUMINANCE_SCALE * sun_intensity)
+        } else {
+            Vector3d::zero()
+        };
+
+        let mut sky = Self {
+            sun_elevation,
+            sun_azimuth,
+            turbidity,
+            ground_albedo,
+            intensity,
+            sun_intensity,
+            sun_size,
+            sun_direction,
+            sun_cos_radius,
+            sun_radiance,
+            perez,
+            zenith: [zenith_luminance.max(0.0), zenith_x, zenith_y],
+            ground_radiance: Vector3d::zero(),
+        };
+        sky.ground_radiance = sky.irradiance() * (ground_albedo / PI);
+        sky
+    }
+
+    /// Get a reference to the sky's sun elevation.
+    pub fn sun_elevation(&self) -> f64 {
+        self.sun_elevation
+    }
+
+    /// Get a reference to the sky's sun azimuth.
+    pub fn sun_azimuth(&self) -> f64 {
+        self.sun_azimuth
+    }
+
+    /// Get a reference to the sky's turbidity.
+    pub fn turbidity(&self) -> f64 {
+        self.turbidity
+    }
+
+    /// Get a reference to the sky's ground albedo.
+    pub fn ground_albedo(&self) -> f64 {
+        self.ground_albedo
+    }
+
+    /// Get a reference to the sky's intensity.
+    pub fn intensity(&self) -> f64 {
+        self.intensity
+    }
+
+    /// Get a reference to the sky's sun intensity.
+    pub fn sun_intensity(&self) -> f64 {
+        self.sun_intensity
+    }
+
+    /// Get a reference to the sky's sun size.
+    pub fn sun_size(&self) -> f64 {
+        self.sun_size
+    }
+
+    pub fn value(&self, direction: &Vector3d) -> Vector3d {
+        let d = direction.normalize();
+        let color = if d.y < 0.0 {
+            self.ground_radiance
+        } else if self.is_sun(&d) {
+            self.sky_radiance(&d) + self.sun_radiance
+        } else {
+            self.sky_radiance(&d)
+        };
+        color * self.intensity
+    }
+
+    /// Samples a direction inside the sun disk.
+    pub fn sample(&self, u: (f64, f64)) -> Option<(Vector3d, f64)> {
+        if self.sun_radiance.is_zero() {
+            return None;
+        }
+        let cos_theta = 1.0 - u.0 * (1.0 - self.sun_cos_radius);
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * PI * u.1;
+        let local = Vector3d::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta);
+        let direction = Onb::from_w(&self.sun_direction).local(&local);
+
+        Some((direction, self.sun_pdf()))
+    }
+
+    pub fn pdf(&self, direction: &Vector3d) -> f64 {
+        if !self.sun_radiance.is_zero() && self.is_sun(&direction.normalize()) {
+            self.sun_pdf()
+        } else {
+            0.0
+        }
+    }
+
+    fn sun_pdf(&self) -> f64 {
+        1.0 / (2.0 * PI * (1.0 - self.sun_cos_radius))
+    }
+
+    fn is_sun(&self, direction: &Vector3d) -> bool {
+        direction * self.sun_direction >= self.sun_cos_radius
+    }
+
+    fn sky_radiance(&self, direction: &Vector3d) -> Vector3d {
+        let cos_theta = direction.y.max(1e-3);
+        let cos_gamma = (direction * self.sun_direction).clamp(-1.0, 1.0);
+        let theta_s = (FRAC_PI_2 - self.sun_elevation.to_radians()).clamp(0.0, FRAC_PI_2);
+
+        let perez = |c: &[f64; 5], cos_theta: f64, cos_gamma: f64| {
+            let gamma = cos_gamma.acos();
+            (1.0 + c[0] * (c[1] / cos_theta).exp())
+                * (1.0 + c[2] * (c[3] * gamma).exp() + c[4] * cos_gamma * cos_gamma)
+        };
+        let [luminance, x, y] = [0, 1, 2].map(|i| {
+            self.zenith[i] * perez(&self.perez[i], cos_theta, cos_gamma)
+                / perez(&self.perez[i], 1.0, theta_s.cos())
+        });
+
+        xy_luminance_to_rgb(x, y, luminance * LUMINANCE_SCALE)
+    }
+
+    /// Light falling on a horizontal surface from the sky and the sun.
+    fn irradiance(&self) -> Vector3d {
+        let (n_theta, n_phi) = (32, 64);
+        let d_theta = FRAC_PI_2 / n_theta as f64;
+        let d_phi = 2.0 * PI / n_phi as f64;
+        let mut result = Vector3d::zero();
+        for i in 0..n_theta {
+            let theta = (i as f64 + 0.5) * d_theta;
+            for j in 0..n_phi {
+                let phi = (j as f64 + 0.5) * d_phi;
+                let direction = Vector3d::new(
+                    theta.sin() * phi.cos(),
+                    theta.cos(),
+                    theta.sin() * phi.sin(),
+                );
+                result += self.sky_radiance(&direction)
+                    * (theta.cos() * theta.sin() * d_theta * d_phi);
+            }
+        }
+
+        let sun_solid_angle = 2.0 * PI * (1.0 - self.sun_cos_radius);
+        result + self.sun_radiance * (sun_solid_angle * self.sun_direction.y.max(0.0))
+    }
+}
+
+/// Rayleigh and aerosol extinction of sunlight along the optical path, for red,
+/// green and blue wavelengths.
+fn sun_transmittance(theta_s: f64, turbidity: f64) -> Vector3d {
+    let air_mass =
+        1.0 / (theta_s.cos() + 0.15 * (93.885 - theta_s.to_degrees()).powf(-1.253));
+    let beta = 0.04608 * turbidity - 0.04586;
+    let channel = |lambda: f64| {
+        let rayleigh = 0.008735 * lambda.powf(-4.08);
+        let aerosol = beta * lambda.powf(-1.3);
+        (-(rayleigh + aerosol) * air_mass).exp()
+    };
+    Vector3d::new(channel(0.68), channel(0.55), channel(0.44))
+}
+
+/// CIE xyY to linear sRGB.
+fn xy_luminance_to_rgb(x: f64, y: f64, luminance: f64) -> Vector3d {
+    if y <= 0.0 {
+        return Vector3d::zero();
+    }
+    let big_x = x / y * luminance;
+    let big_z = (1.0 - x - y) / y * luminance;
+    Vector3d::new(
+        (3.2406 * big_x - 1.5372 * luminance - 0.4986 * big_z).max(0.0),
+        (-0.9689 * big_x + 1.8758 * luminance + 0.0415 * big_z).max(0.0),
+        (0.0557 * big_x - 0.2040 * luminance + 1.0570 * big_z).max(0.0),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sky() {
+        let sky = PhysicalSky::new(30.0, 45.0, 3.0, 0.3, 1.0, 1.0, 0.53);
+
+        let zenith = sky.value(&Vector3d::new(0.0, 1.0, 0.0));
+        assert!(zenith.z > zenith.x && zenith.x > 0.0, "{}", zenith);
+        assert!(zenith.max_component() < 10.0, "{}", zenith);
+
+        let (direction, pdf) = sky.sample((0.3, 0.8)).unwrap();
+        assert_eq!(sky.pdf(&direction), pdf);
+        assert!(sky.value(&direction).min_component() > 1000.0);
+        assert_eq!(sky.pdf(&Vector3d::new(0.0, 1.0, 0.0)), 0.0);
+
+        let night = PhysicalSky::new(-10.0, 0.0, 3.0, 0.3, 1.0, 1.0, 0.53);
+        assert!(night.sample((0.5, 0.5)).is_none());
+    }
+}
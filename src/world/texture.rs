@@ -5,6 +5,9 @@ use std::{f64::consts::PI, fmt::Debug};
 #[typetag::serde(tag = "type")]
 pub trait Texture: Debug + Send + Sync {
     fn value(&self, u: f64, v: f64, p: &Vector3d) -> Vector3d;
+
+    /// Derives the random state of the texture from the seed of the render.
+    fn reseed(&mut self, _seed: u32) {}
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -48,13 +51,20 @@ impl Texture for CheckerTexture {
             self.even.value(u, v, p)
         }
     }
+
+    fn reseed(&mut self, seed: u32) {
+        self.odd.reseed(seed);
+        self.even.reseed(seed);
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
+#[serde(from = "json_models::NoiseTextureJson")]
 pub struct NoiseTexture {
     #[serde(skip)]
     noise: Perlin,
     scale: f64,
+    seed: u64,
 }
 
 #[typetag::serde]
@@ -65,6 +75,11 @@ impl Texture for NoiseTexture {
         0.5 * (1.0 + (self.scale * p.z + 10.0 * self.noise.turb(&p, 7)).sin())
             * Vector3d::new(1.0, 1.0, 1.0)
     }
+
+    /// The seed of the texture picks one of the noises of the render seed, seed 0 keeps it.
+    fn reseed(&mut self, seed: u32) {
+        self.noise = Perlin::new(self.seed ^ (seed as u64).wrapping_mul(0x9e3779b97f4a7c15));
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -84,10 +99,15 @@ impl Texture for UVChecker {
             self.even.value(u, v, p)
         }
     }
+
+    fn reseed(&mut self, seed: u32) {
+        self.odd.reseed(seed);
+        self.even.reseed(seed);
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
-#[serde(from = "json_models::ImageTextureJson")]
+#[serde(try_from = "json_models::ImageTextureJson")]
 pub struct ImageTexture {
     image_filename: String,
 
@@ -117,7 +137,7 @@ impl Texture for ImageTexture {
 }
 
 mod json_models {
-    use super::ImageTexture;
+    use super::{ImageTexture, NoiseTexture, Perlin};
     use serde::{Deserialize, Serialize};
 
     #[derive(Serialize, Deserialize, Debug)]
@@ -125,16 +145,64 @@ mod json_models {
         image_filename: String,
     }
 
-    impl From<ImageTextureJson> for ImageTexture {
-        fn from(texture: ImageTextureJson) -> Self {
-            let img = image::open(&texture.image_filename).expect(&format!(
-                "Could not open texture file: {}",
-                texture.image_filename
-            ));
+    #[derive(Serialize, Deserialize, Debug)]
+    pub struct NoiseTextureJson {
+        scale: f64,
+        #[serde(default)]
+        seed: u64,
+    }
+
+    impl From<NoiseTextureJson> for NoiseTexture {
+        fn from(texture: NoiseTextureJson) -> Self {
             Self {
+                noise: Perlin::new(texture.seed),
+                scale: texture.scale,
+                seed: texture.seed,
+            }
+        }
+    }
+
+    impl TryFrom<ImageTextureJson> for ImageTexture {
+        type Error = String;
+
+        fn try_from(texture: ImageTextureJson) -> Result<Self, Self::Error> {
+            let img = image::open(&texture.image_filename).map_err(|err| {
+                format!(
+                    "Could not open texture file {}: {}",
+                    texture.image_filename, err
+                )
+            })?;
+            Ok(Self {
                 image: img.into_rgba8(),
                 image_filename: texture.image_filename,
-            }
+            })
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_noise_seed() {
+        let texture = || -> Box<dyn Texture> {
+            serde_json::from_str(r#"{"type": "NoiseTexture", "scale": 4.0, "seed": 3}"#).unwrap()
+        };
+        let values = |texture: &dyn Texture| {
+            (0..64)
+                .map(|i| texture.value(0.0, 0.0, &Vector3d::new(i as f64 * 0.37, 1.3, -0.7)))
+                .collect::<Vec<_>>()
+        };
+        let reseeded = |seed| {
+            let mut texture = texture();
+            texture.reseed(seed);
+            values(&*texture)
+        };
+
+        assert_eq!(reseeded(0), values(&*texture()));
+        assert_eq!(reseeded(7), reseeded(7));
+        assert_ne!(reseeded(7), reseeded(8));
+        assert_ne!(reseeded(7), reseeded(0));
+    }
+}